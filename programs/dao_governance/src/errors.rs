@@ -0,0 +1,33 @@
+use anchor_lang::prelude::*;
+
+#[error_code]
+pub enum GovernanceError {
+    #[msg("Unauthorized access.")]
+    Unauthorized,
+    #[msg("Calculation overflow or underflow.")]
+    CalculationError,
+    #[msg("Quorum, voting period or timelock is out of range.")]
+    InvalidGovernorConfig,
+    #[msg("Proposer must have an active stake.")]
+    NoActiveStake,
+    #[msg("Voting period has ended.")]
+    VotingClosed,
+    #[msg("Voting period has not ended yet.")]
+    VotingStillOpen,
+    #[msg("Position was staked after the proposal snapshot.")]
+    StakedAfterSnapshot,
+    #[msg("Timelock has not elapsed.")]
+    TimelockActive,
+    #[msg("Proposal did not reach quorum.")]
+    QuorumNotReached,
+    #[msg("Proposal was not approved.")]
+    ProposalRejected,
+    #[msg("Proposal was already executed.")]
+    AlreadyExecuted,
+    #[msg("Accounts required by the proposal action are missing or do not match.")]
+    InvalidActionAccounts,
+    #[msg("Vote-escrow locks exist; pass the engine's ve schedule.")]
+    VeScheduleRequired,
+    #[msg("Proposal's execution window has passed.")]
+    ProposalExpired,
+}
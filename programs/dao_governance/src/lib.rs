@@ -0,0 +1,656 @@
+pub mod errors;
+pub mod state;
+
+use crate::errors::GovernanceError;
+use crate::state::{Governor, Proposal, ProposalAction, VoteRecord};
+use anchor_lang::prelude::*;
+use anchor_spl::token::{Token, TokenAccount};
+use revenue_engine::program::RevenueEngine;
+use revenue_engine::state::{EngineState, VeSchedule};
+use staking_program::state::{NftStakeState, TokenStakeState, VeLock};
+
+declare_id!("H5ceqjWWJL2dRE3ED7WUDQx3ChNDd6sKzVRRUtydeLKu");
+
+/// Longest allowed voting period or timelock.
+const MAX_GOVERNANCE_PERIOD_SECS: i64 = 90 * 24 * 60 * 60;
+
+/// How long a passed proposal stays executable once its timelock is over.
+const EXECUTION_WINDOW_SECS: i64 = 14 * 24 * 60 * 60;
+
+#[program]
+pub mod dao_governance {
+    use super::*;
+
+    /// Creates the governor for an engine. Only the engine authority may do this; afterwards
+    /// the engine's `governance` role should be set to this governor's `governance_authority`
+    /// PDA (seeded by the governor's key).
+    pub fn initialize_governor(
+        ctx: Context<InitializeGovernor>,
+        quorum_bps: u16,
+        voting_period_secs: i64,
+        timelock_secs: i64,
+    ) -> Result<()> {
+        require_keys_eq!(
+            ctx.accounts.engine_state.authority,
+            ctx.accounts.authority.key(),
+            GovernanceError::Unauthorized
+        );
+        validate_governor_config(quorum_bps, voting_period_secs, timelock_secs)?;
+
+        let governor = &mut ctx.accounts.governor;
+        governor.engine_state_ref = ctx.accounts.engine_state.key();
+        governor.quorum_bps = quorum_bps;
+        governor.voting_period_secs = voting_period_secs;
+        governor.timelock_secs = timelock_secs;
+        governor.proposal_count = 0;
+        governor.bump = ctx.bumps.governor;
+
+        msg!(
+            "Governor initialized: quorum {} bps, voting {}s, timelock {}s",
+            quorum_bps,
+            voting_period_secs,
+            timelock_secs
+        );
+        Ok(())
+    }
+
+    /// Opens a proposal. Voting power is snapshotted at creation: only positions staked
+    /// before this moment may vote, and quorum is measured against the engine's current
+    /// total (NFT and token stakes) plus the decayed weight of ve locks.
+    pub fn create_proposal(ctx: Context<CreateProposal>, action: ProposalAction) -> Result<()> {
+        validate_action(&action)?;
+        let now = Clock::get()?.unix_timestamp;
+        require_proposer_stake(
+            &ctx.accounts.proposer_stake,
+            ctx.accounts.proposer.key(),
+            ctx.accounts.governor.engine_state_ref,
+            now,
+        )?;
+
+        let engine_state = &ctx.accounts.engine_state;
+        let ve_weight = match ctx.accounts.ve_schedule.as_ref() {
+            Some(ve_schedule) => ve_schedule
+                .weight_at(engine_state, now)
+                .ok_or(GovernanceError::CalculationError)?,
+            None => {
+                require!(engine_state.ve_slope == 0, GovernanceError::VeScheduleRequired);
+                0
+            }
+        };
+        let snapshot_total_weight = engine_state
+            .total_staked_amount
+            .checked_add(ve_weight)
+            .ok_or(GovernanceError::CalculationError)?;
+
+        let governor = &mut ctx.accounts.governor;
+        let proposal = &mut ctx.accounts.proposal;
+        proposal.governor = governor.key();
+        proposal.id = governor.proposal_count;
+        proposal.proposer = ctx.accounts.proposer.key();
+        proposal.action = action;
+        proposal.created_at = now;
+        proposal.voting_ends_at = now
+            .checked_add(governor.voting_period_secs)
+            .ok_or(GovernanceError::CalculationError)?;
+        proposal.snapshot_total_weight = snapshot_total_weight;
+        proposal.yes_weight = 0;
+        proposal.no_weight = 0;
+        proposal.executed = false;
+        proposal.bump = ctx.bumps.proposal;
+
+        governor.proposal_count = governor
+            .proposal_count
+            .checked_add(1)
+            .ok_or(GovernanceError::CalculationError)?;
+
+        msg!(
+            "Proposal {} created by {}, voting ends at {}",
+            proposal.id,
+            proposal.proposer,
+            proposal.voting_ends_at
+        );
+        Ok(())
+    }
+
+    /// Votes with one stake position's weight.
+    pub fn cast_vote(ctx: Context<CastVote>, approve: bool) -> Result<()> {
+        let proposal = &mut ctx.accounts.proposal;
+        let stake = &ctx.accounts.nft_stake_state;
+        require!(
+            Clock::get()?.unix_timestamp < proposal.voting_ends_at,
+            GovernanceError::VotingClosed
+        );
+        require!(
+            stake.is_staked && stake.staked_amount > 0,
+            GovernanceError::NoActiveStake
+        );
+        require!(
+            stake.last_staked_timestamp < proposal.created_at,
+            GovernanceError::StakedAfterSnapshot
+        );
+
+        let weight = stake.staked_amount;
+        let position = stake.key();
+        record_vote(
+            proposal,
+            &mut ctx.accounts.vote_record,
+            position,
+            ctx.accounts.voter.key(),
+            weight,
+            approve,
+            ctx.bumps.vote_record,
+        )
+    }
+
+    /// Votes with a HAiO token stake's weight. Its weight is part of the engine's staked
+    /// total, so it counts toward quorum like NFT positions.
+    pub fn cast_token_vote(ctx: Context<CastTokenVote>, approve: bool) -> Result<()> {
+        let proposal = &mut ctx.accounts.proposal;
+        let stake = &ctx.accounts.token_stake_state;
+        require!(
+            Clock::get()?.unix_timestamp < proposal.voting_ends_at,
+            GovernanceError::VotingClosed
+        );
+        require!(stake.staked_amount > 0, GovernanceError::NoActiveStake);
+        require!(
+            stake.last_staked_timestamp < proposal.created_at,
+            GovernanceError::StakedAfterSnapshot
+        );
+
+        let weight = stake.staked_amount;
+        let position = stake.key();
+        record_vote(
+            proposal,
+            &mut ctx.accounts.vote_record,
+            position,
+            ctx.accounts.voter.key(),
+            weight,
+            approve,
+            ctx.bumps.vote_record,
+        )
+    }
+
+    /// Votes with a ve lock's weight, decayed to the proposal's creation time.
+    pub fn cast_ve_vote(ctx: Context<CastVeVote>, approve: bool) -> Result<()> {
+        let proposal = &mut ctx.accounts.proposal;
+        let lock = &ctx.accounts.ve_lock;
+        require!(
+            Clock::get()?.unix_timestamp < proposal.voting_ends_at,
+            GovernanceError::VotingClosed
+        );
+        require!(
+            lock.locked_at < proposal.created_at,
+            GovernanceError::StakedAfterSnapshot
+        );
+        let weight = lock.weight_at(proposal.created_at);
+        require!(lock.weight > 0 && weight > 0, GovernanceError::NoActiveStake);
+
+        let position = lock.key();
+        record_vote(
+            proposal,
+            &mut ctx.accounts.vote_record,
+            position,
+            ctx.accounts.voter.key(),
+            weight,
+            approve,
+            ctx.bumps.vote_record,
+        )
+    }
+
+    /// Executes a passed proposal once voting and the timelock are over, within
+    /// `EXECUTION_WINDOW_SECS` after that. Permissionless.
+    pub fn execute_proposal(ctx: Context<ExecuteProposal>) -> Result<()> {
+        let proposal = &ctx.accounts.proposal;
+        require_executable(
+            &ctx.accounts.governor,
+            proposal,
+            Clock::get()?.unix_timestamp,
+        )?;
+
+        let governor_key = ctx.accounts.governor.key();
+        let authority_bump = ctx.bumps.governance_authority;
+        let seeds = &[
+            b"governance_authority".as_ref(),
+            governor_key.as_ref(),
+            &[authority_bump][..],
+        ];
+        let signer_seeds = &[&seeds[..]];
+
+        match proposal.action {
+            ProposalAction::UpdateRatios {
+                staking_ratio_bps,
+                dao_ratio_bps,
+                developer_ratio_bps,
+            } => {
+                revenue_engine::cpi::governance_update_ratios(
+                    CpiContext::new_with_signer(
+                        ctx.accounts.revenue_engine_program.to_account_info(),
+                        revenue_engine::cpi::accounts::GovernanceAction {
+                            engine_state: ctx.accounts.engine_state.to_account_info(),
+                            governance: ctx.accounts.governance_authority.to_account_info(),
+                        },
+                        signer_seeds,
+                    ),
+                    staking_ratio_bps,
+                    dao_ratio_bps,
+                    developer_ratio_bps,
+                )?;
+            }
+            ProposalAction::TransferDaoTreasury {
+                destination,
+                amount,
+            } => {
+                let (Some(dao_treasury), Some(destination_account), Some(token_program)) = (
+                    ctx.accounts.dao_treasury_pda.as_ref(),
+                    ctx.accounts.destination.as_ref(),
+                    ctx.accounts.token_program.as_ref(),
+                ) else {
+                    return err!(GovernanceError::InvalidActionAccounts);
+                };
+                require_keys_eq!(
+                    destination_account.key(),
+                    destination,
+                    GovernanceError::InvalidActionAccounts
+                );
+                revenue_engine::cpi::governance_transfer_dao_treasury(
+                    CpiContext::new_with_signer(
+                        ctx.accounts.revenue_engine_program.to_account_info(),
+                        revenue_engine::cpi::accounts::GovernanceTreasuryTransfer {
+                            engine_state: ctx.accounts.engine_state.to_account_info(),
+                            governance: ctx.accounts.governance_authority.to_account_info(),
+                            dao_treasury_pda: dao_treasury.to_account_info(),
+                            destination: destination_account.to_account_info(),
+                            token_program: token_program.to_account_info(),
+                        },
+                        signer_seeds,
+                    ),
+                    amount,
+                )?;
+            }
+        }
+
+        ctx.accounts.proposal.executed = true;
+        msg!("Proposal {} executed", ctx.accounts.proposal.id);
+        Ok(())
+    }
+}
+
+/// Checks that a proposal may run at `now`: not yet executed, past voting and the timelock
+/// but inside the execution window, with quorum reached and more yes than no weight.
+fn require_executable(governor: &Governor, proposal: &Proposal, now: i64) -> Result<()> {
+    require!(!proposal.executed, GovernanceError::AlreadyExecuted);
+    require!(now >= proposal.voting_ends_at, GovernanceError::VotingStillOpen);
+    let executable_at = proposal
+        .voting_ends_at
+        .checked_add(governor.timelock_secs)
+        .ok_or(GovernanceError::CalculationError)?;
+    require!(now >= executable_at, GovernanceError::TimelockActive);
+    let expires_at = executable_at
+        .checked_add(EXECUTION_WINDOW_SECS)
+        .ok_or(GovernanceError::CalculationError)?;
+    require!(now < expires_at, GovernanceError::ProposalExpired);
+
+    let turnout = (proposal.yes_weight as u128)
+        .checked_add(proposal.no_weight as u128)
+        .and_then(|v| v.checked_mul(10000))
+        .ok_or(GovernanceError::CalculationError)?;
+    let required = (proposal.snapshot_total_weight as u128)
+        .checked_mul(governor.quorum_bps as u128)
+        .ok_or(GovernanceError::CalculationError)?;
+    require!(
+        turnout >= required && proposal.yes_weight > 0,
+        GovernanceError::QuorumNotReached
+    );
+    require!(
+        proposal.yes_weight > proposal.no_weight,
+        GovernanceError::ProposalRejected
+    );
+    Ok(())
+}
+
+/// Accepts as the proposer's stake any live position on the governor's engine: a staked
+/// NFT, a token stake or a ve lock that still has weight.
+fn require_proposer_stake(
+    proposer_stake: &AccountInfo,
+    proposer: Pubkey,
+    engine_state_ref: Pubkey,
+    now: i64,
+) -> Result<()> {
+    require_keys_eq!(
+        *proposer_stake.owner,
+        staking_program::ID,
+        GovernanceError::NoActiveStake
+    );
+    let data = proposer_stake.try_borrow_data()?;
+    let (owner, engine, active) = if let Ok(stake) = NftStakeState::try_deserialize(&mut &data[..]) {
+        (stake.user_wallet, stake.engine_state_ref, stake.is_staked && stake.staked_amount > 0)
+    } else if let Ok(stake) = TokenStakeState::try_deserialize(&mut &data[..]) {
+        (stake.user_wallet, stake.engine_state_ref, stake.staked_amount > 0)
+    } else if let Ok(lock) = VeLock::try_deserialize(&mut &data[..]) {
+        (lock.user_wallet, lock.engine_state_ref, lock.weight_at(now) > 0)
+    } else {
+        return err!(GovernanceError::NoActiveStake);
+    };
+    require!(
+        owner == proposer && engine == engine_state_ref && active,
+        GovernanceError::NoActiveStake
+    );
+    Ok(())
+}
+
+fn record_vote(
+    proposal: &mut Account<Proposal>,
+    vote: &mut Account<VoteRecord>,
+    position: Pubkey,
+    voter: Pubkey,
+    weight: u64,
+    approve: bool,
+    bump: u8,
+) -> Result<()> {
+    if approve {
+        proposal.yes_weight = proposal
+            .yes_weight
+            .checked_add(weight)
+            .ok_or(GovernanceError::CalculationError)?;
+    } else {
+        proposal.no_weight = proposal
+            .no_weight
+            .checked_add(weight)
+            .ok_or(GovernanceError::CalculationError)?;
+    }
+
+    vote.proposal = proposal.key();
+    vote.position = position;
+    vote.voter = voter;
+    vote.weight = weight;
+    vote.approve = approve;
+    vote.bump = bump;
+
+    msg!(
+        "Vote on proposal {}: {} with weight {} (yes {}, no {})",
+        proposal.id,
+        if approve { "yes" } else { "no" },
+        weight,
+        proposal.yes_weight,
+        proposal.no_weight
+    );
+    Ok(())
+}
+
+fn validate_governor_config(
+    quorum_bps: u16,
+    voting_period_secs: i64,
+    timelock_secs: i64,
+) -> Result<()> {
+    require!(
+        quorum_bps > 0
+            && quorum_bps <= 10000
+            && voting_period_secs > 0
+            && voting_period_secs <= MAX_GOVERNANCE_PERIOD_SECS
+            && (0..=MAX_GOVERNANCE_PERIOD_SECS).contains(&timelock_secs),
+        GovernanceError::InvalidGovernorConfig
+    );
+    Ok(())
+}
+
+fn validate_action(action: &ProposalAction) -> Result<()> {
+    match *action {
+        ProposalAction::TransferDaoTreasury { amount, .. } => {
+            require!(amount > 0, GovernanceError::InvalidActionAccounts);
+        }
+        ProposalAction::UpdateRatios {
+            staking_ratio_bps,
+            dao_ratio_bps,
+            developer_ratio_bps,
+        } => {
+            let total = staking_ratio_bps as u32 + dao_ratio_bps as u32 + developer_ratio_bps as u32;
+            require!(total <= 10000, GovernanceError::InvalidActionAccounts);
+        }
+    }
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct InitializeGovernor<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub engine_state: Account<'info, EngineState>,
+
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + Governor::MAX_SIZE,
+        seeds = [b"governor", engine_state.key().as_ref()],
+        bump
+    )]
+    pub governor: Account<'info, Governor>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CreateProposal<'info> {
+    #[account(mut)]
+    pub proposer: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"governor", governor.engine_state_ref.as_ref()],
+        bump = governor.bump
+    )]
+    pub governor: Account<'info, Governor>,
+
+    #[account(address = governor.engine_state_ref)]
+    pub engine_state: Account<'info, EngineState>,
+
+    /// Required once vote-escrow locks exist.
+    #[account(constraint = ve_schedule.engine_state_ref == governor.engine_state_ref @ GovernanceError::Unauthorized)]
+    pub ve_schedule: Option<Box<Account<'info, VeSchedule>>>,
+
+    /// CHECK: Proposer's NFT stake, token stake or ve lock; checked in the handler.
+    pub proposer_stake: UncheckedAccount<'info>,
+
+    #[account(
+        init,
+        payer = proposer,
+        space = 8 + Proposal::MAX_SIZE,
+        seeds = [b"proposal", governor.key().as_ref(), &governor.proposal_count.to_le_bytes()],
+        bump
+    )]
+    pub proposal: Account<'info, Proposal>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CastVote<'info> {
+    #[account(mut)]
+    pub voter: Signer<'info>,
+
+    #[account(seeds = [b"governor", governor.engine_state_ref.as_ref()], bump = governor.bump)]
+    pub governor: Account<'info, Governor>,
+
+    #[account(mut, has_one = governor)]
+    pub proposal: Account<'info, Proposal>,
+
+    #[account(
+        constraint = nft_stake_state.user_wallet == voter.key() @ GovernanceError::Unauthorized,
+        constraint = nft_stake_state.engine_state_ref == governor.engine_state_ref @ GovernanceError::Unauthorized
+    )]
+    pub nft_stake_state: Account<'info, NftStakeState>,
+
+    #[account(
+        init,
+        payer = voter,
+        space = 8 + VoteRecord::MAX_SIZE,
+        seeds = [b"vote", proposal.key().as_ref(), nft_stake_state.key().as_ref()],
+        bump
+    )]
+    pub vote_record: Account<'info, VoteRecord>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CastTokenVote<'info> {
+    #[account(mut)]
+    pub voter: Signer<'info>,
+
+    #[account(seeds = [b"governor", governor.engine_state_ref.as_ref()], bump = governor.bump)]
+    pub governor: Account<'info, Governor>,
+
+    #[account(mut, has_one = governor)]
+    pub proposal: Account<'info, Proposal>,
+
+    #[account(
+        constraint = token_stake_state.user_wallet == voter.key() @ GovernanceError::Unauthorized,
+        constraint = token_stake_state.engine_state_ref == governor.engine_state_ref @ GovernanceError::Unauthorized
+    )]
+    pub token_stake_state: Account<'info, TokenStakeState>,
+
+    #[account(
+        init,
+        payer = voter,
+        space = 8 + VoteRecord::MAX_SIZE,
+        seeds = [b"vote", proposal.key().as_ref(), token_stake_state.key().as_ref()],
+        bump
+    )]
+    pub vote_record: Account<'info, VoteRecord>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CastVeVote<'info> {
+    #[account(mut)]
+    pub voter: Signer<'info>,
+
+    #[account(seeds = [b"governor", governor.engine_state_ref.as_ref()], bump = governor.bump)]
+    pub governor: Account<'info, Governor>,
+
+    #[account(mut, has_one = governor)]
+    pub proposal: Account<'info, Proposal>,
+
+    #[account(
+        constraint = ve_lock.user_wallet == voter.key() @ GovernanceError::Unauthorized,
+        constraint = ve_lock.engine_state_ref == governor.engine_state_ref @ GovernanceError::Unauthorized
+    )]
+    pub ve_lock: Account<'info, VeLock>,
+
+    #[account(
+        init,
+        payer = voter,
+        space = 8 + VoteRecord::MAX_SIZE,
+        seeds = [b"vote", proposal.key().as_ref(), ve_lock.key().as_ref()],
+        bump
+    )]
+    pub vote_record: Account<'info, VoteRecord>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ExecuteProposal<'info> {
+    #[account(seeds = [b"governor", governor.engine_state_ref.as_ref()], bump = governor.bump)]
+    pub governor: Account<'info, Governor>,
+
+    #[account(mut, has_one = governor)]
+    pub proposal: Account<'info, Proposal>,
+
+    /// CHECK: Engine state; validated by the engine during the CPI.
+    #[account(mut, address = governor.engine_state_ref)]
+    pub engine_state: AccountInfo<'info>,
+
+    /// CHECK: PDA set as the engine's `governance` role; signs the engine CPI.
+    #[account(seeds = [b"governance_authority", governor.key().as_ref()], bump)]
+    pub governance_authority: AccountInfo<'info>,
+
+    pub revenue_engine_program: Program<'info, RevenueEngine>,
+
+    /// Required for `TransferDaoTreasury` proposals.
+    #[account(mut)]
+    pub dao_treasury_pda: Option<Account<'info, TokenAccount>>,
+
+    /// Required for `TransferDaoTreasury` proposals.
+    #[account(mut)]
+    pub destination: Option<Account<'info, TokenAccount>>,
+
+    pub token_program: Option<Program<'info, Token>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn governor() -> Governor {
+        Governor {
+            engine_state_ref: Pubkey::new_unique(),
+            quorum_bps: 2000,
+            voting_period_secs: 100,
+            timelock_secs: 50,
+            proposal_count: 1,
+            bump: 255,
+        }
+    }
+
+    /// Voting ran from 0 to 100 with a snapshot weight of 1000.
+    fn proposal(yes_weight: u64, no_weight: u64) -> Proposal {
+        Proposal {
+            governor: Pubkey::new_unique(),
+            id: 0,
+            proposer: Pubkey::new_unique(),
+            action: ProposalAction::UpdateRatios {
+                staking_ratio_bps: 5000,
+                dao_ratio_bps: 3000,
+                developer_ratio_bps: 2000,
+            },
+            created_at: 0,
+            voting_ends_at: 100,
+            snapshot_total_weight: 1000,
+            yes_weight,
+            no_weight,
+            executed: false,
+            bump: 255,
+        }
+    }
+
+    #[test]
+    fn executable_only_between_timelock_and_expiry() {
+        let governor = governor();
+        let passed = proposal(300, 100);
+        assert_eq!(
+            require_executable(&governor, &passed, 99).unwrap_err(),
+            GovernanceError::VotingStillOpen.into()
+        );
+        assert_eq!(
+            require_executable(&governor, &passed, 149).unwrap_err(),
+            GovernanceError::TimelockActive.into()
+        );
+        assert!(require_executable(&governor, &passed, 150).is_ok());
+        assert!(require_executable(&governor, &passed, 150 + EXECUTION_WINDOW_SECS - 1).is_ok());
+        assert_eq!(
+            require_executable(&governor, &passed, 150 + EXECUTION_WINDOW_SECS).unwrap_err(),
+            GovernanceError::ProposalExpired.into()
+        );
+    }
+
+    #[test]
+    fn executable_needs_quorum_and_a_majority() {
+        let governor = governor();
+        assert_eq!(
+            require_executable(&governor, &proposal(150, 0), 150).unwrap_err(),
+            GovernanceError::QuorumNotReached.into()
+        );
+        assert_eq!(
+            require_executable(&governor, &proposal(100, 100), 150).unwrap_err(),
+            GovernanceError::ProposalRejected.into()
+        );
+        let mut executed = proposal(300, 100);
+        executed.executed = true;
+        assert_eq!(
+            require_executable(&governor, &executed, 150).unwrap_err(),
+            GovernanceError::AlreadyExecuted.into()
+        );
+    }
+}
@@ -0,0 +1,92 @@
+use anchor_lang::prelude::*;
+
+/// What a proposal does once it passes.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum ProposalAction {
+    TransferDaoTreasury {
+        destination: Pubkey,
+        amount: u64,
+    },
+    UpdateRatios {
+        staking_ratio_bps: u16,
+        dao_ratio_bps: u16,
+        developer_ratio_bps: u16,
+    },
+}
+
+impl ProposalAction {
+    pub const MAX_SIZE: usize = 1 // variant
+        + 32                      // destination (largest variant)
+        + 8; // amount
+}
+
+#[account]
+pub struct Governor {
+    pub engine_state_ref: Pubkey,
+    pub quorum_bps: u16,
+    pub voting_period_secs: i64,
+    pub timelock_secs: i64,
+    pub proposal_count: u64,
+    pub bump: u8,
+}
+
+impl Governor {
+    pub const MAX_SIZE: usize = 8  // Discriminator
+        + 32                       // engine_state_ref
+        + 2                        // quorum_bps (of snapshot staked total)
+        + 8                        // voting_period_secs
+        + 8                        // timelock_secs (after voting ends)
+        + 8                        // proposal_count
+        + 1; // bump
+}
+
+#[account]
+pub struct Proposal {
+    pub governor: Pubkey,
+    pub id: u64,
+    pub proposer: Pubkey,
+    pub action: ProposalAction,
+    pub created_at: i64,
+    pub voting_ends_at: i64,
+    pub snapshot_total_weight: u64,
+    pub yes_weight: u64,
+    pub no_weight: u64,
+    pub executed: bool,
+    pub bump: u8,
+}
+
+impl Proposal {
+    pub const MAX_SIZE: usize = 8  // Discriminator
+        + 32                       // governor
+        + 8                        // id
+        + 32                       // proposer
+        + ProposalAction::MAX_SIZE // action
+        + 8                        // created_at (voting power snapshot time)
+        + 8                        // voting_ends_at
+        + 8                        // snapshot_total_weight (engine total staked plus ve weight at creation)
+        + 8                        // yes_weight
+        + 8                        // no_weight
+        + 1                        // executed
+        + 1; // bump
+}
+
+/// One vote per stake position (NFT stake or ve lock) per proposal.
+#[account]
+pub struct VoteRecord {
+    pub proposal: Pubkey,
+    pub position: Pubkey,
+    pub voter: Pubkey,
+    pub weight: u64,
+    pub approve: bool,
+    pub bump: u8,
+}
+
+impl VoteRecord {
+    pub const MAX_SIZE: usize = 8  // Discriminator
+        + 32                       // proposal
+        + 32                       // position (NftStakeState or VeLock)
+        + 32                       // voter
+        + 8                        // weight
+        + 1                        // approve
+        + 1; // bump
+}
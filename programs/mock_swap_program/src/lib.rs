@@ -9,6 +9,10 @@ const USDC_DECIMALS: u8 = 6;
 const HAIO_DECIMALS: u8 = 9;
 const ATH_DECIMALS: u8 = 9;
 
+/// Seed of the PDA that owns the vaults of `swap_haio_for_usdc`, so other programs can
+/// swap through CPI without an admin co-signing.
+pub const VAULT_AUTHORITY_SEED: &[u8] = b"vault_authority";
+
 #[program]
 pub mod mock_swap_program {
     use super::*;
@@ -81,6 +85,69 @@ pub mod mock_swap_program {
         Ok(())
     }
 
+    /// Pays out of PDA-owned vaults; only the user signs.
+    pub fn swap_haio_for_usdc(ctx: Context<SwapHaioForUsdc>, amount_in: u64) -> Result<()> {
+        require!(amount_in > 0, SwapError::ZeroAmount);
+
+        let rate_u128 = HAIO_PER_USDC as u128;
+        let haio_factor = 10u128.pow(HAIO_DECIMALS as u32);
+        let usdc_factor = 10u128.pow(USDC_DECIMALS as u32);
+        let amount_out_u128 = (amount_in as u128)
+            .checked_mul(usdc_factor)
+            .ok_or(SwapError::CalculationError)?
+            .checked_div(
+                rate_u128
+                    .checked_mul(haio_factor)
+                    .ok_or(SwapError::CalculationError)?,
+            )
+            .ok_or(SwapError::CalculationError)?;
+        let amount_out = amount_out_u128 as u64;
+        require!(amount_out > 0, SwapError::ZeroAmount);
+
+        ctx.accounts.usdc_vault.reload()?;
+        require!(
+            ctx.accounts.usdc_vault.amount >= amount_out,
+            SwapError::InsufficientLiquidity
+        );
+
+        token::transfer(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.user_haio_account.to_account_info(),
+                    to: ctx.accounts.haio_vault.to_account_info(),
+                    authority: ctx.accounts.user_or_op_wallet.to_account_info(),
+                },
+            ),
+            amount_in,
+        )?;
+        msg!(
+            "MockSwap: Received {} HAiO from {}",
+            amount_in,
+            ctx.accounts.user_or_op_wallet.key()
+        );
+
+        token::transfer(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.usdc_vault.to_account_info(),
+                    to: ctx.accounts.user_usdc_account.to_account_info(),
+                    authority: ctx.accounts.vault_authority.to_account_info(),
+                },
+                &[&[VAULT_AUTHORITY_SEED, &[ctx.bumps.vault_authority]]],
+            ),
+            amount_out,
+        )?;
+        msg!(
+            "MockSwap: Sent {} USDC to {}",
+            amount_out,
+            ctx.accounts.user_or_op_wallet.key()
+        );
+
+        Ok(())
+    }
+
     pub fn swap_usdc_for_ath(ctx: Context<SwapUsdcForAth>, amount_in: u64) -> Result<()> {
         require!(amount_in > 0, SwapError::ZeroAmount);
 
@@ -190,6 +257,29 @@ pub struct SwapUsdcForHaio<'info> {
     pub haio_mint: Account<'info, Mint>,
 }
 
+#[derive(Accounts)]
+pub struct SwapHaioForUsdc<'info> {
+    #[account(mut)]
+    pub user_or_op_wallet: Signer<'info>,
+    /// CHECK: PDA that owns the swap vaults.
+    #[account(seeds = [VAULT_AUTHORITY_SEED], bump)]
+    pub vault_authority: AccountInfo<'info>,
+
+    #[account(mut, token::mint = usdc_mint)]
+    pub user_usdc_account: Account<'info, TokenAccount>,
+    #[account(mut, token::mint = haio_mint)]
+    pub user_haio_account: Account<'info, TokenAccount>,
+
+    #[account(mut, token::mint = usdc_mint, token::authority = vault_authority)]
+    pub usdc_vault: Account<'info, TokenAccount>,
+    #[account(mut, token::mint = haio_mint, token::authority = vault_authority)]
+    pub haio_vault: Account<'info, TokenAccount>,
+
+    pub token_program: Program<'info, Token>,
+    pub usdc_mint: Account<'info, Mint>,
+    pub haio_mint: Account<'info, Mint>,
+}
+
 #[derive(Accounts)]
 pub struct SwapUsdcForAth<'info> {
     #[account(mut)]
@@ -1,14 +1,23 @@
 pub mod state;
 
 use anchor_lang::prelude::*;
-use anchor_spl::token::{self, Token, TokenAccount, Transfer};
-use crate::state::EngineState;
+use anchor_lang::system_program;
+use anchor_spl::token::{self, CloseAccount, Token, TokenAccount, Transfer};
+use crate::state::{
+    ve_week_start, DistributionCheckpoint, EngineState, RatioScheduleEntry, SolvencyReport,
+    VeSchedule, CLOSE_GRACE_PERIOD_SECS, MAX_DISTRIBUTION_CHECKPOINTS,
+    MAX_RATIO_SCHEDULE_ENTRIES, MAX_VE_LOCK_SECS, PRECISION,
+};
 
 declare_id!("AUdeJW2sdUErNTqyRvSYcYZJE72yURxLxQ9GeEVayLqq");
 
-const PRECISION: u128 = 1_000_000_000_000; // 10^12
+/// Number of distributions the moving average used by the circuit breaker roughly spans.
+const DISTRIBUTION_AVERAGE_WINDOW: u64 = 8;
 
-const STAKING_PROGRAM_ID_STR: &str = "DNEYpF5jMNjpxAPNYQhPkpuaxWGudBTvyrmKDkNQdZMP";
+const STAKING_PROGRAM_ID: Pubkey = anchor_lang::solana_program::pubkey!("DNEYpF5jMNjpxAPNYQhPkpuaxWGudBTvyrmKDkNQdZMP");
+
+/// Seed of the staking program PDA that signs every staking-only CPI into the engine.
+pub const ENGINE_CALLER_SEED: &[u8] = b"engine_caller";
 
 #[program]
 pub mod revenue_engine {
@@ -39,6 +48,29 @@ pub mod revenue_engine {
         engine_state.reward_per_token_cumulative = 0;
         engine_state.last_distribution_timestamp = 0;
         engine_state.bump = ctx.bumps.engine_state;
+        engine_state.governance = ctx.accounts.authority.key();
+        engine_state.max_distribution_amount = 0;
+        engine_state.max_distribution_multiple_bps = 0;
+        engine_state.distribution_moving_average = 0;
+        engine_state.quarantined_amount = 0;
+        engine_state.total_distributed_staking = 0;
+        engine_state.total_distributed_dao = 0;
+        engine_state.total_distributed_developer = 0;
+        engine_state.total_claimed = 0;
+        engine_state.total_reward_debt = 0;
+        engine_state.staked_update_count = 0;
+        engine_state.ratio_schedule_len = 0;
+        engine_state.ratio_schedule = [RatioScheduleEntry::default(); MAX_RATIO_SCHEDULE_ENTRIES];
+        engine_state.paused = false;
+        engine_state.paused_at = 0;
+        engine_state.total_reward_iou = 0;
+        engine_state.min_stake_age_secs = 0;
+        engine_state.checkpoint_head = 0;
+        engine_state.checkpoint_len = 0;
+        engine_state.checkpoints = [DistributionCheckpoint::default(); MAX_DISTRIBUTION_CHECKPOINTS];
+        engine_state.total_forfeited = 0;
+        engine_state.max_solvency_surplus = 0;
+        engine_state.open_deposits = 0;
 
         msg!("Global Revenue Engine state initialized.");
         Ok(())
@@ -50,96 +82,248 @@ pub mod revenue_engine {
 
         engine_state.reload()?;
         ctx.accounts.revenue_safe.reload()?;
+        require!(!engine_state.paused, ErrorCode::EnginePaused);
 
-        let revenue_amount = ctx.accounts.revenue_safe.amount;
-        msg!("Distributing {} HAiO from Revenue Safe {}", revenue_amount, engine_state.revenue_safe);
+        let safe_balance = ctx.accounts.revenue_safe.amount;
+        let available_amount = safe_balance.saturating_sub(engine_state.quarantined_amount);
+        msg!(
+            "Revenue Safe {} balance: {}, quarantined: {}, available: {}",
+            engine_state.revenue_safe, safe_balance, engine_state.quarantined_amount, available_amount
+        );
 
-        if revenue_amount == 0 {
+        if available_amount == 0 {
             msg!("Revenue Safe is empty.");
             return Ok(());
         }
 
-        let total_staked = engine_state.total_staked_amount;
-        msg!("Current total_staked_amount: {}", total_staked);
-
-        let staker_reward_total = (revenue_amount as u128 * engine_state.staking_ratio_bps as u128 / 10000) as u64;
-        let dao_reward_total = (revenue_amount as u128 * engine_state.dao_ratio_bps as u128 / 10000) as u64;
-        let developer_reward_total = (revenue_amount as u128 * engine_state.developer_ratio_bps as u128 / 10000) as u64;
-
-        let distributed_total = staker_reward_total.checked_add(dao_reward_total).and_then(|sum| sum.checked_add(developer_reward_total)).ok_or(ErrorCode::CalculationError)?;
-        require!(distributed_total <= revenue_amount, ErrorCode::CalculationError);
-        let remaining_in_safe = revenue_amount.saturating_sub(distributed_total);
-
-        msg!(
-            "Calculated distribution: Staker={}, DAO={}, Dev={}, TotalDist={}, Remaining={}",
-            staker_reward_total, dao_reward_total, developer_reward_total, distributed_total, remaining_in_safe
-        );
-
-        let bump_seed = &[engine_state.bump];
-        let seeds = &[ b"engine_state_v1".as_ref(), bump_seed.as_ref() ];
-        let signer_seeds = &[&seeds[..]];
+        let revenue_amount = quarantine_excess(engine_state, available_amount)?;
 
-        if staker_reward_total > 0 {
-            token::transfer(
-                CpiContext::new_with_signer(
-                    ctx.accounts.token_program.to_account_info(),
-                    Transfer { from: ctx.accounts.revenue_safe.to_account_info(), to: ctx.accounts.reward_pool_pda.to_account_info(), authority: engine_state.to_account_info(), },
-                    signer_seeds
-                ), staker_reward_total)?;
-            msg!(" -> Sent {} to Reward Pool PDA", staker_reward_total);
-        }
-        if dao_reward_total > 0 {
-            token::transfer(
-                CpiContext::new_with_signer(
-                    ctx.accounts.token_program.to_account_info(),
-                    Transfer { from: ctx.accounts.revenue_safe.to_account_info(), to: ctx.accounts.dao_treasury_pda.to_account_info(), authority: engine_state.to_account_info(), },
-                    signer_seeds
-                ), dao_reward_total)?;
-             msg!(" -> Sent {} to DAO Treasury PDA.", dao_reward_total);
-        }
-        if developer_reward_total > 0 {
-             token::transfer(
-                 CpiContext::new_with_signer(
-                    ctx.accounts.token_program.to_account_info(),
-                    Transfer { from: ctx.accounts.revenue_safe.to_account_info(), to: ctx.accounts.developer_treasury_pda.to_account_info(), authority: engine_state.to_account_info(), },
-                    signer_seeds
-                 ), developer_reward_total)?;
-             msg!(" -> Sent {} to Developer Treasury PDA.", developer_reward_total);
+        if revenue_amount == 0 {
+            msg!("Nothing left to distribute after quarantine.");
+            return Ok(());
         }
+        msg!("Distributing {} HAiO from Revenue Safe {}", revenue_amount, engine_state.revenue_safe);
+
+        split_revenue(
+            engine_state,
+            &ctx.accounts.revenue_safe,
+            &ctx.accounts.reward_pool_pda,
+            &ctx.accounts.dao_treasury_pda,
+            &ctx.accounts.developer_treasury_pda,
+            &ctx.accounts.token_program,
+            ctx.accounts.ve_schedule.as_deref_mut(),
+            revenue_amount,
+        )?;
 
-        if total_staked > 0 && staker_reward_total > 0 {
-             let delta_reward_per_token = (staker_reward_total as u128).checked_mul(PRECISION)
-                                          .and_then(|v| v.checked_div(total_staked as u128))
-                                          .ok_or(ErrorCode::CalculationError)?;
-             engine_state.reward_per_token_cumulative = engine_state.reward_per_token_cumulative
-                                         .checked_add(delta_reward_per_token)
-                                         .ok_or(ErrorCode::CalculationError)?;
-             msg!("Updated global reward rate: {}", engine_state.reward_per_token_cumulative);
-        } else { msg!("Staked amount is zero or no reward for stakers this period. Rate not updated."); }
+        engine_state.distribution_moving_average =
+            next_moving_average(engine_state.distribution_moving_average, revenue_amount)?;
 
         engine_state.last_distribution_timestamp = Clock::get()?.unix_timestamp;
-        msg!("Distribution finished.");
+        msg!("Distribution finished. Moving average: {}", engine_state.distribution_moving_average);
         Ok(())
     }
 
     pub fn increase_total_staked(ctx: Context<UpdateTotalStaked>, amount: u64) -> Result<()> {
-        msg!("CPI: increase_total_staked (amount: {}) called by: {}", amount, ctx.accounts.caller_program.key());
+        msg!("CPI: increase_total_staked (amount: {}) called by: {}", amount, ctx.accounts.engine_caller.key());
         let engine_state = &mut ctx.accounts.engine_state;
+        require!(!engine_state.paused, ErrorCode::EnginePaused);
         engine_state.total_staked_amount = engine_state.total_staked_amount
             .checked_add(amount).ok_or(ErrorCode::CalculationError)?;
+        let added_debt = engine_state.reward_per_token_cumulative
+            .checked_mul(amount as u128).ok_or(ErrorCode::CalculationError)?;
+        engine_state.total_reward_debt = engine_state.total_reward_debt
+            .checked_add(added_debt).ok_or(ErrorCode::CalculationError)?;
+        engine_state.staked_update_count = engine_state.staked_update_count.wrapping_add(1);
         msg!("New total_staked_amount: {}", engine_state.total_staked_amount);
         Ok(())
     }
 
     pub fn decrease_total_staked(ctx: Context<UpdateTotalStaked>, amount: u64) -> Result<()> {
-        msg!("CPI: decrease_total_staked (amount: {}) called by: {}", amount, ctx.accounts.caller_program.key());
+        msg!("CPI: decrease_total_staked (amount: {}) called by: {}", amount, ctx.accounts.engine_caller.key());
          let engine_state = &mut ctx.accounts.engine_state;
         engine_state.total_staked_amount = engine_state.total_staked_amount
              .checked_sub(amount).ok_or(ErrorCode::CalculationError)?;
+        // The caller settles the position up to the current rate before removing it.
+        let removed_debt = engine_state.reward_per_token_cumulative
+             .checked_mul(amount as u128).ok_or(ErrorCode::CalculationError)?;
+        engine_state.total_reward_debt = engine_state.total_reward_debt
+             .checked_sub(removed_debt).ok_or(ErrorCode::CalculationError)?;
+        engine_state.staked_update_count = engine_state.staked_update_count.wrapping_add(1);
          msg!("New total_staked_amount: {}", engine_state.total_staked_amount);
          Ok(())
      }
 
+    /// Records a claim of `amount`, the reward debt it settled and the part of that debt's
+    /// rewards the positions forfeited to the stake-age rule, which stays in the pool.
+    pub fn record_claim(
+        ctx: Context<UpdateTotalStaked>,
+        amount: u64,
+        settled_reward_debt: u128,
+        forfeited: u64,
+    ) -> Result<()> {
+        msg!("CPI: record_claim (amount: {}, forfeited: {}) called by: {}", amount, forfeited, ctx.accounts.engine_caller.key());
+        let engine_state = &mut ctx.accounts.engine_state;
+        engine_state.total_claimed = engine_state.total_claimed
+            .checked_add(amount).ok_or(ErrorCode::CalculationError)?;
+        engine_state.total_forfeited = engine_state.total_forfeited
+            .checked_add(forfeited).ok_or(ErrorCode::CalculationError)?;
+        engine_state.total_reward_debt = engine_state.total_reward_debt
+            .checked_add(settled_reward_debt).ok_or(ErrorCode::CalculationError)?;
+        // Reconcile passes count reward debt too, so a claim makes them stale.
+        engine_state.staked_update_count = engine_state.staked_update_count.wrapping_add(1);
+        msg!("New total_claimed: {}", engine_state.total_claimed);
+        Ok(())
+    }
+
+    /// Counts a token stake that starts holding a deposit or a new ve lock. Such positions
+    /// still need the engine to be withdrawn, so `close_engine` waits for them.
+    pub fn open_deposit(ctx: Context<UpdateTotalStaked>) -> Result<()> {
+        let engine_state = &mut ctx.accounts.engine_state;
+        engine_state.open_deposits = engine_state.open_deposits
+            .checked_add(1).ok_or(ErrorCode::CalculationError)?;
+        msg!("CPI: open_deposit called by: {} (open: {})", ctx.accounts.engine_caller.key(), engine_state.open_deposits);
+        Ok(())
+    }
+
+    /// Counts a token stake emptied or a ve lock withdrawn or released.
+    pub fn close_deposit(ctx: Context<UpdateTotalStaked>) -> Result<()> {
+        let engine_state = &mut ctx.accounts.engine_state;
+        engine_state.open_deposits = engine_state.open_deposits
+            .checked_sub(1).ok_or(ErrorCode::CalculationError)?;
+        msg!("CPI: close_deposit called by: {} (open: {})", ctx.accounts.engine_caller.key(), engine_state.open_deposits);
+        Ok(())
+    }
+
+    /// Raises the reward rate by `amount` that the staking program has already moved into
+    /// the reward pool (e.g. forfeited vesting), sharing it among everyone currently staked.
+    pub fn redistribute_to_stakers(ctx: Context<UpdateTotalStaked>, amount: u64) -> Result<()> {
+        msg!("CPI: redistribute_to_stakers (amount: {}) called by: {}", amount, ctx.accounts.engine_caller.key());
+        let engine_state = &mut ctx.accounts.engine_state;
+        require!(engine_state.total_staked_amount > 0, ErrorCode::NothingStaked);
+        let delta_reward_per_token = (amount as u128).checked_mul(PRECISION)
+            .and_then(|v| v.checked_div(engine_state.total_staked_amount as u128))
+            .ok_or(ErrorCode::CalculationError)?;
+        engine_state.reward_per_token_cumulative = engine_state.reward_per_token_cumulative
+            .checked_add(delta_reward_per_token).ok_or(ErrorCode::CalculationError)?;
+        engine_state.total_distributed_staking = engine_state.total_distributed_staking
+            .checked_add(amount).ok_or(ErrorCode::CalculationError)?;
+        // A checkpoint that does not move the rate would only evict a real one.
+        if delta_reward_per_token > 0 {
+            engine_state.push_checkpoint(Clock::get()?.unix_timestamp);
+        }
+        msg!("Updated global reward rate: {}", engine_state.reward_per_token_cumulative);
+        Ok(())
+    }
+
+    /// Adds a vote-escrow lock of `weight` whose reward weight decays linearly to zero at
+    /// `unlock_timestamp`, a week boundary at most `MAX_VE_LOCK_SECS` away.
+    pub fn add_ve_lock(ctx: Context<UpdateVeLock>, weight: u64, unlock_timestamp: i64) -> Result<()> {
+        msg!("CPI: add_ve_lock (weight: {}, unlock: {}) called by: {}", weight, unlock_timestamp, ctx.accounts.engine_caller.key());
+        let engine_state = &mut ctx.accounts.engine_state;
+        require!(!engine_state.paused, ErrorCode::EnginePaused);
+        let now = Clock::get()?.unix_timestamp;
+        require!(
+            weight > 0
+                && unlock_timestamp > now
+                && unlock_timestamp <= now.saturating_add(MAX_VE_LOCK_SECS)
+                && ve_week_start(unlock_timestamp) == unlock_timestamp,
+            ErrorCode::InvalidVeLock
+        );
+
+        let ve_schedule = &mut ctx.accounts.ve_schedule;
+        require!(
+            ve_schedule.unlock_week_available(unlock_timestamp),
+            ErrorCode::VeUnlockWeekInUse
+        );
+        ve_schedule.checkpoint(engine_state, now).ok_or(ErrorCode::CalculationError)?;
+        ve_schedule
+            .add_slope(engine_state, VeSchedule::slope_for(weight), now, unlock_timestamp)
+            .ok_or(ErrorCode::CalculationError)?;
+        msg!("New total ve weight: {}", engine_state.ve_weight());
+        Ok(())
+    }
+
+    /// Called once per lock when it settles past its unlock week, after which the lock keeps
+    /// its own copy of that week's reward rates and the week's slot may be reused.
+    pub fn release_ve_unlock(ctx: Context<UpdateVeLock>, unlock_timestamp: i64) -> Result<()> {
+        msg!("CPI: release_ve_unlock (unlock: {}) called by: {}", unlock_timestamp, ctx.accounts.engine_caller.key());
+        let engine_state = &mut ctx.accounts.engine_state;
+        let now = Clock::get()?.unix_timestamp;
+        require!(now >= unlock_timestamp, ErrorCode::InvalidVeLock);
+
+        let ve_schedule = &mut ctx.accounts.ve_schedule;
+        ve_schedule.checkpoint(engine_state, now).ok_or(ErrorCode::CalculationError)?;
+        ve_schedule.release_unlock_week(unlock_timestamp);
+        Ok(())
+    }
+
+    /// Records a ve lock claim: `settled` rewards leave the ve liability, `paid` of them
+    /// were actually transferred (the rest is tracked through `record_reward_iou`).
+    pub fn record_ve_claim(ctx: Context<UpdateTotalStaked>, settled: u64, paid: u64) -> Result<()> {
+        msg!("CPI: record_ve_claim (settled: {}, paid: {}) called by: {}", settled, paid, ctx.accounts.engine_caller.key());
+        let engine_state = &mut ctx.accounts.engine_state;
+        // Per-lock rewards round down, so the aggregate may end up slightly above their sum.
+        engine_state.ve_rewards_outstanding = engine_state.ve_rewards_outstanding.saturating_sub(settled);
+        engine_state.total_claimed = engine_state.total_claimed
+            .checked_add(paid).ok_or(ErrorCode::CalculationError)?;
+        msg!("New ve_rewards_outstanding: {}", engine_state.ve_rewards_outstanding);
+        Ok(())
+    }
+
+    /// Creates the week ring used by vote-escrow locks.
+    pub fn initialize_ve_schedule(ctx: Context<InitializeVeSchedule>) -> Result<()> {
+        let ve_schedule = &mut ctx.accounts.ve_schedule;
+        ve_schedule.engine_state_ref = ctx.accounts.engine_state.key();
+        ve_schedule.bump = ctx.bumps.ve_schedule;
+        msg!("Ve schedule initialized: {}", ve_schedule.key());
+        Ok(())
+    }
+
+    /// Tracks rewards that were earned but could not be paid because the reward pool was short.
+    pub fn record_reward_iou(ctx: Context<UpdateTotalStaked>, issued: u64, repaid: u64) -> Result<()> {
+        msg!("CPI: record_reward_iou (issued: {}, repaid: {}) called by: {}", issued, repaid, ctx.accounts.engine_caller.key());
+        let engine_state = &mut ctx.accounts.engine_state;
+        engine_state.total_reward_iou = engine_state.total_reward_iou
+            .checked_add(issued)
+            .and_then(|v| v.checked_sub(repaid))
+            .ok_or(ErrorCode::CalculationError)?;
+        msg!("New total_reward_iou: {}", engine_state.total_reward_iou);
+        Ok(())
+    }
+
+    pub fn correct_total_staked(
+        ctx: Context<CorrectTotalStaked>,
+        new_total_staked: u64,
+        new_total_reward_debt: u128,
+    ) -> Result<()> {
+        msg!("CPI: correct_total_staked called by: {}", ctx.accounts.engine_caller.key());
+        let engine_state = &mut ctx.accounts.engine_state;
+        msg!(
+            "Correcting total_staked_amount {} -> {}, total_reward_debt {} -> {}",
+            engine_state.total_staked_amount, new_total_staked,
+            engine_state.total_reward_debt, new_total_reward_debt
+        );
+        engine_state.total_staked_amount = new_total_staked;
+        engine_state.total_reward_debt = new_total_reward_debt;
+        engine_state.staked_update_count = engine_state.staked_update_count.wrapping_add(1);
+        Ok(())
+    }
+
+    /// Compares the reward pool with what it owes stakers. Fails if the pool falls short, or
+    /// holds more than the liabilities plus the forfeited stake-age shares and the authority's
+    /// `max_solvency_surplus` (rounding dust leaves a small legitimate surplus; a larger one
+    /// means under-counted liabilities).
+    pub fn check_solvency(ctx: Context<CheckSolvency>) -> Result<SolvencyReport> {
+        solvency_report(&ctx.accounts.engine_state, ctx.accounts.reward_pool_pda.amount)
+    }
+
+    /// Sets how much unexplained surplus `check_solvency` tolerates in the reward pool.
+    pub fn set_solvency_tolerance(ctx: Context<UpdateRatios>, max_surplus: u64) -> Result<()> {
+        ctx.accounts.engine_state.max_solvency_surplus = max_surplus;
+        msg!("Solvency surplus tolerance set to {}", max_surplus);
+        Ok(())
+    }
+
      pub fn update_ratios(
          ctx: Context<UpdateRatios>,
          new_staking_ratio: u16,
@@ -148,19 +332,534 @@ pub mod revenue_engine {
     ) -> Result<()> {
          let engine_state = &mut ctx.accounts.engine_state;
          require_keys_eq!(ctx.accounts.authority.key(), engine_state.authority, ErrorCode::Unauthorized);
+        require_ungoverned(engine_state)?;
+        set_ratios(engine_state, new_staking_ratio, new_dao_ratio, new_dev_ratio)?;
 
-         let total_ratio = new_staking_ratio.checked_add(new_dao_ratio)
-            .and_then(|sum| sum.checked_add(new_dev_ratio))
-            .ok_or(ErrorCode::CalculationError)?;
-         require!(total_ratio <= 10000, ErrorCode::InvalidRatioSum);
+        msg!("Ratios updated: Staking={}, DAO={}, Dev={}", new_staking_ratio, new_dao_ratio, new_dev_ratio);
+        Ok(())
+    }
 
-        engine_state.staking_ratio_bps = new_staking_ratio;
-         engine_state.dao_ratio_bps = new_dao_ratio;
-         engine_state.developer_ratio_bps = new_dev_ratio;
+    /// Replaces the ratio schedule. Entries must be in strictly increasing timestamp order;
+    /// the latest entry whose timestamp has passed overrides the ratios at each distribution.
+    /// A later `update_ratios` clears the schedule.
+    pub fn set_ratio_schedule(
+        ctx: Context<UpdateRatios>,
+        entries: Vec<RatioScheduleEntry>,
+    ) -> Result<()> {
+        let engine_state = &mut ctx.accounts.engine_state;
+        require_ungoverned(engine_state)?;
+        require!(entries.len() <= MAX_RATIO_SCHEDULE_ENTRIES, ErrorCode::InvalidRatioSchedule);
 
-        msg!("Ratios updated: Staking={}, DAO={}, Dev={}", new_staking_ratio, new_dao_ratio, new_dev_ratio);
+        let mut previous_timestamp: Option<i64> = None;
+        for entry in entries.iter() {
+            if let Some(previous) = previous_timestamp {
+                require!(entry.effective_timestamp > previous, ErrorCode::InvalidRatioSchedule);
+            }
+            let total_ratio = entry.staking_ratio_bps.checked_add(entry.dao_ratio_bps)
+                .and_then(|sum| sum.checked_add(entry.developer_ratio_bps))
+                .ok_or(ErrorCode::CalculationError)?;
+            require!(total_ratio <= 10000, ErrorCode::InvalidRatioSum);
+            previous_timestamp = Some(entry.effective_timestamp);
+        }
+
+        let mut schedule = [RatioScheduleEntry::default(); MAX_RATIO_SCHEDULE_ENTRIES];
+        schedule[..entries.len()].copy_from_slice(&entries);
+        engine_state.ratio_schedule = schedule;
+        engine_state.ratio_schedule_len = entries.len() as u8;
+
+        msg!("Ratio schedule set with {} entries", entries.len());
+        Ok(())
+    }
+
+    pub fn configure_circuit_breaker(
+        ctx: Context<UpdateRatios>,
+        max_distribution_amount: u64,
+        max_distribution_multiple_bps: u32,
+    ) -> Result<()> {
+        let engine_state = &mut ctx.accounts.engine_state;
+        require!(
+            max_distribution_multiple_bps == 0 || max_distribution_multiple_bps >= 10000,
+            ErrorCode::InvalidCircuitBreaker
+        );
+
+        engine_state.max_distribution_amount = max_distribution_amount;
+        engine_state.max_distribution_multiple_bps = max_distribution_multiple_bps;
+
+        msg!(
+            "Circuit breaker updated: MaxAmount={}, MaxMultipleBps={}",
+            max_distribution_amount, max_distribution_multiple_bps
+        );
+        Ok(())
+    }
+
+    /// Hands the governance role over. Once it has left the authority, ratios can only be
+    /// changed through governance and the authority cannot take the role back.
+    pub fn set_governance(ctx: Context<UpdateRatios>, new_governance: Pubkey) -> Result<()> {
+        let engine_state = &mut ctx.accounts.engine_state;
+        require_ungoverned(engine_state)?;
+        engine_state.governance = new_governance;
+        msg!("Governance role set to {}", new_governance);
+        Ok(())
+    }
+
+    pub fn release_quarantined(ctx: Context<ReleaseQuarantined>, amount: u64) -> Result<()> {
+        let engine_state = &mut ctx.accounts.engine_state;
+        require!(!engine_state.paused, ErrorCode::EnginePaused);
+        require!(amount > 0, ErrorCode::CalculationError);
+        require!(amount <= engine_state.quarantined_amount, ErrorCode::QuarantineExceeded);
+
+        ctx.accounts.revenue_safe.reload()?;
+        require!(ctx.accounts.revenue_safe.amount >= amount, ErrorCode::QuarantineExceeded);
+
+        engine_state.quarantined_amount -= amount;
+        msg!("Releasing {} quarantined HAiO for distribution", amount);
+
+        split_revenue(
+            engine_state,
+            &ctx.accounts.revenue_safe,
+            &ctx.accounts.reward_pool_pda,
+            &ctx.accounts.dao_treasury_pda,
+            &ctx.accounts.developer_treasury_pda,
+            &ctx.accounts.token_program,
+            ctx.accounts.ve_schedule.as_deref_mut(),
+            amount,
+        )?;
+
+        engine_state.last_distribution_timestamp = Clock::get()?.unix_timestamp;
+        msg!("Quarantine release finished. Remaining quarantined: {}", engine_state.quarantined_amount);
+        Ok(())
+    }
+
+    pub fn refund_quarantined(ctx: Context<RefundQuarantined>, amount: u64) -> Result<()> {
+        let engine_state = &mut ctx.accounts.engine_state;
+        require!(amount > 0, ErrorCode::CalculationError);
+        require!(amount <= engine_state.quarantined_amount, ErrorCode::QuarantineExceeded);
+
+        let bump_seed = &[engine_state.bump];
+        let seeds = &[ b"engine_state_v1".as_ref(), bump_seed.as_ref() ];
+        let signer_seeds = &[&seeds[..]];
+
+        token::transfer(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer { from: ctx.accounts.revenue_safe.to_account_info(), to: ctx.accounts.refund_destination.to_account_info(), authority: engine_state.to_account_info(), },
+                signer_seeds
+            ), amount)?;
+
+        engine_state.quarantined_amount -= amount;
+        msg!(
+            "Refunded {} quarantined HAiO to {}. Remaining quarantined: {}",
+            amount, ctx.accounts.refund_destination.key(), engine_state.quarantined_amount
+        );
         Ok(())
     }
+
+    /// Ratio update executed by the governance role (e.g. a passed DAO proposal).
+    pub fn governance_update_ratios(
+        ctx: Context<GovernanceAction>,
+        new_staking_ratio: u16,
+        new_dao_ratio: u16,
+        new_dev_ratio: u16,
+    ) -> Result<()> {
+        let engine_state = &mut ctx.accounts.engine_state;
+        set_ratios(engine_state, new_staking_ratio, new_dao_ratio, new_dev_ratio)?;
+
+        msg!("Ratios updated by governance: Staking={}, DAO={}, Dev={}", new_staking_ratio, new_dao_ratio, new_dev_ratio);
+        Ok(())
+    }
+
+    /// Spends from the DAO treasury on behalf of the governance role. The treasury token
+    /// account must be owned by the engine PDA for the engine to sign the transfer.
+    pub fn governance_transfer_dao_treasury(ctx: Context<GovernanceTreasuryTransfer>, amount: u64) -> Result<()> {
+        require!(amount > 0, ErrorCode::CalculationError);
+        let engine_state = &ctx.accounts.engine_state;
+        let bump_seed = &[engine_state.bump];
+        let seeds = &[ b"engine_state_v1".as_ref(), bump_seed.as_ref() ];
+        let signer_seeds = &[&seeds[..]];
+
+        token::transfer(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer { from: ctx.accounts.dao_treasury_pda.to_account_info(), to: ctx.accounts.destination.to_account_info(), authority: engine_state.to_account_info(), },
+                signer_seeds
+            ), amount)?;
+
+        msg!("Governance transferred {} from DAO treasury to {}", amount, ctx.accounts.destination.key());
+        Ok(())
+    }
+
+    /// Positions staked from now on only share in distributions made at least
+    /// `min_stake_age_secs` after they were staked; earlier increments are forfeited to the pool.
+    pub fn set_min_stake_age(ctx: Context<UpdateRatios>, min_stake_age_secs: i64) -> Result<()> {
+        require!(min_stake_age_secs >= 0, ErrorCode::InvalidMinStakeAge);
+        ctx.accounts.engine_state.min_stake_age_secs = min_stake_age_secs;
+        msg!("Minimum stake age set to {} seconds", min_stake_age_secs);
+        Ok(())
+    }
+
+    pub fn set_paused(ctx: Context<UpdateRatios>, paused: bool) -> Result<()> {
+        let engine_state = &mut ctx.accounts.engine_state;
+        if paused && !engine_state.paused {
+            engine_state.paused_at = Clock::get()?.unix_timestamp;
+        }
+        engine_state.paused = paused;
+        msg!("Engine paused: {} (since {})", paused, engine_state.paused_at);
+        Ok(())
+    }
+
+    /// Retires the engine. The Reward Pool must already be swept through `staking_program`,
+    /// and every stake, deposit, ve lock, IOU and ve reward must be settled, since none of
+    /// them can be withdrawn without the engine. Treasuries are swept and closed only if they
+    /// are custodied by the EngineState PDA; the ve schedule is closed along with the engine.
+    pub fn close_engine(ctx: Context<CloseEngine>) -> Result<()> {
+        let engine_state = &ctx.accounts.engine_state;
+        require!(engine_state.paused, ErrorCode::EngineNotPaused);
+        let now = Clock::get()?.unix_timestamp;
+        let closable_at = engine_state.paused_at
+            .checked_add(CLOSE_GRACE_PERIOD_SECS).ok_or(ErrorCode::CalculationError)?;
+        require!(now >= closable_at, ErrorCode::GracePeriodActive);
+        require!(engine_state.total_staked_amount == 0, ErrorCode::StakesOutstanding);
+        require!(
+            engine_state.open_deposits == 0 && engine_state.ve_slope == 0,
+            ErrorCode::StakesOutstanding
+        );
+        require!(
+            engine_state.total_reward_iou == 0 && engine_state.ve_rewards_outstanding == 0,
+            ErrorCode::RewardsOutstanding
+        );
+        require!(ctx.accounts.reward_pool_pda.amount == 0, ErrorCode::RewardPoolNotEmpty);
+        // A ve schedule exists once ve locks were used; it must close with the engine.
+        require!(
+            engine_state.ve_last_timestamp == 0 || ctx.accounts.ve_schedule.is_some(),
+            ErrorCode::VeScheduleRequired
+        );
+
+        let bump_seed = &[engine_state.bump];
+        let seeds = &[ b"engine_state_v1".as_ref(), bump_seed.as_ref() ];
+        let signer_seeds = &[&seeds[..]];
+
+        let engine_owned_accounts = [
+            (&ctx.accounts.revenue_safe, &ctx.accounts.safe_destination, "Revenue Safe"),
+            (&ctx.accounts.dao_treasury_pda, &ctx.accounts.dao_destination, "DAO Treasury"),
+            (&ctx.accounts.developer_treasury_pda, &ctx.accounts.developer_destination, "Developer Treasury"),
+        ];
+        for (source, destination, label) in engine_owned_accounts {
+            if source.owner != engine_state.key() {
+                msg!(" -> {} {} is not custodied by the engine; skipped.", label, source.key());
+                continue;
+            }
+            if source.amount > 0 {
+                token::transfer(
+                    CpiContext::new_with_signer(
+                        ctx.accounts.token_program.to_account_info(),
+                        Transfer { from: source.to_account_info(), to: destination.to_account_info(), authority: engine_state.to_account_info(), },
+                        signer_seeds
+                    ), source.amount)?;
+                msg!(" -> Swept {} from {} to {}", source.amount, label, destination.key());
+            }
+            token::close_account(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    CloseAccount { account: source.to_account_info(), destination: ctx.accounts.authority.to_account_info(), authority: engine_state.to_account_info(), },
+                    signer_seeds
+                ))?;
+            msg!(" -> Closed {} {}", label, source.key());
+        }
+
+        msg!("Engine {} closed by {}", engine_state.key(), ctx.accounts.authority.key());
+        Ok(())
+    }
+
+    /// Grows an `EngineState` created by an earlier program version to the current layout.
+    /// Fields added since were appended and start zeroed; `migrate_engine_state` fills the
+    /// ones that cannot stay zero. Anyone may pay the extra rent; a current account is left alone.
+    pub fn migrate_account(ctx: Context<MigrateAccount>) -> Result<()> {
+        let account = ctx.accounts.account.to_account_info();
+        require!(
+            account.try_borrow_data()?.starts_with(EngineState::DISCRIMINATOR),
+            ErrorCode::AccountNotMigratable
+        );
+        let space = 8 + EngineState::MAX_SIZE;
+        let old_len = account.data_len();
+        if old_len >= space {
+            msg!("Account {} is already at the current layout ({} bytes)", account.key(), old_len);
+            return Ok(());
+        }
+
+        let top_up = Rent::get()?.minimum_balance(space).saturating_sub(account.lamports());
+        if top_up > 0 {
+            system_program::transfer(
+                CpiContext::new(
+                    ctx.accounts.system_program.to_account_info(),
+                    system_program::Transfer {
+                        from: ctx.accounts.payer.to_account_info(),
+                        to: account.clone(),
+                    },
+                ),
+                top_up,
+            )?;
+        }
+        account.realloc(space, true)?;
+
+        let mut engine_state = EngineState::try_deserialize(&mut &account.try_borrow_data()?[..])?;
+        migrate_engine_state(&mut engine_state);
+        engine_state.try_serialize(&mut &mut account.try_borrow_mut_data()?[..])?;
+
+        msg!("Migrated account {} from {} to {} bytes", account.key(), old_len, space);
+        Ok(())
+    }
+}
+
+fn solvency_report(engine_state: &EngineState, pool_balance: u64) -> Result<SolvencyReport> {
+    let liabilities = engine_state
+        .reward_liabilities()
+        .ok_or(ErrorCode::AccountingMismatch)?;
+
+    msg!(
+        "Solvency: pool={}, liabilities={} (iou {}, ve {}), distributed_to_stakers={}, claimed={}",
+        pool_balance, liabilities, engine_state.total_reward_iou, engine_state.ve_rewards_outstanding,
+        engine_state.total_distributed_staking, engine_state.total_claimed
+    );
+    require!(pool_balance as u128 >= liabilities, ErrorCode::SolvencyCheckFailed);
+    // Forfeited shares stay in the pool until they pay someone else's IOU or are swept.
+    let surplus = (pool_balance as u128 - liabilities)
+        .saturating_sub(engine_state.total_forfeited as u128);
+    msg!(
+        "Reward pool surplus: {} beyond {} forfeited (tolerance {})",
+        surplus, engine_state.total_forfeited, engine_state.max_solvency_surplus
+    );
+    require!(surplus <= engine_state.max_solvency_surplus as u128, ErrorCode::UnexpectedSurplus);
+
+    Ok(SolvencyReport { pool_balance, liabilities })
+}
+
+/// Fills the appended fields of a migrated `EngineState` whose zero value would mean something
+/// else. A zeroed `governance` would read as handed over and lock the authority out of ratio
+/// changes, so it starts as the authority like it does in `initialize_engine_state`.
+fn migrate_engine_state(engine_state: &mut EngineState) {
+    if engine_state.governance == Pubkey::default() {
+        engine_state.governance = engine_state.authority;
+    }
+}
+
+/// Ratios, the ratio schedule and the governance role are the authority's until governance
+/// has been handed over.
+fn require_ungoverned(engine_state: &EngineState) -> Result<()> {
+    require_keys_eq!(engine_state.governance, engine_state.authority, ErrorCode::RatiosGoverned);
+    Ok(())
+}
+
+fn set_ratios(
+    engine_state: &mut EngineState,
+    staking_ratio_bps: u16,
+    dao_ratio_bps: u16,
+    developer_ratio_bps: u16,
+) -> Result<()> {
+    let total_ratio = staking_ratio_bps.checked_add(dao_ratio_bps)
+        .and_then(|sum| sum.checked_add(developer_ratio_bps))
+        .ok_or(ErrorCode::CalculationError)?;
+    require!(total_ratio <= 10000, ErrorCode::InvalidRatioSum);
+
+    engine_state.staking_ratio_bps = staking_ratio_bps;
+    engine_state.dao_ratio_bps = dao_ratio_bps;
+    engine_state.developer_ratio_bps = developer_ratio_bps;
+    // The schedule would overwrite these at the next distribution; a manual update replaces it.
+    clear_ratio_schedule(engine_state);
+    Ok(())
+}
+
+fn current_distribution_cap(engine_state: &EngineState) -> Result<u64> {
+    let mut cap = u64::MAX;
+    if engine_state.max_distribution_amount > 0 {
+        cap = engine_state.max_distribution_amount;
+    }
+    if engine_state.max_distribution_multiple_bps > 0 && engine_state.distribution_moving_average > 0 {
+        let average_cap = (engine_state.distribution_moving_average as u128)
+            .checked_mul(engine_state.max_distribution_multiple_bps as u128)
+            .map(|v| v / 10000)
+            .ok_or(ErrorCode::CalculationError)?;
+        cap = cap.min(average_cap.min(u64::MAX as u128) as u64);
+    }
+    Ok(cap)
+}
+
+/// Applies the circuit breaker to `available_amount`: anything above the current cap is
+/// quarantined. Returns the amount to distribute.
+fn quarantine_excess(engine_state: &mut EngineState, available_amount: u64) -> Result<u64> {
+    let distribution_cap = current_distribution_cap(engine_state)?;
+    if available_amount <= distribution_cap {
+        return Ok(available_amount);
+    }
+    let excess = available_amount - distribution_cap;
+    engine_state.quarantined_amount = engine_state.quarantined_amount
+        .checked_add(excess).ok_or(ErrorCode::CalculationError)?;
+    msg!(
+        "Circuit breaker tripped: cap={}, quarantined {} (total quarantined: {})",
+        distribution_cap, excess, engine_state.quarantined_amount
+    );
+    Ok(distribution_cap)
+}
+
+fn next_moving_average(average: u64, revenue_amount: u64) -> Result<u64> {
+    if average == 0 {
+        return Ok(revenue_amount);
+    }
+    let weighted = (average as u128)
+        .checked_mul((DISTRIBUTION_AVERAGE_WINDOW - 1) as u128)
+        .and_then(|v| v.checked_add(revenue_amount as u128))
+        .ok_or(ErrorCode::CalculationError)?;
+    Ok((weighted / DISTRIBUTION_AVERAGE_WINDOW as u128) as u64)
+}
+
+fn clear_ratio_schedule(engine_state: &mut EngineState) {
+    if engine_state.ratio_schedule_len > 0 {
+        msg!("Cleared ratio schedule of {} entries", engine_state.ratio_schedule_len);
+        engine_state.ratio_schedule = [RatioScheduleEntry::default(); MAX_RATIO_SCHEDULE_ENTRIES];
+        engine_state.ratio_schedule_len = 0;
+    }
+}
+
+fn apply_scheduled_ratios(engine_state: &mut EngineState) -> Result<()> {
+    let now = Clock::get()?.unix_timestamp;
+    let schedule_len = engine_state.ratio_schedule_len as usize;
+    let active = engine_state.ratio_schedule[..schedule_len]
+        .iter()
+        .rev()
+        .find(|entry| entry.effective_timestamp <= now)
+        .copied();
+
+    if let Some(entry) = active {
+        if entry.staking_ratio_bps != engine_state.staking_ratio_bps
+            || entry.dao_ratio_bps != engine_state.dao_ratio_bps
+            || entry.developer_ratio_bps != engine_state.developer_ratio_bps
+        {
+            engine_state.staking_ratio_bps = entry.staking_ratio_bps;
+            engine_state.dao_ratio_bps = entry.dao_ratio_bps;
+            engine_state.developer_ratio_bps = entry.developer_ratio_bps;
+            msg!(
+                "Scheduled ratios active since {}: Staking={}, DAO={}, Dev={}",
+                entry.effective_timestamp, entry.staking_ratio_bps, entry.dao_ratio_bps, entry.developer_ratio_bps
+            );
+        }
+    }
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+fn split_revenue<'info>(
+    engine_state: &mut Account<'info, EngineState>,
+    revenue_safe: &Account<'info, TokenAccount>,
+    reward_pool_pda: &Account<'info, TokenAccount>,
+    dao_treasury_pda: &Account<'info, TokenAccount>,
+    developer_treasury_pda: &Account<'info, TokenAccount>,
+    token_program: &Program<'info, Token>,
+    ve_schedule: Option<&mut Account<'info, VeSchedule>>,
+    revenue_amount: u64,
+) -> Result<()> {
+    apply_scheduled_ratios(engine_state)?;
+
+    let now = Clock::get()?.unix_timestamp;
+    let ve_weight = match ve_schedule {
+        Some(ve_schedule) => {
+            ve_schedule.checkpoint(engine_state, now).ok_or(ErrorCode::CalculationError)?;
+            engine_state.ve_weight()
+        }
+        None => {
+            require!(engine_state.ve_slope == 0, ErrorCode::VeScheduleRequired);
+            0
+        }
+    };
+    let total_staked = engine_state.total_staked_amount;
+    msg!("Current total_staked_amount: {}, ve weight: {}", total_staked, ve_weight);
+
+    let staker_reward_total = (revenue_amount as u128 * engine_state.staking_ratio_bps as u128 / 10000) as u64;
+    let dao_reward_total = (revenue_amount as u128 * engine_state.dao_ratio_bps as u128 / 10000) as u64;
+    let developer_reward_total = (revenue_amount as u128 * engine_state.developer_ratio_bps as u128 / 10000) as u64;
+
+    let distributed_total = staker_reward_total.checked_add(dao_reward_total).and_then(|sum| sum.checked_add(developer_reward_total)).ok_or(ErrorCode::CalculationError)?;
+    require!(distributed_total <= revenue_amount, ErrorCode::CalculationError);
+    let remaining_in_safe = revenue_amount.saturating_sub(distributed_total);
+
+    msg!(
+        "Calculated distribution: Staker={}, DAO={}, Dev={}, TotalDist={}, Remaining={}",
+        staker_reward_total, dao_reward_total, developer_reward_total, distributed_total, remaining_in_safe
+    );
+
+    let bump_seed = &[engine_state.bump];
+    let seeds = &[ b"engine_state_v1".as_ref(), bump_seed.as_ref() ];
+    let signer_seeds = &[&seeds[..]];
+
+    if staker_reward_total > 0 {
+        token::transfer(
+            CpiContext::new_with_signer(
+                token_program.to_account_info(),
+                Transfer { from: revenue_safe.to_account_info(), to: reward_pool_pda.to_account_info(), authority: engine_state.to_account_info(), },
+                signer_seeds
+            ), staker_reward_total)?;
+        msg!(" -> Sent {} to Reward Pool PDA", staker_reward_total);
+    }
+    if dao_reward_total > 0 {
+        token::transfer(
+            CpiContext::new_with_signer(
+                token_program.to_account_info(),
+                Transfer { from: revenue_safe.to_account_info(), to: dao_treasury_pda.to_account_info(), authority: engine_state.to_account_info(), },
+                signer_seeds
+            ), dao_reward_total)?;
+         msg!(" -> Sent {} to DAO Treasury PDA.", dao_reward_total);
+    }
+    if developer_reward_total > 0 {
+         token::transfer(
+             CpiContext::new_with_signer(
+                token_program.to_account_info(),
+                Transfer { from: revenue_safe.to_account_info(), to: developer_treasury_pda.to_account_info(), authority: engine_state.to_account_info(), },
+                signer_seeds
+             ), developer_reward_total)?;
+         msg!(" -> Sent {} to Developer Treasury PDA.", developer_reward_total);
+    }
+
+    engine_state.total_distributed_staking = engine_state.total_distributed_staking
+        .checked_add(staker_reward_total).ok_or(ErrorCode::CalculationError)?;
+    engine_state.total_distributed_dao = engine_state.total_distributed_dao
+        .checked_add(dao_reward_total).ok_or(ErrorCode::CalculationError)?;
+    engine_state.total_distributed_developer = engine_state.total_distributed_developer
+        .checked_add(developer_reward_total).ok_or(ErrorCode::CalculationError)?;
+
+    // Ve locks take their current decayed weight's share; the rest goes to regular positions.
+    let total_weight = total_staked as u128 + ve_weight as u128;
+    let ve_reward_total = (staker_reward_total as u128 * ve_weight as u128)
+        .checked_div(total_weight)
+        .unwrap_or(0) as u64;
+    let linear_reward_total = staker_reward_total - ve_reward_total;
+
+    if total_staked > 0 && linear_reward_total > 0 {
+         let delta_reward_per_token = (linear_reward_total as u128).checked_mul(PRECISION)
+                                      .and_then(|v| v.checked_div(total_staked as u128))
+                                      .ok_or(ErrorCode::CalculationError)?;
+         engine_state.reward_per_token_cumulative = engine_state.reward_per_token_cumulative
+                                     .checked_add(delta_reward_per_token)
+                                     .ok_or(ErrorCode::CalculationError)?;
+         if delta_reward_per_token > 0 {
+             engine_state.push_checkpoint(now);
+         }
+         msg!("Updated global reward rate: {}", engine_state.reward_per_token_cumulative);
+    } else { msg!("Staked amount is zero or no reward for stakers this period. Rate not updated."); }
+
+    if ve_reward_total > 0 {
+        let delta_reward_per_weight = (ve_reward_total as u128).checked_mul(PRECISION)
+            .and_then(|v| v.checked_div(ve_weight as u128))
+            .ok_or(ErrorCode::CalculationError)?;
+        engine_state.ve_reward_per_weight_cumulative = engine_state.ve_reward_per_weight_cumulative
+            .checked_add(delta_reward_per_weight).ok_or(ErrorCode::CalculationError)?;
+        engine_state.ve_reward_time_cumulative = delta_reward_per_weight
+            .checked_mul(now as u128)
+            .and_then(|v| engine_state.ve_reward_time_cumulative.checked_add(v))
+            .ok_or(ErrorCode::CalculationError)?;
+        engine_state.ve_rewards_outstanding = engine_state.ve_rewards_outstanding
+            .checked_add(ve_reward_total).ok_or(ErrorCode::CalculationError)?;
+        msg!("Updated ve reward rate: {} ({} HAiO to ve locks)", engine_state.ve_reward_per_weight_cumulative, ve_reward_total);
+    }
+
+    Ok(())
 }
 
 // --- Account Contexts ---
@@ -218,6 +917,10 @@ pub struct DistributeRevenue<'info> {
     pub developer_treasury_pda: Account<'info, TokenAccount>,
 
     pub token_program: Program<'info, Token>,
+
+    /// Required once vote-escrow locks exist.
+    #[account(mut, seeds = [b"ve_schedule", engine_state.key().as_ref()], bump = ve_schedule.bump)]
+    pub ve_schedule: Option<Box<Account<'info, VeSchedule>>>,
 }
 
 #[derive(Accounts)]
@@ -229,12 +932,124 @@ pub struct UpdateTotalStaked<'info> {
     )]
     pub engine_state: Account<'info, EngineState>,
 
-    /// CHECK: This ensures only the staking program can call this.
+    /// PDA of the staking program; only the staking program can sign for it.
+    #[account(seeds = [ENGINE_CALLER_SEED], bump, seeds::program = STAKING_PROGRAM_ID)]
+    pub engine_caller: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateVeLock<'info> {
     #[account(
-        executable,
-        constraint = caller_program.key().to_string() == STAKING_PROGRAM_ID_STR @ ErrorCode::Unauthorized
+        mut,
+        seeds = [b"engine_state_v1"],
+        bump = engine_state.bump
     )]
-    pub caller_program: AccountInfo<'info>,
+    pub engine_state: Account<'info, EngineState>,
+
+    #[account(mut, seeds = [b"ve_schedule", engine_state.key().as_ref()], bump = ve_schedule.bump)]
+    pub ve_schedule: Box<Account<'info, VeSchedule>>,
+
+    /// PDA of the staking program; only the staking program can sign for it.
+    #[account(seeds = [ENGINE_CALLER_SEED], bump, seeds::program = STAKING_PROGRAM_ID)]
+    pub engine_caller: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeVeSchedule<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    #[account(
+        seeds = [b"engine_state_v1"],
+        bump = engine_state.bump,
+        has_one = authority @ ErrorCode::Unauthorized,
+    )]
+    pub engine_state: Account<'info, EngineState>,
+
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + VeSchedule::MAX_SIZE,
+        seeds = [b"ve_schedule", engine_state.key().as_ref()],
+        bump
+    )]
+    pub ve_schedule: Box<Account<'info, VeSchedule>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CorrectTotalStaked<'info> {
+    #[account(
+        mut,
+        seeds = [b"engine_state_v1"],
+        bump = engine_state.bump,
+        has_one = authority @ ErrorCode::Unauthorized,
+    )]
+    pub engine_state: Account<'info, EngineState>,
+    pub authority: Signer<'info>,
+
+    /// PDA of the staking program; only the staking program can sign for it.
+    #[account(seeds = [ENGINE_CALLER_SEED], bump, seeds::program = STAKING_PROGRAM_ID)]
+    pub engine_caller: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CloseEngine<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        close = authority,
+        seeds = [b"engine_state_v1"],
+        bump = engine_state.bump,
+        has_one = authority @ ErrorCode::Unauthorized,
+        has_one = revenue_safe,
+        has_one = reward_pool_pda,
+        has_one = dao_treasury_pda,
+        has_one = developer_treasury_pda,
+    )]
+    pub engine_state: Account<'info, EngineState>,
+
+    #[account(mut)]
+    pub revenue_safe: Account<'info, TokenAccount>,
+    #[account(mut, constraint = safe_destination.mint == revenue_safe.mint @ ErrorCode::InvalidMint)]
+    pub safe_destination: Account<'info, TokenAccount>,
+
+    pub reward_pool_pda: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub dao_treasury_pda: Account<'info, TokenAccount>,
+    #[account(mut, constraint = dao_destination.mint == dao_treasury_pda.mint @ ErrorCode::InvalidMint)]
+    pub dao_destination: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub developer_treasury_pda: Account<'info, TokenAccount>,
+    #[account(mut, constraint = developer_destination.mint == developer_treasury_pda.mint @ ErrorCode::InvalidMint)]
+    pub developer_destination: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        close = authority,
+        seeds = [b"ve_schedule", engine_state.key().as_ref()],
+        bump = ve_schedule.bump,
+    )]
+    pub ve_schedule: Option<Box<Account<'info, VeSchedule>>>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct CheckSolvency<'info> {
+    #[account(
+        seeds = [b"engine_state_v1"],
+        bump = engine_state.bump,
+        has_one = reward_pool_pda,
+    )]
+    pub engine_state: Account<'info, EngineState>,
+
+    pub reward_pool_pda: Account<'info, TokenAccount>,
 }
 
  #[derive(Accounts)]
@@ -244,6 +1059,117 @@ pub struct UpdateTotalStaked<'info> {
     pub authority: Signer<'info>,
 }
 
+#[derive(Accounts)]
+pub struct ReleaseQuarantined<'info> {
+    #[account(
+        mut,
+        seeds = [b"engine_state_v1"],
+        bump = engine_state.bump,
+        has_one = governance @ ErrorCode::Unauthorized,
+        has_one = revenue_safe,
+        has_one = reward_pool_pda,
+        has_one = dao_treasury_pda,
+        has_one = developer_treasury_pda,
+    )]
+    pub engine_state: Account<'info, EngineState>,
+    pub governance: Signer<'info>,
+
+    #[account(
+        mut,
+        constraint = revenue_safe.owner == engine_state.key() @ ErrorCode::InvalidOwner
+    )]
+    pub revenue_safe: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub reward_pool_pda: Account<'info, TokenAccount>,
+    #[account(mut)]
+    pub dao_treasury_pda: Account<'info, TokenAccount>,
+    #[account(mut)]
+    pub developer_treasury_pda: Account<'info, TokenAccount>,
+
+    pub token_program: Program<'info, Token>,
+
+    /// Required once vote-escrow locks exist.
+    #[account(mut, seeds = [b"ve_schedule", engine_state.key().as_ref()], bump = ve_schedule.bump)]
+    pub ve_schedule: Option<Box<Account<'info, VeSchedule>>>,
+}
+
+#[derive(Accounts)]
+pub struct GovernanceAction<'info> {
+    #[account(
+        mut,
+        seeds = [b"engine_state_v1"],
+        bump = engine_state.bump,
+        has_one = governance @ ErrorCode::Unauthorized,
+    )]
+    pub engine_state: Account<'info, EngineState>,
+    pub governance: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct GovernanceTreasuryTransfer<'info> {
+    #[account(
+        seeds = [b"engine_state_v1"],
+        bump = engine_state.bump,
+        has_one = governance @ ErrorCode::Unauthorized,
+        has_one = dao_treasury_pda,
+    )]
+    pub engine_state: Account<'info, EngineState>,
+    pub governance: Signer<'info>,
+
+    #[account(
+        mut,
+        constraint = dao_treasury_pda.owner == engine_state.key() @ ErrorCode::InvalidOwner
+    )]
+    pub dao_treasury_pda: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = destination.mint == dao_treasury_pda.mint @ ErrorCode::InvalidMint
+    )]
+    pub destination: Account<'info, TokenAccount>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct RefundQuarantined<'info> {
+    #[account(
+        mut,
+        seeds = [b"engine_state_v1"],
+        bump = engine_state.bump,
+        has_one = governance @ ErrorCode::Unauthorized,
+        has_one = revenue_safe,
+    )]
+    pub engine_state: Account<'info, EngineState>,
+    pub governance: Signer<'info>,
+
+    #[account(
+        mut,
+        constraint = revenue_safe.owner == engine_state.key() @ ErrorCode::InvalidOwner
+    )]
+    pub revenue_safe: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = refund_destination.mint == revenue_safe.mint @ ErrorCode::InvalidMint
+    )]
+    pub refund_destination: Account<'info, TokenAccount>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateAccount<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    /// CHECK: An old layout cannot be deserialized; the handler checks the discriminator.
+    #[account(mut, owner = crate::ID @ ErrorCode::InvalidOwner)]
+    pub account: UncheckedAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+}
 
 #[error_code]
 pub enum ErrorCode {
@@ -255,4 +1181,197 @@ pub enum ErrorCode {
     InvalidRatioSum,
     #[msg("Account owner is invalid")]
     InvalidOwner,
-}
\ No newline at end of file
+    #[msg("Amount exceeds quarantined revenue")]
+    QuarantineExceeded,
+    #[msg("Moving-average cap must be 0 (disabled) or at least 10000 bps")]
+    InvalidCircuitBreaker,
+    #[msg("Token account mint does not match")]
+    InvalidMint,
+    #[msg("Reward debt exceeds accrued rewards; accounting is inconsistent")]
+    AccountingMismatch,
+    #[msg("Reward pool balance does not cover outstanding staker liabilities")]
+    SolvencyCheckFailed,
+    #[msg("Reward pool holds more than its liabilities plus the allowed surplus")]
+    UnexpectedSurplus,
+    #[msg("Ratio schedule is too long or its timestamps are not strictly increasing")]
+    InvalidRatioSchedule,
+    #[msg("Engine is paused")]
+    EnginePaused,
+    #[msg("Engine must be paused first")]
+    EngineNotPaused,
+    #[msg("Close grace period has not elapsed")]
+    GracePeriodActive,
+    #[msg("Stakes, deposits or ve locks are still outstanding")]
+    StakesOutstanding,
+    #[msg("Reward Pool must be swept before closing the engine")]
+    RewardPoolNotEmpty,
+    #[msg("Nothing is staked to receive redistributed rewards")]
+    NothingStaked,
+    #[msg("Minimum stake age cannot be negative")]
+    InvalidMinStakeAge,
+    #[msg("Vote-escrow locks exist; pass the ve schedule account")]
+    VeScheduleRequired,
+    #[msg("Ve lock weight must be positive and unlock on a week boundary within the maximum lock")]
+    InvalidVeLock,
+    #[msg("Governance has been handed over; ratios and the governance role change through it")]
+    RatiosGoverned,
+    #[msg("Locks of an earlier week still hold this unlock week's slot; pick another week")]
+    VeUnlockWeekInUse,
+    #[msg("Account is not an EngineState")]
+    AccountNotMigratable,
+    #[msg("IOUs or ve rewards are still owed from the reward pool")]
+    RewardsOutstanding,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Bytes a baseline `EngineState` held after its discriminator, up to and including `bump`.
+    const BASELINE_FIELDS_LEN: usize = 5 * 32 + 3 * 2 + 8 + 16 + 8 + 1;
+
+    fn engine_state() -> EngineState {
+        EngineState::deserialize(&mut &[0u8; EngineState::MAX_SIZE][..]).unwrap()
+    }
+
+    #[test]
+    fn migrated_engine_keeps_authority_control_of_ratios() {
+        let mut baseline = engine_state();
+        baseline.authority = Pubkey::new_unique();
+        baseline.staking_ratio_bps = 5000;
+        baseline.dao_ratio_bps = 3000;
+        baseline.developer_ratio_bps = 2000;
+        baseline.bump = 254;
+        let mut data = Vec::new();
+        baseline.try_serialize(&mut data).unwrap();
+        // What `realloc(space, true)` leaves behind: the baseline bytes, zero-extended.
+        data.truncate(8 + BASELINE_FIELDS_LEN);
+        data.resize(8 + EngineState::MAX_SIZE, 0);
+
+        let mut migrated = EngineState::try_deserialize(&mut &data[..]).unwrap();
+        assert_eq!(migrated.governance, Pubkey::default());
+        assert_eq!(require_ungoverned(&migrated).unwrap_err(), ErrorCode::RatiosGoverned.into());
+
+        migrate_engine_state(&mut migrated);
+        assert_eq!(migrated.governance, baseline.authority);
+        assert_eq!(migrated.bump, 254);
+        require_ungoverned(&migrated).unwrap();
+        set_ratios(&mut migrated, 6000, 2000, 2000).unwrap();
+        assert_eq!(migrated.staking_ratio_bps, 6000);
+        assert_eq!(
+            set_ratios(&mut migrated, 6000, 3000, 2000).unwrap_err(),
+            ErrorCode::InvalidRatioSum.into()
+        );
+
+        // Migrating again leaves a handed-over governance alone.
+        migrated.governance = Pubkey::new_unique();
+        let governance = migrated.governance;
+        migrate_engine_state(&mut migrated);
+        assert_eq!(migrated.governance, governance);
+    }
+
+    /// 200 HAiO accrued to stakers and not yet claimed.
+    /// A full ring of distributions at 100, 200, ... raising the rate by 1 each time, after
+    /// `extra` more distributions have pushed the oldest ones out.
+    fn engine_with_full_ring(extra: usize) -> EngineState {
+        let mut state = engine_state();
+        for i in 1..=MAX_DISTRIBUTION_CHECKPOINTS + extra {
+            state.reward_per_token_cumulative = i as u128;
+            state.push_checkpoint(100 * i as i64);
+        }
+        state
+    }
+
+    #[test]
+    fn rate_before_uses_the_newest_evicted_checkpoint() {
+        let state = engine_with_full_ring(2);
+        // Distributions 1 and 2 (at 100 and 200) were evicted; 3 at 300 is the oldest kept.
+        assert_eq!(state.rate_before(350), Some(3));
+        assert_eq!(state.rate_before(300), Some(2));
+        assert_eq!(state.rate_before(250), Some(2));
+    }
+
+    #[test]
+    fn rate_before_a_window_older_than_the_ring_forfeits_nothing_evicted() {
+        let state = engine_with_full_ring(2);
+        // The window started among evicted distributions, so the true rate is unknown; the
+        // oldest kept rate (3) would forfeit distribution 2, which the position was owed.
+        assert_eq!(state.rate_before(150), None);
+        // Before anything is evicted, the rate before the first distribution is 0.
+        assert_eq!(engine_with_full_ring(0).rate_before(100), Some(0));
+    }
+
+    fn engine_with_accruals() -> EngineState {
+        let mut state = engine_state();
+        state.reward_per_token_cumulative = 2 * PRECISION;
+        state.total_staked_amount = 100;
+        state
+    }
+
+    #[test]
+    fn solvency_counts_leftover_claims() {
+        let mut state = engine_with_accruals();
+        // An unstake that left 50 unpaid carries it to a leftover claim; the IOU stays counted.
+        state.total_reward_iou = 50;
+        assert_eq!(state.reward_liabilities(), Some(250));
+        assert_eq!(solvency_report(&state, 250).unwrap().liabilities, 250);
+        assert_eq!(solvency_report(&state, 249).unwrap_err(), ErrorCode::SolvencyCheckFailed.into());
+    }
+
+    #[test]
+    fn solvency_allows_forfeits_and_the_tolerance() {
+        let mut state = engine_with_accruals();
+        state.total_forfeited = 30;
+        assert!(solvency_report(&state, 230).is_ok());
+        assert_eq!(solvency_report(&state, 231).unwrap_err(), ErrorCode::UnexpectedSurplus.into());
+        state.max_solvency_surplus = 5;
+        assert!(solvency_report(&state, 235).is_ok());
+        assert_eq!(solvency_report(&state, 236).unwrap_err(), ErrorCode::UnexpectedSurplus.into());
+        // Forfeits spent on other IOUs leave less than their total behind.
+        assert!(solvency_report(&state, 210).is_ok());
+    }
+
+    #[test]
+    fn breaker_disabled_distributes_everything() {
+        let mut state = engine_state();
+        assert_eq!(quarantine_excess(&mut state, 1_000_000).unwrap(), 1_000_000);
+        assert_eq!(state.quarantined_amount, 0);
+    }
+
+    #[test]
+    fn breaker_quarantines_above_absolute_cap() {
+        let mut state = engine_state();
+        state.max_distribution_amount = 100;
+        assert_eq!(quarantine_excess(&mut state, 250).unwrap(), 100);
+        assert_eq!(state.quarantined_amount, 150);
+        assert_eq!(quarantine_excess(&mut state, 130).unwrap(), 100);
+        assert_eq!(state.quarantined_amount, 180);
+        assert_eq!(quarantine_excess(&mut state, 60).unwrap(), 60);
+        assert_eq!(state.quarantined_amount, 180);
+    }
+
+    #[test]
+    fn breaker_takes_the_lower_of_both_caps() {
+        let mut state = engine_state();
+        state.max_distribution_multiple_bps = 20_000;
+        state.distribution_moving_average = 1_000;
+        assert_eq!(current_distribution_cap(&state).unwrap(), 2_000);
+        state.max_distribution_amount = 1_500;
+        assert_eq!(current_distribution_cap(&state).unwrap(), 1_500);
+        state.max_distribution_amount = 5_000;
+        assert_eq!(quarantine_excess(&mut state, 3_000).unwrap(), 2_000);
+        assert_eq!(state.quarantined_amount, 1_000);
+    }
+
+    #[test]
+    fn moving_average_cap_ignores_the_first_distribution() {
+        let mut state = engine_state();
+        state.max_distribution_multiple_bps = 20_000;
+        assert_eq!(quarantine_excess(&mut state, 9_000).unwrap(), 9_000);
+        state.distribution_moving_average = next_moving_average(0, 9_000).unwrap();
+        assert_eq!(state.distribution_moving_average, 9_000);
+        state.distribution_moving_average = next_moving_average(9_000, 1_000).unwrap();
+        assert_eq!(state.distribution_moving_average, 8_000);
+        assert_eq!(current_distribution_cap(&state).unwrap(), 16_000);
+    }
+}
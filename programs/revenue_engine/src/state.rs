@@ -1,5 +1,86 @@
 use anchor_lang::prelude::*;
 
+pub const PRECISION: u128 = 1_000_000_000_000; // 10^12
+
+pub const MAX_RATIO_SCHEDULE_ENTRIES: usize = 8;
+pub const MAX_DISTRIBUTION_CHECKPOINTS: usize = 16;
+
+/// Vote-escrow locks unlock on week boundaries.
+pub const VE_WEEK_SECS: i64 = 7 * 24 * 60 * 60;
+pub const MAX_VE_LOCK_WEEKS: i64 = 52;
+pub const MAX_VE_LOCK_SECS: i64 = MAX_VE_LOCK_WEEKS * VE_WEEK_SECS;
+/// Weeks kept in the schedule ring: the lock horizon, plus as long again so a week whose
+/// locks have not all settled rarely blocks a new lock from using its slot.
+pub const VE_WEEK_RING: usize = 2 * MAX_VE_LOCK_WEEKS as usize;
+/// Scale of ve slopes and bias, so slopes of small locks do not round to zero.
+pub const VE_PRECISION: u128 = 1_000_000_000_000;
+
+/// Minimum time an engine must stay paused before it can be closed.
+pub const CLOSE_GRACE_PERIOD_SECS: i64 = 7 * 24 * 60 * 60;
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default)]
+pub struct RatioScheduleEntry {
+    pub effective_timestamp: i64,
+    pub staking_ratio_bps: u16,
+    pub dao_ratio_bps: u16,
+    pub developer_ratio_bps: u16,
+}
+
+impl RatioScheduleEntry {
+    pub const SIZE: usize = 8 // effective_timestamp
+        + 2  // staking_ratio_bps
+        + 2  // dao_ratio_bps
+        + 2; // developer_ratio_bps
+}
+
+/// Return data of `check_solvency`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default)]
+pub struct SolvencyReport {
+    pub pool_balance: u64,
+    pub liabilities: u128,
+}
+
+/// Reward rate right after a distribution, used to tell which distributions a position
+/// was old enough to share in.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default)]
+pub struct DistributionCheckpoint {
+    pub timestamp: i64,
+    pub reward_per_token_cumulative: u128,
+}
+
+impl DistributionCheckpoint {
+    pub const SIZE: usize = 8 // timestamp
+        + 16; // reward_per_token_cumulative
+}
+
+/// One unlock week of the vote-escrow schedule. Before the boundary passes it holds the
+/// slope that expires there; afterwards also the ve reward rates at that moment. The slot
+/// is not reused while locks unlocking that week still need those rates.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default)]
+pub struct VeWeek {
+    pub week_start: i64,
+    pub slope_change: u128,
+    pub reward_per_weight_cumulative: u128,
+    pub reward_time_cumulative: u128,
+    pub open_locks: u32,
+}
+
+impl VeWeek {
+    pub const SIZE: usize = 8 // week_start
+        + 16  // slope_change
+        + 16  // reward_per_weight_cumulative
+        + 16  // reward_time_cumulative
+        + 4;  // open_locks (locks unlocking this week not yet settled past it)
+}
+
+pub fn ve_week_start(timestamp: i64) -> i64 {
+    timestamp - timestamp.rem_euclid(VE_WEEK_SECS)
+}
+
+fn ve_week_slot(week_start: i64) -> usize {
+    (week_start / VE_WEEK_SECS).rem_euclid(VE_WEEK_RING as i64) as usize
+}
+
 #[account]
 pub struct EngineState {
     pub authority: Pubkey, 
@@ -14,6 +95,37 @@ pub struct EngineState {
     pub reward_per_token_cumulative: u128, 
     pub last_distribution_timestamp: i64, 
     pub bump: u8,            
+    pub governance: Pubkey,
+    pub max_distribution_amount: u64,
+    pub max_distribution_multiple_bps: u32,
+    pub distribution_moving_average: u64,
+    pub quarantined_amount: u64,
+    pub total_distributed_staking: u64,
+    pub total_distributed_dao: u64,
+    pub total_distributed_developer: u64,
+    pub total_claimed: u64,
+    pub total_reward_debt: u128,
+    pub staked_update_count: u64,
+    pub ratio_schedule_len: u8,
+    pub ratio_schedule: [RatioScheduleEntry; MAX_RATIO_SCHEDULE_ENTRIES],
+    pub paused: bool,
+    pub paused_at: i64,
+    pub total_reward_iou: u64,
+    pub min_stake_age_secs: i64,
+    pub checkpoint_head: u8,
+    pub checkpoint_len: u8,
+    pub checkpoints: [DistributionCheckpoint; MAX_DISTRIBUTION_CHECKPOINTS],
+    pub ve_bias: u128,
+    pub ve_slope: u128,
+    pub ve_last_timestamp: i64,
+    pub ve_reward_per_weight_cumulative: u128,
+    pub ve_reward_time_cumulative: u128,
+    pub ve_rewards_outstanding: u64,
+    pub total_forfeited: u64,
+    pub max_solvency_surplus: u64,
+    pub open_deposits: u64,
+    /// Newest checkpoint the full ring has overwritten.
+    pub evicted_checkpoint: DistributionCheckpoint,
 }
 
 impl EngineState {
@@ -29,5 +141,207 @@ impl EngineState {
         + 8  // total_staked_amount
         + 16 // reward_per_token_cumulative (u128)
         + 8  // last_distribution_timestamp
-        + 1; // bump
+        + 1  // bump
+        + 32 // governance
+        + 8  // max_distribution_amount (0 = no absolute cap)
+        + 4  // max_distribution_multiple_bps (0 = no moving-average cap)
+        + 8  // distribution_moving_average
+        + 8  // quarantined_amount
+        + 8  // total_distributed_staking
+        + 8  // total_distributed_dao
+        + 8  // total_distributed_developer
+        + 8  // total_claimed
+        + 16 // total_reward_debt (u128, sum of reward_debt * staked_amount)
+        + 8  // staked_update_count
+        + 1  // ratio_schedule_len
+        + RatioScheduleEntry::SIZE * MAX_RATIO_SCHEDULE_ENTRIES // ratio_schedule
+        + 1  // paused
+        + 8  // paused_at
+        + 8  // total_reward_iou (earned rewards the pool could not pay yet)
+        + 8  // min_stake_age_secs (0 = no eligibility delay)
+        + 1  // checkpoint_head (next slot to overwrite)
+        + 1  // checkpoint_len
+        + DistributionCheckpoint::SIZE * MAX_DISTRIBUTION_CHECKPOINTS // checkpoints
+        + 16 // ve_bias (u128, total ve weight at ve_last_timestamp, scaled by VE_PRECISION)
+        + 16 // ve_slope (u128, ve weight lost per second, scaled by VE_PRECISION)
+        + 8  // ve_last_timestamp
+        + 16 // ve_reward_per_weight_cumulative (u128)
+        + 16 // ve_reward_time_cumulative (u128, sum of rate increase * distribution time)
+        + 8  // ve_rewards_outstanding (distributed to ve locks, not yet claimed)
+        + 8  // total_forfeited (settled rewards skipped by the stake-age rule, left in the pool)
+        + 8  // max_solvency_surplus (unexplained pool surplus check_solvency tolerates)
+        + 8  // open_deposits (token stakes holding a deposit plus ve locks not yet withdrawn)
+        + DistributionCheckpoint::SIZE; // evicted_checkpoint (newest checkpoint dropped from the ring)
+}
+
+impl EngineState {
+    /// Records the current reward rate, overwriting the oldest checkpoint once full.
+    pub fn push_checkpoint(&mut self, timestamp: i64) {
+        if self.checkpoint_len as usize == MAX_DISTRIBUTION_CHECKPOINTS {
+            self.evicted_checkpoint = self.checkpoints[self.checkpoint_head as usize];
+        }
+        self.checkpoints[self.checkpoint_head as usize] = DistributionCheckpoint {
+            timestamp,
+            reward_per_token_cumulative: self.reward_per_token_cumulative,
+        };
+        self.checkpoint_head = ((self.checkpoint_head as usize + 1) % MAX_DISTRIBUTION_CHECKPOINTS) as u8;
+        if (self.checkpoint_len as usize) < MAX_DISTRIBUTION_CHECKPOINTS {
+            self.checkpoint_len += 1;
+        }
+    }
+
+    /// Reward rate after the last recorded distribution strictly before `timestamp`. Once the
+    /// full ring holds nothing before `timestamp`, the newest evicted checkpoint answers if it
+    /// is before `timestamp`; otherwise the rate is unknown and `None` lets the position keep
+    /// the evicted distributions rather than forfeit ones it was eligible for.
+    pub fn rate_before(&self, timestamp: i64) -> Option<u128> {
+        let recorded = &self.checkpoints[..self.checkpoint_len as usize];
+        let exact = recorded
+            .iter()
+            .filter(|c| c.timestamp < timestamp)
+            .map(|c| c.reward_per_token_cumulative)
+            .max();
+        if exact.is_some() || recorded.len() < MAX_DISTRIBUTION_CHECKPOINTS {
+            return exact;
+        }
+        let evicted = self.evicted_checkpoint;
+        (evicted.timestamp < timestamp).then_some(evicted.reward_per_token_cumulative)
+    }
+
+    /// Rewards the reward pool owes: unclaimed staker accruals, IOUs and undrawn ve rewards.
+    /// IOUs moved to leftover claims stay in `total_reward_iou` until paid. Accruals a
+    /// position will forfeit to the stake-age rule count until it is settled, after which
+    /// they move to `total_forfeited`. `None` if the reward debt exceeds the accrued total.
+    pub fn reward_liabilities(&self) -> Option<u128> {
+        let outstanding_scaled = self
+            .reward_per_token_cumulative
+            .checked_mul(self.total_staked_amount as u128)?
+            .checked_sub(self.total_reward_debt)?;
+        (outstanding_scaled / PRECISION)
+            .checked_add(self.total_reward_iou as u128)?
+            .checked_add(self.ve_rewards_outstanding as u128)
+    }
+
+    /// Current total ve weight, valid after `VeSchedule::checkpoint`.
+    pub fn ve_weight(&self) -> u64 {
+        (self.ve_bias / VE_PRECISION).min(u64::MAX as u128) as u64
+    }
+}
+
+/// Week ring of the vote-escrow model, kept apart from `EngineState` to keep that account small.
+#[account]
+pub struct VeSchedule {
+    pub engine_state_ref: Pubkey,
+    pub bump: u8,
+    pub weeks: [VeWeek; VE_WEEK_RING],
+}
+
+impl VeSchedule {
+    pub const MAX_SIZE: usize = 8  // Discriminator
+        + 32 // engine_state_ref
+        + 1  // bump
+        + VeWeek::SIZE * VE_WEEK_RING; // weeks
+
+    /// Slope of a ve lock of `weight`; a full-length lock decays to zero over `MAX_VE_LOCK_SECS`.
+    pub fn slope_for(weight: u64) -> u128 {
+        weight as u128 * VE_PRECISION / MAX_VE_LOCK_SECS as u128
+    }
+
+    /// Brings the engine's ve bias forward to `now`, expiring the slopes of locks that
+    /// unlocked at each week boundary crossed and recording the ve reward rates there.
+    pub fn checkpoint(&mut self, engine_state: &mut EngineState, now: i64) -> Option<()> {
+        if now <= engine_state.ve_last_timestamp {
+            return Some(());
+        }
+        let mut week = ve_week_start(engine_state.ve_last_timestamp).checked_add(VE_WEEK_SECS)?;
+        while engine_state.ve_slope > 0 && week <= now {
+            let elapsed = (week - engine_state.ve_last_timestamp) as u128;
+            engine_state.ve_bias = engine_state.ve_bias
+                .checked_sub(engine_state.ve_slope.checked_mul(elapsed)?)?;
+            let slot = &mut self.weeks[ve_week_slot(week)];
+            if slot.week_start == week {
+                engine_state.ve_slope = engine_state.ve_slope.checked_sub(slot.slope_change)?;
+                slot.reward_per_weight_cumulative = engine_state.ve_reward_per_weight_cumulative;
+                slot.reward_time_cumulative = engine_state.ve_reward_time_cumulative;
+            }
+            engine_state.ve_last_timestamp = week;
+            week = week.checked_add(VE_WEEK_SECS)?;
+        }
+        let elapsed = (now - engine_state.ve_last_timestamp) as u128;
+        engine_state.ve_bias = engine_state.ve_bias
+            .checked_sub(engine_state.ve_slope.checked_mul(elapsed)?)?;
+        engine_state.ve_last_timestamp = now;
+        Some(())
+    }
+
+    /// Adds a lock whose weight decays to zero at `unlock_timestamp`. Checkpoint to `now` first.
+    pub fn add_slope(
+        &mut self,
+        engine_state: &mut EngineState,
+        slope: u128,
+        now: i64,
+        unlock_timestamp: i64,
+    ) -> Option<()> {
+        let remaining = unlock_timestamp.checked_sub(now)? as u128;
+        engine_state.ve_bias = engine_state.ve_bias.checked_add(slope.checked_mul(remaining)?)?;
+        engine_state.ve_slope = engine_state.ve_slope.checked_add(slope)?;
+        let slot = &mut self.weeks[ve_week_slot(unlock_timestamp)];
+        if slot.week_start != unlock_timestamp {
+            *slot = VeWeek { week_start: unlock_timestamp, ..VeWeek::default() };
+        }
+        slot.slope_change = slot.slope_change.checked_add(slope)?;
+        slot.open_locks = slot.open_locks.checked_add(1)?;
+        Some(())
+    }
+
+    /// Whether a lock may unlock at `unlock_timestamp`: its slot is free unless an older week
+    /// there still has locks waiting for their unlock rates.
+    pub fn unlock_week_available(&self, unlock_timestamp: i64) -> bool {
+        let slot = &self.weeks[ve_week_slot(unlock_timestamp)];
+        slot.week_start == unlock_timestamp || slot.open_locks == 0
+    }
+
+    /// Marks one lock of the week starting at `unlock_timestamp` as settled past its unlock.
+    /// Locks from before the count existed are not counted, hence the saturating math.
+    pub fn release_unlock_week(&mut self, unlock_timestamp: i64) {
+        let slot = &mut self.weeks[ve_week_slot(unlock_timestamp)];
+        if slot.week_start == unlock_timestamp {
+            slot.open_locks = slot.open_locks.saturating_sub(1);
+        }
+    }
+
+    /// Total ve weight at `timestamp` (not before the last checkpoint) without mutating state.
+    pub fn weight_at(&self, engine_state: &EngineState, timestamp: i64) -> Option<u64> {
+        let mut bias = engine_state.ve_bias;
+        let mut slope = engine_state.ve_slope;
+        let mut last = engine_state.ve_last_timestamp;
+        if timestamp > last {
+            let mut week = ve_week_start(last).checked_add(VE_WEEK_SECS)?;
+            while slope > 0 && week <= timestamp {
+                bias = bias.checked_sub(slope.checked_mul((week - last) as u128)?)?;
+                let slot = &self.weeks[ve_week_slot(week)];
+                if slot.week_start == week {
+                    slope = slope.checked_sub(slot.slope_change)?;
+                }
+                last = week;
+                week = week.checked_add(VE_WEEK_SECS)?;
+            }
+            bias = bias.checked_sub(slope.checked_mul((timestamp - last) as u128)?)?;
+        }
+        Some((bias / VE_PRECISION).min(u64::MAX as u128) as u64)
+    }
+
+    /// Ve reward rates (per weight, time-weighted) as of a lock's unlock week. `None` only for
+    /// locks from before the ring counted open locks, whose week may have been reused.
+    pub fn rates_at_unlock(&self, engine_state: &EngineState, unlock_timestamp: i64) -> Option<(u128, u128)> {
+        if engine_state.ve_last_timestamp < unlock_timestamp {
+            return Some((
+                engine_state.ve_reward_per_weight_cumulative,
+                engine_state.ve_reward_time_cumulative,
+            ));
+        }
+        let slot = &self.weeks[ve_week_slot(unlock_timestamp)];
+        (slot.week_start == unlock_timestamp)
+            .then_some((slot.reward_per_weight_cumulative, slot.reward_time_cumulative))
+    }
 }
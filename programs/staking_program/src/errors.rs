@@ -10,4 +10,96 @@ pub enum StakingError {
     NoRewardsToClaim,
     #[msg("Calculation overflow error.")]
     CalculationError,
+    #[msg("A reconciliation pass is already in progress.")]
+    ReconcileInProgress,
+    #[msg("No reconciliation pass is in progress.")]
+    ReconcileNotInProgress,
+    #[msg("Staked totals changed since the reconciliation result was recorded.")]
+    ReconcileStale,
+    #[msg("Reconciliation result does not match the expected totals.")]
+    ReconcileResultMismatch,
+    #[msg("Account passed for reconciliation must be writable.")]
+    AccountNotWritable,
+    #[msg("Engine is not paused past its grace period or still has stakes.")]
+    EngineNotRetired,
+    #[msg("NFT weight must be greater than zero.")]
+    InvalidWeight,
+    #[msg("Lock tier does not exist.")]
+    InvalidLockTier,
+    #[msg("Lock tiers need a positive duration and a boost of at least 10000 bps.")]
+    InvalidLockTiers,
+    #[msg("Position is still locked.")]
+    StillLocked,
+    #[msg("Unbonding cooldown is enabled; use request_unstake and complete_unstake.")]
+    UnbondingRequired,
+    #[msg("Position is not unbonding.")]
+    NotUnbonding,
+    #[msg("Unbonding cooldown has not elapsed.")]
+    UnbondingCooldownActive,
+    #[msg("Unbonding cooldown cannot be negative.")]
+    InvalidCooldown,
+    #[msg("Batch accounts are missing, malformed or exceed the batch limit.")]
+    InvalidBatch,
+    #[msg("Reward destination is not owned by the position's beneficiary.")]
+    InvalidBeneficiary,
+    #[msg("Position has no reward beneficiary set.")]
+    NoBeneficiary,
+    #[msg("Position is tokenized; authorize with its receipt token.")]
+    PositionTokenized,
+    #[msg("Position is not tokenized.")]
+    PositionNotTokenized,
+    #[msg("Signer does not hold the position's receipt token.")]
+    NotReceiptHolder,
+    #[msg("This instruction does not support the position's custody mode.")]
+    WrongCustodyMode,
+    #[msg("Token account does not hold the NFT for this user.")]
+    InvalidNftAccount,
+    #[msg("NFT is not a programmable NFT.")]
+    NotProgrammableNft,
+    #[msg("Authorization rules account does not match the NFT's rule set.")]
+    InvalidAuthorizationRules,
+    #[msg("Asset ID does not match the Merkle tree and leaf nonce.")]
+    InvalidAssetId,
+    #[msg("Asset is not part of the configured Core collection.")]
+    InvalidCollection,
+    #[msg("Fungible HAiO staking is disabled.")]
+    TokenStakingDisabled,
+    #[msg("Amount must be greater than zero.")]
+    InvalidAmount,
+    #[msg("Amount exceeds the deposited stake.")]
+    InsufficientStake,
+    #[msg("Vesting duration or early-exit penalty is out of range.")]
+    InvalidVestingConfig,
+    #[msg("Vesting is enabled; pass the vesting escrow and vault accounts.")]
+    VestingAccountsRequired,
+    #[msg("Nothing to withdraw from the vesting escrow.")]
+    NothingVested,
+    #[msg("Ve lock must unlock on a future week boundary within the maximum lock.")]
+    InvalidVeLock,
+    #[msg("Wallet already has an active ve lock.")]
+    VeLockActive,
+    #[msg("Referrer must be a registered referrer other than the staker.")]
+    InvalidReferrer,
+    #[msg("Position has a referrer; pass its referral account and the referral budget.")]
+    ReferralAccountsRequired,
+    #[msg("Referral share cannot exceed 10000 bps.")]
+    InvalidReferralConfig,
+    #[msg("Output mint does not match the output token account or is HAiO itself.")]
+    InvalidOutputMint,
+    #[msg("Swap returned less than the minimum output.")]
+    SlippageExceeded,
+    #[msg("Staking with a lock tier requires the staking config account.")]
+    StakingConfigRequired,
+    #[msg("Vesting escrow does not belong to the owner of the reward destination.")]
+    VestingEscrowMismatch,
+    #[msg("Position is ve-locked; release the ve lock first.")]
+    PositionVeLocked,
+    #[msg("Account is not an NftStakeState.")]
+    AccountNotMigratable,
+    #[msg("IOUs or ve rewards are still owed from the reward pool.")]
+    RewardsOutstanding,
+    #[msg("Leftover claim is owed by a different engine.")]
+    LeftoverEngineMismatch,
+    #[msg("Tokenizing a stake requires the NFT, vault and receipt accounts.")]
+    ReceiptAccountsRequired,
 }
@@ -2,23 +2,171 @@ pub mod errors;
 pub mod state;
 
 use crate::errors::StakingError;
-use crate::state::NftStakeState;
+use crate::state::{
+    CompressedLeaf, CustodyMode, LeftoverClaim, LockTier, NftStakeState, NftWeight, ReconcileState,
+    ReferralAccount, StakingConfig, TokenStakeState, UserStakeSummary, VeLock, VestingEscrow,
+    MAX_LOCK_TIERS,
+};
 use anchor_lang::prelude::*;
-use anchor_spl::token::{self, Mint, Token, TokenAccount, Transfer};
+use anchor_lang::system_program;
+use anchor_spl::associated_token::AssociatedToken;
+use mpl_bubblegum::instructions::TransferCpiBuilder;
+use mpl_bubblegum::types::MetadataArgs;
+use mpl_core::accounts::BaseAssetV1;
+use mpl_core::instructions::{
+    AddPluginV1CpiBuilder, RemovePluginV1CpiBuilder, UpdatePluginV1CpiBuilder,
+};
+use mpl_core::types::{FreezeDelegate, Plugin, PluginAuthority, PluginType, UpdateAuthority};
+use anchor_spl::metadata::mpl_token_metadata::{
+    self,
+    instructions::{
+        DelegateStakingV1CpiBuilder, LockV1CpiBuilder, RevokeStakingV1CpiBuilder,
+        UnlockV1CpiBuilder,
+    },
+    types::{ProgrammableConfig, TokenStandard},
+};
+use anchor_spl::metadata::{self, FreezeDelegatedAccount, Metadata, ThawDelegatedAccount};
+use anchor_spl::token::spl_token::instruction::AuthorityType;
+use anchor_spl::token::{
+    self, Approve, Burn, CloseAccount, Mint, MintTo, Revoke, SetAuthority, Token, TokenAccount,
+    Transfer,
+};
+use mock_swap_program::program::MockSwapProgram;
 use revenue_engine::program::RevenueEngine;
-use revenue_engine::state::EngineState;
+use revenue_engine::state::{
+    ve_week_start, EngineState, VeSchedule, CLOSE_GRACE_PERIOD_SECS, MAX_VE_LOCK_SECS,
+};
 
 declare_id!("DNEYpF5jMNjpxAPNYQhPkpuaxWGudBTvyrmKDkNQdZMP");
 
 const PRECISION: u128 = 1_000_000_000_000;
 
+/// Staking weight of an NFT that has no `NftWeight` entry in the registry.
+const DEFAULT_NFT_WEIGHT: u64 = 1;
+
+/// Upper bound on positions handled by one batch instruction.
+const MAX_BATCH_SIZE: usize = 16;
+/// Accounts per NFT in `stake_batch`: mint, stake state, weight entry.
+const STAKE_BATCH_GROUP_SIZE: usize = 3;
+
+/// Metaplex Token Auth Rules program, which enforces pNFT rule sets.
+const TOKEN_AUTH_RULES_PROGRAM_ID: Pubkey = pubkey!("auth9SigNpDKz4sJJ1DfCTuZrZNSAgh9sFD3rboVmgg");
+/// SPL Account Compression and Noop programs used by Bubblegum.
+const SPL_ACCOUNT_COMPRESSION_ID: Pubkey = pubkey!("cmtDvXumGCrqC1Age74AVPhSRVXJMd8PJS91L8KbNCK");
+const SPL_NOOP_ID: Pubkey = pubkey!("noopb9bkMVfRPU8AsbpTUg8AQkHtKwMYZiFUjNRtMmV");
+
 use revenue_engine::ID as REVENUE_ENGINE_PROGRAM_ID;
+use revenue_engine::ENGINE_CALLER_SEED;
+
+/// CPI context for the revenue engine's staking-only instructions, signed by the
+/// `engine_caller` PDA. Must be used inline in the CPI call so the signer seeds outlive it.
+macro_rules! engine_cpi_ctx {
+    ($revenue_engine_program:expr, $engine_state:expr, $engine_caller:expr, $engine_caller_bump:expr $(,)?) => {
+        CpiContext::new_with_signer(
+            $revenue_engine_program.to_account_info(),
+            revenue_engine::cpi::accounts::UpdateTotalStaked {
+                engine_state: $engine_state.to_account_info(),
+                engine_caller: $engine_caller.to_account_info(),
+            },
+            &[&[ENGINE_CALLER_SEED, &[$engine_caller_bump]]],
+        )
+    };
+}
+
+/// `RewardPayout` over a context's reward pool, engine and vesting accounts. Contexts whose
+/// payouts cannot vest pass `None, None` for the vesting accounts; contexts whose staking
+/// config may not exist pass it as `config = Option<&StakingConfig>`.
+macro_rules! reward_payout {
+    ($accounts:expr, $bumps:expr $(,)?) => {
+        reward_payout!(
+            $accounts,
+            $bumps,
+            $accounts.vesting_escrow.as_mut(),
+            $accounts.vesting_vault.as_ref(),
+        )
+    };
+    ($accounts:expr, $bumps:expr, $vesting_escrow:expr, $vesting_vault:expr $(,)?) => {
+        reward_payout!(
+            config = Some(&*$accounts.staking_config),
+            $accounts,
+            $bumps,
+            $vesting_escrow,
+            $vesting_vault,
+        )
+    };
+    (config = $config:expr, $accounts:expr, $bumps:expr $(,)?) => {
+        reward_payout!(
+            config = $config,
+            $accounts,
+            $bumps,
+            $accounts.vesting_escrow.as_mut(),
+            $accounts.vesting_vault.as_ref(),
+        )
+    };
+    (config = $config:expr, $accounts:expr, $bumps:expr, $vesting_escrow:expr, $vesting_vault:expr $(,)?) => {
+        RewardPayout {
+            token_program: &$accounts.token_program,
+            reward_pool_pda: &$accounts.reward_pool_pda,
+            reward_pool_authority: &$accounts.reward_pool_authority,
+            reward_pool_authority_bump: $bumps.reward_pool_authority,
+            revenue_engine_program: &$accounts.revenue_engine_program,
+            engine_state: &$accounts.engine_state,
+            engine_caller: &$accounts.engine_caller,
+            engine_caller_bump: $bumps.engine_caller,
+            vesting_duration_secs: vesting_duration_secs($config),
+            vesting_escrow: $vesting_escrow,
+            vesting_vault: $vesting_vault,
+        }
+    };
+}
+
+/// `ReferralCredit` over a context's staking config and optional referral accounts.
+macro_rules! referral_credit {
+    ($accounts:expr $(,)?) => {
+        referral_credit!(config = Some(&mut *$accounts.staking_config), $accounts)
+    };
+    (config = $config:expr, $accounts:expr $(,)?) => {
+        ReferralCredit {
+            config: $config,
+            referral_account: $accounts.referral_account.as_mut(),
+            referral_budget: $accounts.referral_budget.as_ref(),
+        }
+    };
+}
+
+/// `LeftoverDestination` for the leftover claim of the context's `$owner` signer, owed by
+/// the context's engine unless `$engine_state_ref` names it.
+macro_rules! leftover_destination {
+    ($accounts:expr, $bumps:expr, $owner:ident $(,)?) => {
+        leftover_destination!($accounts, $bumps, $owner, $accounts.engine_state.key())
+    };
+    ($accounts:expr, $bumps:expr, $owner:ident, $engine_state_ref:expr $(,)?) => {
+        LeftoverDestination {
+            claim: $accounts.leftover_claim.to_account_info(),
+            owner: $accounts.$owner.key(),
+            bump: $bumps.leftover_claim,
+            engine_state_ref: $engine_state_ref,
+            payer: $accounts.$owner.to_account_info(),
+            system_program: &$accounts.system_program,
+        }
+    };
+}
 
 #[program]
 pub mod staking_program {
     use super::*;
 
-    pub fn stake(ctx: Context<StakeNft>) -> Result<()> {
+    /// Stakes an NFT. An optional `referrer` (a registered referrer other than the staker,
+    /// passed with its `referral_account`) earns a share of this position's claims.
+    /// With `tokenize`, the NFT moves into the position's vault and a supply-1 receipt is
+    /// minted to the staker; whoever holds the receipt then claims and unstakes through the
+    /// `*_by_receipt` instructions and receives the NFT.
+    pub fn stake(
+        ctx: Context<StakeNft>,
+        lock_tier: Option<u8>,
+        referrer: Option<Pubkey>,
+        tokenize: bool,
+    ) -> Result<()> {
         let stake_state = &mut ctx.accounts.nft_stake_state;
         let engine_state_info = &ctx.accounts.engine_state;
 
@@ -32,29 +180,129 @@ pub mod staking_program {
         let current_engine_state = EngineState::try_deserialize(&mut &engine_state_data[..])?;
         drop(engine_state_data);
 
-        stake_state.user_wallet = ctx.accounts.user_wallet.key();
-        stake_state.nft_mint = ctx.accounts.nft_mint.key();
-        stake_state.staked_amount = 1;
-        stake_state.last_staked_timestamp = Clock::get()?.unix_timestamp;
-        stake_state.is_staked = true;
-        stake_state.reward_debt = current_engine_state.reward_per_token_cumulative;
+        let base_weight = read_nft_weight(&ctx.accounts.nft_weight)?;
+        let tier = resolve_lock_tier(ctx.accounts.staking_config.as_deref(), lock_tier)?;
+        let weight = open_position(
+            stake_state,
+            ctx.accounts.user_wallet.key(),
+            ctx.accounts.nft_mint.key(),
+            engine_state_info.key(),
+            &current_engine_state,
+            base_weight,
+            tier,
+        )?;
         stake_state.bump = ctx.bumps.nft_stake_state;
-        stake_state.engine_state_ref = engine_state_info.key();
+        if let Some(referrer) = referrer {
+            require!(
+                referrer != ctx.accounts.user_wallet.key()
+                    && ctx
+                        .accounts
+                        .referral_account
+                        .as_ref()
+                        .is_some_and(|account| account.referrer == referrer),
+                StakingError::InvalidReferrer
+            );
+            stake_state.referrer = referrer;
+        }
 
-        let cpi_program = ctx.accounts.revenue_engine_program.to_account_info();
-        let cpi_accounts = revenue_engine::cpi::accounts::UpdateTotalStaked {
-            engine_state: ctx.accounts.engine_state.to_account_info(),
-            caller_program: ctx.accounts.staking_program_executable.to_account_info(),
-        };
-        let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
-        revenue_engine::cpi::increase_total_staked(cpi_ctx, 1)?;
+        // A tokenized position belongs to the receipt holder, so the staker's summary does
+        // not count it.
+        let (summary_count, summary_weight) = if tokenize { (0, 0) } else { (1, weight) };
+        if tokenize {
+            let (
+                Some(user_nft_account),
+                Some(nft_vault),
+                Some(receipt_mint),
+                Some(receipt_authority),
+                Some(user_receipt_account),
+            ) = (
+                ctx.accounts.user_nft_account.as_ref(),
+                ctx.accounts.nft_vault.as_ref(),
+                ctx.accounts.receipt_mint.as_ref(),
+                ctx.accounts.receipt_authority.as_ref(),
+                ctx.accounts.user_receipt_account.as_ref(),
+            )
+            else {
+                return err!(StakingError::ReceiptAccountsRequired);
+            };
+            require!(
+                ctx.accounts.nft_mint.decimals == 0 && ctx.accounts.nft_mint.supply == 1,
+                StakingError::InvalidNftAccount
+            );
+            token::transfer(
+                CpiContext::new(
+                    ctx.accounts.token_program.to_account_info(),
+                    Transfer {
+                        from: user_nft_account.to_account_info(),
+                        to: nft_vault.to_account_info(),
+                        authority: ctx.accounts.user_wallet.to_account_info(),
+                    },
+                ),
+                1,
+            )?;
+
+            let Some(authority_bump) = ctx.bumps.receipt_authority else {
+                return err!(StakingError::ReceiptAccountsRequired);
+            };
+            let seeds = &[b"receipt_authority".as_ref(), &[authority_bump][..]];
+            let signer_seeds = &[&seeds[..]];
+            token::mint_to(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    MintTo {
+                        mint: receipt_mint.to_account_info(),
+                        to: user_receipt_account.to_account_info(),
+                        authority: receipt_authority.to_account_info(),
+                    },
+                    signer_seeds,
+                ),
+                1,
+            )?;
+            token::set_authority(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    SetAuthority {
+                        current_authority: receipt_authority.to_account_info(),
+                        account_or_mint: receipt_mint.to_account_info(),
+                    },
+                    signer_seeds,
+                ),
+                AuthorityType::MintTokens,
+                None,
+            )?;
+            stake_state.custody_mode = CustodyMode::VaultEscrow;
+            stake_state.receipt_mint = receipt_mint.key();
+            msg!(
+                "Position {} tokenized with receipt {}",
+                stake_state.nft_mint,
+                stake_state.receipt_mint
+            );
+        }
+        summary_add_stake(
+            &mut ctx.accounts.user_stake_summary,
+            ctx.accounts.user_wallet.key(),
+            ctx.bumps.user_stake_summary,
+            summary_count,
+            summary_weight,
+        )?;
 
-        msg!("NFT staked: {}", stake_state.nft_mint);
+        revenue_engine::cpi::increase_total_staked(
+            engine_cpi_ctx!(
+                &ctx.accounts.revenue_engine_program,
+                &ctx.accounts.engine_state,
+                &ctx.accounts.engine_caller,
+                ctx.bumps.engine_caller,
+            ),
+            weight,
+        )?;
+
+        msg!("NFT staked: {} (weight {})", stake_state.nft_mint, weight);
         Ok(())
     }
 
     pub fn unstake(ctx: Context<UnstakeNft>) -> Result<()> {
-        let stake_state = &ctx.accounts.nft_stake_state;
+        let mut staking_config = read_staking_config(&ctx.accounts.staking_config)?;
+        let stake_state = &mut ctx.accounts.nft_stake_state;
         let engine_state_info = &ctx.accounts.engine_state;
 
         require_keys_eq!(
@@ -74,222 +322,6406 @@ pub mod staking_program {
             ctx.accounts.user_wallet.key(),
             StakingError::Unauthorized
         );
+        require_not_tokenized(stake_state)?;
+        require_custody_mode(stake_state, CustodyMode::Untracked)?;
+        require_unstakable(
+            stake_state,
+            unbonding_cooldown_secs(staking_config.as_ref()),
+            &ctx.accounts.user_haio_account,
+        )?;
 
-        let claimable_reward =
-            calculate_rewards_from_state(&current_engine_state_read, stake_state)?;
-
-        if claimable_reward > 0 {
-            msg!("Claiming {} rewards on unstake", claimable_reward);
-            let authority_bump = ctx.bumps.reward_pool_authority;
-            let seeds = &[
-                b"reward_pool_authority_seed".as_ref(),
-                &[authority_bump][..],
-            ];
-            let signer_seeds = &[&seeds[..]];
-            token::transfer(
-                CpiContext::new_with_signer(
-                    ctx.accounts.token_program.to_account_info(),
-                    Transfer {
-                        from: ctx.accounts.reward_pool_pda.to_account_info(),
-                        to: ctx.accounts.user_haio_account.to_account_info(),
-                        authority: ctx.accounts.reward_pool_authority.to_account_info(),
-                    },
-                    signer_seeds,
-                ),
-                claimable_reward,
-            )?;
+        let paid = finish_unstake(
+            &mut reward_payout!(config = staking_config.as_ref(), ctx.accounts, ctx.bumps),
+            &mut referral_credit!(config = staking_config.as_mut(), ctx.accounts),
+            &current_engine_state_read,
+            stake_state,
+            &ctx.accounts.user_haio_account,
+            &mut leftover_destination!(ctx.accounts, ctx.bumps, user_wallet),
+            ctx.accounts.user_stake_summary.as_deref_mut(),
+        )?;
+        if paid > 0 {
+            msg!("Claimed {} rewards on unstake", paid);
         }
 
-        let cpi_program = ctx.accounts.revenue_engine_program.to_account_info();
-        let cpi_accounts = revenue_engine::cpi::accounts::UpdateTotalStaked {
-            engine_state: ctx.accounts.engine_state.to_account_info(),
-            caller_program: ctx.accounts.staking_program_executable.to_account_info(),
-        };
-        let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
-        revenue_engine::cpi::decrease_total_staked(cpi_ctx, 1)?;
-
         msg!("NFT unstaked: {}", stake_state.nft_mint);
+        write_staking_config(&ctx.accounts.staking_config, staking_config.as_ref())?;
         Ok(())
     }
 
-    pub fn claim_rewards(ctx: Context<ClaimRewards>) -> Result<()> {
-        let stake_state = &mut ctx.accounts.nft_stake_state;
-        let engine_state_info = &ctx.accounts.engine_state;
-
-        require_keys_eq!(
-            *engine_state_info.owner,
-            REVENUE_ENGINE_PROGRAM_ID,
-            StakingError::Unauthorized
-        );
-
-        let engine_state_data = engine_state_info.try_borrow_data()?;
+    /// Non-custodial stake: the NFT stays in the owner's token account, delegated to the
+    /// `stake_delegate` PDA and frozen through Token Metadata's freeze-delegated-account.
+    pub fn stake_in_place(ctx: Context<StakeInPlace>, lock_tier: Option<u8>) -> Result<()> {
+        let engine_state_data = ctx.accounts.engine_state.try_borrow_data()?;
         let current_engine_state = EngineState::try_deserialize(&mut &engine_state_data[..])?;
         drop(engine_state_data);
 
-        require!(stake_state.is_staked, StakingError::NftNotStaked);
-        require_keys_eq!(
-            stake_state.user_wallet,
+        let stake_state = &mut ctx.accounts.nft_stake_state;
+        let base_weight = read_nft_weight(&ctx.accounts.nft_weight)?;
+        let tier = resolve_lock_tier(Some(&ctx.accounts.staking_config), lock_tier)?;
+        let weight = open_position(
+            stake_state,
             ctx.accounts.user_wallet.key(),
-            StakingError::Unauthorized
-        );
-
-        let claimable_reward = calculate_rewards_from_state(&current_engine_state, stake_state)?;
-        require!(claimable_reward > 0, StakingError::NoRewardsToClaim);
+            ctx.accounts.nft_mint.key(),
+            ctx.accounts.engine_state.key(),
+            &current_engine_state,
+            base_weight,
+            tier,
+        )?;
+        stake_state.bump = ctx.bumps.nft_stake_state;
+        stake_state.custody_mode = CustodyMode::FreezeInPlace;
+        summary_add_stake(
+            &mut ctx.accounts.user_stake_summary,
+            ctx.accounts.user_wallet.key(),
+            ctx.bumps.user_stake_summary,
+            1,
+            weight,
+        )?;
 
-        let authority_bump = ctx.bumps.reward_pool_authority;
-        let seeds = &[
-            b"reward_pool_authority_seed".as_ref(),
-            &[authority_bump][..],
-        ];
-        let signer_seeds = &[&seeds[..]];
-        token::transfer(
-            CpiContext::new_with_signer(
+        token::approve(
+            CpiContext::new(
                 ctx.accounts.token_program.to_account_info(),
-                Transfer {
-                    from: ctx.accounts.reward_pool_pda.to_account_info(),
-                    to: ctx.accounts.user_haio_account.to_account_info(),
-                    authority: ctx.accounts.reward_pool_authority.to_account_info(),
+                Approve {
+                    to: ctx.accounts.user_nft_account.to_account_info(),
+                    delegate: ctx.accounts.stake_delegate.to_account_info(),
+                    authority: ctx.accounts.user_wallet.to_account_info(),
                 },
-                signer_seeds,
             ),
-            claimable_reward,
+            1,
+        )?;
+        let delegate_bump = ctx.bumps.stake_delegate;
+        let seeds = &[b"stake_delegate".as_ref(), &[delegate_bump][..]];
+        let signer_seeds = &[&seeds[..]];
+        metadata::freeze_delegated_account(CpiContext::new_with_signer(
+            ctx.accounts.token_metadata_program.to_account_info(),
+            FreezeDelegatedAccount {
+                metadata: ctx.accounts.nft_metadata.to_account_info(),
+                delegate: ctx.accounts.stake_delegate.to_account_info(),
+                token_account: ctx.accounts.user_nft_account.to_account_info(),
+                edition: ctx.accounts.nft_edition.to_account_info(),
+                mint: ctx.accounts.nft_mint.to_account_info(),
+                token_program: ctx.accounts.token_program.to_account_info(),
+            },
+            signer_seeds,
+        ))?;
+
+        revenue_engine::cpi::increase_total_staked(
+            engine_cpi_ctx!(
+                &ctx.accounts.revenue_engine_program,
+                &ctx.accounts.engine_state,
+                &ctx.accounts.engine_caller,
+                ctx.bumps.engine_caller,
+            ),
+            weight,
         )?;
 
-        stake_state.reward_debt = current_engine_state.reward_per_token_cumulative;
         msg!(
-            "Claimed {} HAiO rewards by {}",
-            claimable_reward,
-            stake_state.user_wallet
+            "NFT staked in place: {} (weight {})",
+            ctx.accounts.nft_mint.key(),
+            weight
         );
         Ok(())
     }
-}
 
-fn calculate_rewards_from_state(
-    engine_state: &EngineState,
-    stake_state: &Account<NftStakeState>,
-) -> Result<u64> {
-    let current = engine_state.reward_per_token_cumulative;
-    let last = stake_state.reward_debt;
-    if current <= last {
-        return Ok(0);
+    /// Thaws and revokes a freeze-in-place position and closes it. Works directly on a
+    /// staked position when no unbonding cooldown is set, or after `request_unstake`.
+    pub fn unstake_in_place(ctx: Context<UnstakeInPlace>) -> Result<()> {
+        let mut staking_config = read_staking_config(&ctx.accounts.staking_config)?;
+        let engine_state_data = ctx.accounts.engine_state.try_borrow_data()?;
+        let current_engine_state = EngineState::try_deserialize(&mut &engine_state_data[..])?;
+        drop(engine_state_data);
+
+        let stake_state = &mut ctx.accounts.nft_stake_state;
+        require_custody_mode(stake_state, CustodyMode::FreezeInPlace)?;
+
+        require_unstakable(
+            stake_state,
+            unbonding_cooldown_secs(staking_config.as_ref()),
+            &ctx.accounts.user_haio_account,
+        )?;
+        finish_unstake(
+            &mut reward_payout!(config = staking_config.as_ref(), ctx.accounts, ctx.bumps),
+            &mut referral_credit!(config = staking_config.as_mut(), ctx.accounts),
+            &current_engine_state,
+            stake_state,
+            &ctx.accounts.user_haio_account,
+            &mut leftover_destination!(ctx.accounts, ctx.bumps, user_wallet),
+            ctx.accounts.user_stake_summary.as_deref_mut(),
+        )?;
+
+        let delegate_bump = ctx.bumps.stake_delegate;
+        let seeds = &[b"stake_delegate".as_ref(), &[delegate_bump][..]];
+        let signer_seeds = &[&seeds[..]];
+        metadata::thaw_delegated_account(CpiContext::new_with_signer(
+            ctx.accounts.token_metadata_program.to_account_info(),
+            ThawDelegatedAccount {
+                metadata: ctx.accounts.nft_metadata.to_account_info(),
+                delegate: ctx.accounts.stake_delegate.to_account_info(),
+                token_account: ctx.accounts.user_nft_account.to_account_info(),
+                edition: ctx.accounts.nft_edition.to_account_info(),
+                mint: ctx.accounts.nft_mint.to_account_info(),
+                token_program: ctx.accounts.token_program.to_account_info(),
+            },
+            signer_seeds,
+        ))?;
+        token::revoke(CpiContext::new(
+            ctx.accounts.token_program.to_account_info(),
+            Revoke {
+                source: ctx.accounts.user_nft_account.to_account_info(),
+                authority: ctx.accounts.user_wallet.to_account_info(),
+            },
+        ))?;
+
+        msg!("NFT thawed and unstaked: {}", stake_state.nft_mint);
+        write_staking_config(&ctx.accounts.staking_config, staking_config.as_ref())?;
+        Ok(())
     }
-    let diff = current
-        .checked_sub(last)
-        .ok_or(StakingError::CalculationError)?;
-    let reward = diff
-        .checked_mul(stake_state.staked_amount as u128)
-        .and_then(|r| r.checked_div(PRECISION))
-        .ok_or(StakingError::CalculationError)?;
-    require!(reward <= u64::MAX as u128, StakingError::CalculationError);
-    Ok(reward as u64)
-}
 
-#[derive(Accounts)]
-pub struct StakeNft<'info> {
-    #[account(mut)]
-    pub user_wallet: Signer<'info>,
-    pub nft_mint: Account<'info, Mint>,
+    /// Stakes a programmable NFT without moving it: the `stake_delegate` PDA becomes the
+    /// token's staking delegate and locks it through Token Metadata, so the rule set is honoured.
+    pub fn stake_pnft(ctx: Context<StakePnft>, lock_tier: Option<u8>) -> Result<()> {
+        require_programmable_metadata(
+            &ctx.accounts.nft_metadata,
+            ctx.accounts.authorization_rules.as_ref(),
+        )?;
 
-    /// CHECK: Engine State Account. Marked mut for CPI call. Owner check done in handler.
-    #[account(mut, owner = REVENUE_ENGINE_PROGRAM_ID)]
-    pub engine_state: AccountInfo<'info>,
+        let engine_state_data = ctx.accounts.engine_state.try_borrow_data()?;
+        let current_engine_state = EngineState::try_deserialize(&mut &engine_state_data[..])?;
+        drop(engine_state_data);
 
-    #[account(address = REVENUE_ENGINE_PROGRAM_ID)]
-    pub revenue_engine_program: Program<'info, RevenueEngine>,
+        let stake_state = &mut ctx.accounts.nft_stake_state;
+        let base_weight = read_nft_weight(&ctx.accounts.nft_weight)?;
+        let tier = resolve_lock_tier(Some(&ctx.accounts.staking_config), lock_tier)?;
+        let weight = open_position(
+            stake_state,
+            ctx.accounts.user_wallet.key(),
+            ctx.accounts.nft_mint.key(),
+            ctx.accounts.engine_state.key(),
+            &current_engine_state,
+            base_weight,
+            tier,
+        )?;
+        stake_state.bump = ctx.bumps.nft_stake_state;
+        stake_state.custody_mode = CustodyMode::ProgrammableLock;
+        summary_add_stake(
+            &mut ctx.accounts.user_stake_summary,
+            ctx.accounts.user_wallet.key(),
+            ctx.bumps.user_stake_summary,
+            1,
+            weight,
+        )?;
 
-    #[account(
-        init,
-        payer = user_wallet,
-        space = 8 + NftStakeState::MAX_SIZE,
-        seeds = [b"nft_stake", user_wallet.key().as_ref(), nft_mint.key().as_ref()],
-        bump
-    )]
-    pub nft_stake_state: Account<'info, NftStakeState>,
+        let token_metadata_program = ctx.accounts.token_metadata_program.to_account_info();
+        let user_wallet = ctx.accounts.user_wallet.to_account_info();
+        let user_nft_account = ctx.accounts.user_nft_account.to_account_info();
+        let nft_mint = ctx.accounts.nft_mint.to_account_info();
+        let nft_metadata = ctx.accounts.nft_metadata.to_account_info();
+        let nft_edition = ctx.accounts.nft_edition.to_account_info();
+        let token_record = ctx.accounts.token_record.to_account_info();
+        let stake_delegate = ctx.accounts.stake_delegate.to_account_info();
+        let system_program = ctx.accounts.system_program.to_account_info();
+        let sysvar_instructions = ctx.accounts.sysvar_instructions.to_account_info();
+        let token_program = ctx.accounts.token_program.to_account_info();
+        let auth_rules_program = ctx
+            .accounts
+            .authorization_rules_program
+            .as_ref()
+            .map(|a| a.to_account_info());
+        let auth_rules = ctx
+            .accounts
+            .authorization_rules
+            .as_ref()
+            .map(|a| a.to_account_info());
 
-    /// CHECK: Staking program's executable ID
-    #[account(executable, address = crate::ID)]
-    pub staking_program_executable: AccountInfo<'info>,
+        DelegateStakingV1CpiBuilder::new(&token_metadata_program)
+            .delegate(&stake_delegate)
+            .metadata(&nft_metadata)
+            .master_edition(Some(&nft_edition))
+            .token_record(Some(&token_record))
+            .mint(&nft_mint)
+            .token(&user_nft_account)
+            .authority(&user_wallet)
+            .payer(&user_wallet)
+            .system_program(&system_program)
+            .sysvar_instructions(&sysvar_instructions)
+            .spl_token_program(Some(&token_program))
+            .authorization_rules_program(auth_rules_program.as_ref())
+            .authorization_rules(auth_rules.as_ref())
+            .amount(1)
+            .invoke()?;
 
-    pub system_program: Program<'info, System>,
-    pub rent: Sysvar<'info, Rent>,
-    pub token_program: Program<'info, Token>,
-}
+        let delegate_bump = ctx.bumps.stake_delegate;
+        let seeds = &[b"stake_delegate".as_ref(), &[delegate_bump][..]];
+        LockV1CpiBuilder::new(&token_metadata_program)
+            .authority(&stake_delegate)
+            .token_owner(Some(&user_wallet))
+            .token(&user_nft_account)
+            .mint(&nft_mint)
+            .metadata(&nft_metadata)
+            .edition(Some(&nft_edition))
+            .token_record(Some(&token_record))
+            .payer(&user_wallet)
+            .system_program(&system_program)
+            .sysvar_instructions(&sysvar_instructions)
+            .spl_token_program(Some(&token_program))
+            .authorization_rules_program(auth_rules_program.as_ref())
+            .authorization_rules(auth_rules.as_ref())
+            .invoke_signed(&[&seeds[..]])?;
 
-#[derive(Accounts)]
-pub struct UnstakeNft<'info> {
-    #[account(mut)]
-    pub user_wallet: Signer<'info>,
+        revenue_engine::cpi::increase_total_staked(
+            engine_cpi_ctx!(
+                &ctx.accounts.revenue_engine_program,
+                &ctx.accounts.engine_state,
+                &ctx.accounts.engine_caller,
+                ctx.bumps.engine_caller,
+            ),
+            weight,
+        )?;
 
-    #[account(address = nft_stake_state.nft_mint)]
-    pub nft_mint: Account<'info, Mint>,
+        msg!(
+            "pNFT staked: {} (weight {})",
+            ctx.accounts.nft_mint.key(),
+            weight
+        );
+        Ok(())
+    }
 
-    #[account(mut)]
-    pub user_haio_account: Account<'info, TokenAccount>,
+    /// Unlocks a programmable NFT position, revokes the staking delegate and closes it.
+    /// Same settlement rules as `unstake_in_place`.
+    pub fn unstake_pnft(ctx: Context<UnstakePnft>) -> Result<()> {
+        let mut staking_config = read_staking_config(&ctx.accounts.staking_config)?;
+        require_programmable_metadata(
+            &ctx.accounts.nft_metadata,
+            ctx.accounts.authorization_rules.as_ref(),
+        )?;
 
-    /// CHECK: Engine State Account. Marked mut for CPI call. Owner verification added
-    #[account(mut, owner = REVENUE_ENGINE_PROGRAM_ID, address = nft_stake_state.engine_state_ref)]
-    pub engine_state: AccountInfo<'info>,
+        let engine_state_data = ctx.accounts.engine_state.try_borrow_data()?;
+        let current_engine_state = EngineState::try_deserialize(&mut &engine_state_data[..])?;
+        drop(engine_state_data);
 
-    #[account(address = REVENUE_ENGINE_PROGRAM_ID)]
-    pub revenue_engine_program: Program<'info, RevenueEngine>,
+        let stake_state = &mut ctx.accounts.nft_stake_state;
+        require_custody_mode(stake_state, CustodyMode::ProgrammableLock)?;
 
-    #[account(
-        mut,
-        close = user_wallet,
-        seeds = [b"nft_stake", user_wallet.key().as_ref(), nft_mint.key().as_ref()],
-        bump = nft_stake_state.bump,
-        has_one = user_wallet,
-        has_one = nft_mint,
-    )]
-    pub nft_stake_state: Account<'info, NftStakeState>,
+        require_unstakable(
+            stake_state,
+            unbonding_cooldown_secs(staking_config.as_ref()),
+            &ctx.accounts.user_haio_account,
+        )?;
+        finish_unstake(
+            &mut reward_payout!(config = staking_config.as_ref(), ctx.accounts, ctx.bumps),
+            &mut referral_credit!(config = staking_config.as_mut(), ctx.accounts),
+            &current_engine_state,
+            stake_state,
+            &ctx.accounts.user_haio_account,
+            &mut leftover_destination!(ctx.accounts, ctx.bumps, user_wallet),
+            ctx.accounts.user_stake_summary.as_deref_mut(),
+        )?;
 
-    #[account(address = nft_stake_state.engine_state_ref)]
-    pub engine_state_loader: Account<'info, EngineState>,
+        let token_metadata_program = ctx.accounts.token_metadata_program.to_account_info();
+        let user_wallet = ctx.accounts.user_wallet.to_account_info();
+        let user_nft_account = ctx.accounts.user_nft_account.to_account_info();
+        let nft_mint = ctx.accounts.nft_mint.to_account_info();
+        let nft_metadata = ctx.accounts.nft_metadata.to_account_info();
+        let nft_edition = ctx.accounts.nft_edition.to_account_info();
+        let token_record = ctx.accounts.token_record.to_account_info();
+        let stake_delegate = ctx.accounts.stake_delegate.to_account_info();
+        let system_program = ctx.accounts.system_program.to_account_info();
+        let sysvar_instructions = ctx.accounts.sysvar_instructions.to_account_info();
+        let token_program = ctx.accounts.token_program.to_account_info();
+        let auth_rules_program = ctx
+            .accounts
+            .authorization_rules_program
+            .as_ref()
+            .map(|a| a.to_account_info());
+        let auth_rules = ctx
+            .accounts
+            .authorization_rules
+            .as_ref()
+            .map(|a| a.to_account_info());
 
-    #[account(mut, address = engine_state_loader.reward_pool_pda)]
-    pub reward_pool_pda: Account<'info, TokenAccount>,
+        let delegate_bump = ctx.bumps.stake_delegate;
+        let seeds = &[b"stake_delegate".as_ref(), &[delegate_bump][..]];
+        UnlockV1CpiBuilder::new(&token_metadata_program)
+            .authority(&stake_delegate)
+            .token_owner(Some(&user_wallet))
+            .token(&user_nft_account)
+            .mint(&nft_mint)
+            .metadata(&nft_metadata)
+            .edition(Some(&nft_edition))
+            .token_record(Some(&token_record))
+            .payer(&user_wallet)
+            .system_program(&system_program)
+            .sysvar_instructions(&sysvar_instructions)
+            .spl_token_program(Some(&token_program))
+            .authorization_rules_program(auth_rules_program.as_ref())
+            .authorization_rules(auth_rules.as_ref())
+            .invoke_signed(&[&seeds[..]])?;
 
-    /// CHECK: Reward Pool Authority PDA
-    #[account(seeds = [b"reward_pool_authority_seed"], bump)]
-    pub reward_pool_authority: AccountInfo<'info>,
+        RevokeStakingV1CpiBuilder::new(&token_metadata_program)
+            .delegate(&stake_delegate)
+            .metadata(&nft_metadata)
+            .master_edition(Some(&nft_edition))
+            .token_record(Some(&token_record))
+            .mint(&nft_mint)
+            .token(&user_nft_account)
+            .authority(&user_wallet)
+            .payer(&user_wallet)
+            .system_program(&system_program)
+            .sysvar_instructions(&sysvar_instructions)
+            .spl_token_program(Some(&token_program))
+            .authorization_rules_program(auth_rules_program.as_ref())
+            .authorization_rules(auth_rules.as_ref())
+            .invoke()?;
 
-    /// CHECK: Staking program's executable ID
-    #[account(executable, address = crate::ID)]
-    pub staking_program_executable: AccountInfo<'info>,
+        msg!("pNFT unlocked and unstaked: {}", stake_state.nft_mint);
+        write_staking_config(&ctx.accounts.staking_config, staking_config.as_ref())?;
+        Ok(())
+    }
 
-    pub token_program: Program<'info, Token>,
-}
+    /// Stakes a compressed NFT. Bubblegum verifies the leaf against the Merkle tree while
+    /// transferring it to the `cnft_vault` PDA; the proof path is passed in `remaining_accounts`.
+    /// The position is keyed by asset ID, which is stored in `nft_mint`. `metadata` is the
+    /// leaf's Borsh-serialized `MetadataArgs`; it must match `leaf.data_hash` and carry a
+    /// verified membership of the configured cNFT collection.
+    pub fn stake_cnft<'info>(
+        ctx: Context<'_, '_, 'info, 'info, StakeCnft<'info>>,
+        asset_id: Pubkey,
+        leaf: CompressedLeaf,
+        metadata: Vec<u8>,
+        lock_tier: Option<u8>,
+    ) -> Result<()> {
+        require_keys_eq!(
+            asset_id,
+            mpl_bubblegum::utils::get_asset_id(&ctx.accounts.merkle_tree.key(), leaf.nonce),
+            StakingError::InvalidAssetId
+        );
+        require_cnft_collection(&metadata, &leaf, &ctx.accounts.staking_config)?;
 
-#[derive(Accounts)]
-pub struct ClaimRewards<'info> {
-    #[account(mut)]
-    pub user_wallet: Signer<'info>,
+        let engine_state_data = ctx.accounts.engine_state.try_borrow_data()?;
+        let current_engine_state = EngineState::try_deserialize(&mut &engine_state_data[..])?;
+        drop(engine_state_data);
 
-    #[account(mut)]
-    pub user_haio_account: Account<'info, TokenAccount>,
+        let stake_state = &mut ctx.accounts.nft_stake_state;
+        let base_weight = read_nft_weight(&ctx.accounts.nft_weight)?;
+        let tier = resolve_lock_tier(Some(&ctx.accounts.staking_config), lock_tier)?;
+        let weight = open_position(
+            stake_state,
+            ctx.accounts.user_wallet.key(),
+            asset_id,
+            ctx.accounts.engine_state.key(),
+            &current_engine_state,
+            base_weight,
+            tier,
+        )?;
+        stake_state.bump = ctx.bumps.nft_stake_state;
+        stake_state.custody_mode = CustodyMode::CompressedEscrow;
+        stake_state.collection = ctx.accounts.staking_config.cnft_collection;
+        summary_add_stake(
+            &mut ctx.accounts.user_stake_summary,
+            ctx.accounts.user_wallet.key(),
+            ctx.bumps.user_stake_summary,
+            1,
+            weight,
+        )?;
 
-    /// CHECK: Engine State Account (must be owned by RevenueEngine program)
-    #[account(owner = REVENUE_ENGINE_PROGRAM_ID, address = nft_stake_state.engine_state_ref)]
-    pub engine_state: AccountInfo<'info>,
+        let user_wallet = ctx.accounts.user_wallet.to_account_info();
+        let proof: Vec<(&AccountInfo<'info>, bool, bool)> = ctx
+            .remaining_accounts
+            .iter()
+            .map(|node| (node, false, false))
+            .collect();
+        TransferCpiBuilder::new(&ctx.accounts.bubblegum_program.to_account_info())
+            .tree_config(&ctx.accounts.tree_config.to_account_info())
+            .leaf_owner(&user_wallet, true)
+            .leaf_delegate(&user_wallet, false)
+            .new_leaf_owner(&ctx.accounts.cnft_vault.to_account_info())
+            .merkle_tree(&ctx.accounts.merkle_tree.to_account_info())
+            .log_wrapper(&ctx.accounts.log_wrapper.to_account_info())
+            .compression_program(&ctx.accounts.compression_program.to_account_info())
+            .system_program(&ctx.accounts.system_program.to_account_info())
+            .root(leaf.root)
+            .data_hash(leaf.data_hash)
+            .creator_hash(leaf.creator_hash)
+            .nonce(leaf.nonce)
+            .index(leaf.index)
+            .add_remaining_accounts(&proof)
+            .invoke()?;
 
-    #[account(
-        mut,
-        seeds = [b"nft_stake", user_wallet.key().as_ref(), nft_stake_state.nft_mint.as_ref()],
-        bump = nft_stake_state.bump,
-        has_one = user_wallet
-    )]
-    pub nft_stake_state: Account<'info, NftStakeState>,
+        revenue_engine::cpi::increase_total_staked(
+            engine_cpi_ctx!(
+                &ctx.accounts.revenue_engine_program,
+                &ctx.accounts.engine_state,
+                &ctx.accounts.engine_caller,
+                ctx.bumps.engine_caller,
+            ),
+            weight,
+        )?;
 
-    #[account(address = nft_stake_state.engine_state_ref)]
-    pub engine_state_loader: Account<'info, EngineState>,
+        msg!("cNFT staked: {} (weight {})", asset_id, weight);
+        Ok(())
+    }
 
-    #[account(mut, address = engine_state_loader.reward_pool_pda)]
-    pub reward_pool_pda: Account<'info, TokenAccount>,
+    /// Returns an escrowed compressed NFT to its staker and closes the position. `leaf` must
+    /// describe the leaf as currently owned by `cnft_vault`, with the proof in `remaining_accounts`.
+    pub fn unstake_cnft<'info>(
+        ctx: Context<'_, '_, 'info, 'info, UnstakeCnft<'info>>,
+        leaf: CompressedLeaf,
+    ) -> Result<()> {
+        let mut staking_config = read_staking_config(&ctx.accounts.staking_config)?;
+        let engine_state_data = ctx.accounts.engine_state.try_borrow_data()?;
+        let current_engine_state = EngineState::try_deserialize(&mut &engine_state_data[..])?;
+        drop(engine_state_data);
 
-    /// CHECK: Reward Pool Authority PDA
-    #[account(seeds = [b"reward_pool_authority_seed"], bump)]
-    pub reward_pool_authority: AccountInfo<'info>,
+        let stake_state = &mut ctx.accounts.nft_stake_state;
+        require_custody_mode(stake_state, CustodyMode::CompressedEscrow)?;
+        require_keys_eq!(
+            stake_state.nft_mint,
+            mpl_bubblegum::utils::get_asset_id(&ctx.accounts.merkle_tree.key(), leaf.nonce),
+            StakingError::InvalidAssetId
+        );
 
-    pub token_program: Program<'info, Token>,
+        require_unstakable(
+            stake_state,
+            unbonding_cooldown_secs(staking_config.as_ref()),
+            &ctx.accounts.user_haio_account,
+        )?;
+        finish_unstake(
+            &mut reward_payout!(config = staking_config.as_ref(), ctx.accounts, ctx.bumps),
+            &mut referral_credit!(config = staking_config.as_mut(), ctx.accounts),
+            &current_engine_state,
+            stake_state,
+            &ctx.accounts.user_haio_account,
+            &mut leftover_destination!(ctx.accounts, ctx.bumps, user_wallet),
+            ctx.accounts.user_stake_summary.as_deref_mut(),
+        )?;
+
+        let vault = ctx.accounts.cnft_vault.to_account_info();
+        let vault_bump = ctx.bumps.cnft_vault;
+        let seeds = &[b"cnft_vault".as_ref(), &[vault_bump][..]];
+        let proof: Vec<(&AccountInfo<'info>, bool, bool)> = ctx
+            .remaining_accounts
+            .iter()
+            .map(|node| (node, false, false))
+            .collect();
+        TransferCpiBuilder::new(&ctx.accounts.bubblegum_program.to_account_info())
+            .tree_config(&ctx.accounts.tree_config.to_account_info())
+            .leaf_owner(&vault, true)
+            .leaf_delegate(&vault, false)
+            .new_leaf_owner(&ctx.accounts.user_wallet.to_account_info())
+            .merkle_tree(&ctx.accounts.merkle_tree.to_account_info())
+            .log_wrapper(&ctx.accounts.log_wrapper.to_account_info())
+            .compression_program(&ctx.accounts.compression_program.to_account_info())
+            .system_program(&ctx.accounts.system_program.to_account_info())
+            .root(leaf.root)
+            .data_hash(leaf.data_hash)
+            .creator_hash(leaf.creator_hash)
+            .nonce(leaf.nonce)
+            .index(leaf.index)
+            .add_remaining_accounts(&proof)
+            .invoke_signed(&[&seeds[..]])?;
+
+        msg!("cNFT unstaked: {}", stake_state.nft_mint);
+        write_staking_config(&ctx.accounts.staking_config, staking_config.as_ref())?;
+        Ok(())
+    }
+
+    /// Stakes a Metaplex Core asset from the configured collection by adding a frozen
+    /// `FreezeDelegate` plugin whose authority is the `stake_delegate` PDA.
+    pub fn stake_core(ctx: Context<StakeCore>, lock_tier: Option<u8>) -> Result<()> {
+        require_core_asset(
+            &ctx.accounts.asset,
+            &ctx.accounts.collection,
+            &ctx.accounts.staking_config,
+            &ctx.accounts.user_wallet.key(),
+        )?;
+
+        let engine_state_data = ctx.accounts.engine_state.try_borrow_data()?;
+        let current_engine_state = EngineState::try_deserialize(&mut &engine_state_data[..])?;
+        drop(engine_state_data);
+
+        let stake_state = &mut ctx.accounts.nft_stake_state;
+        let base_weight = read_nft_weight(&ctx.accounts.nft_weight)?;
+        let tier = resolve_lock_tier(Some(&ctx.accounts.staking_config), lock_tier)?;
+        let weight = open_position(
+            stake_state,
+            ctx.accounts.user_wallet.key(),
+            ctx.accounts.asset.key(),
+            ctx.accounts.engine_state.key(),
+            &current_engine_state,
+            base_weight,
+            tier,
+        )?;
+        stake_state.bump = ctx.bumps.nft_stake_state;
+        stake_state.custody_mode = CustodyMode::CoreFreeze;
+        stake_state.collection = ctx.accounts.collection.key();
+        summary_add_stake(
+            &mut ctx.accounts.user_stake_summary,
+            ctx.accounts.user_wallet.key(),
+            ctx.bumps.user_stake_summary,
+            1,
+            weight,
+        )?;
+
+        let user_wallet = ctx.accounts.user_wallet.to_account_info();
+        let collection = ctx.accounts.collection.to_account_info();
+        AddPluginV1CpiBuilder::new(&ctx.accounts.core_program.to_account_info())
+            .asset(&ctx.accounts.asset.to_account_info())
+            .collection(Some(&collection))
+            .payer(&user_wallet)
+            .authority(Some(&user_wallet))
+            .system_program(&ctx.accounts.system_program.to_account_info())
+            .plugin(Plugin::FreezeDelegate(FreezeDelegate { frozen: true }))
+            .init_authority(PluginAuthority::Address {
+                address: ctx.accounts.stake_delegate.key(),
+            })
+            .invoke()?;
+
+        revenue_engine::cpi::increase_total_staked(
+            engine_cpi_ctx!(
+                &ctx.accounts.revenue_engine_program,
+                &ctx.accounts.engine_state,
+                &ctx.accounts.engine_caller,
+                ctx.bumps.engine_caller,
+            ),
+            weight,
+        )?;
+
+        msg!(
+            "Core asset staked: {} (weight {})",
+            ctx.accounts.asset.key(),
+            weight
+        );
+        Ok(())
+    }
+
+    /// Thaws a Core position, removes the freeze plugin and closes it. Same settlement
+    /// rules as `unstake_in_place`.
+    pub fn unstake_core(ctx: Context<UnstakeCore>) -> Result<()> {
+        let mut staking_config = read_staking_config(&ctx.accounts.staking_config)?;
+        let engine_state_data = ctx.accounts.engine_state.try_borrow_data()?;
+        let current_engine_state = EngineState::try_deserialize(&mut &engine_state_data[..])?;
+        drop(engine_state_data);
+
+        let stake_state = &mut ctx.accounts.nft_stake_state;
+        require_custody_mode(stake_state, CustodyMode::CoreFreeze)?;
+
+        require_unstakable(
+            stake_state,
+            unbonding_cooldown_secs(staking_config.as_ref()),
+            &ctx.accounts.user_haio_account,
+        )?;
+        finish_unstake(
+            &mut reward_payout!(config = staking_config.as_ref(), ctx.accounts, ctx.bumps),
+            &mut referral_credit!(config = staking_config.as_mut(), ctx.accounts),
+            &current_engine_state,
+            stake_state,
+            &ctx.accounts.user_haio_account,
+            &mut leftover_destination!(ctx.accounts, ctx.bumps, user_wallet),
+            ctx.accounts.user_stake_summary.as_deref_mut(),
+        )?;
+
+        let core_program = ctx.accounts.core_program.to_account_info();
+        let asset = ctx.accounts.asset.to_account_info();
+        let collection = ctx.accounts.collection.to_account_info();
+        let user_wallet = ctx.accounts.user_wallet.to_account_info();
+        let system_program = ctx.accounts.system_program.to_account_info();
+        let stake_delegate = ctx.accounts.stake_delegate.to_account_info();
+        let delegate_bump = ctx.bumps.stake_delegate;
+        let seeds = &[b"stake_delegate".as_ref(), &[delegate_bump][..]];
+        UpdatePluginV1CpiBuilder::new(&core_program)
+            .asset(&asset)
+            .collection(Some(&collection))
+            .payer(&user_wallet)
+            .authority(Some(&stake_delegate))
+            .system_program(&system_program)
+            .plugin(Plugin::FreezeDelegate(FreezeDelegate { frozen: false }))
+            .invoke_signed(&[&seeds[..]])?;
+        RemovePluginV1CpiBuilder::new(&core_program)
+            .asset(&asset)
+            .collection(Some(&collection))
+            .payer(&user_wallet)
+            .authority(Some(&user_wallet))
+            .system_program(&system_program)
+            .plugin_type(PluginType::FreezeDelegate)
+            .invoke()?;
+
+        msg!("Core asset unstaked: {}", stake_state.nft_mint);
+        write_staking_config(&ctx.accounts.staking_config, staking_config.as_ref())?;
+        Ok(())
+    }
+
+    /// Deposits HAiO into the shared stake vault. Pending rewards are paid out first, then
+    /// the position's weight is recomputed from the new deposit.
+    pub fn stake_tokens(ctx: Context<TokenStake>, amount: u64) -> Result<()> {
+        require!(amount > 0, StakingError::InvalidAmount);
+        require!(
+            ctx.accounts.staking_config.token_stake_unit > 0,
+            StakingError::TokenStakingDisabled
+        );
+
+        let engine_state_data = ctx.accounts.engine_state.try_borrow_data()?;
+        let current_engine_state = EngineState::try_deserialize(&mut &engine_state_data[..])?;
+        drop(engine_state_data);
+
+        let position = &mut ctx.accounts.token_stake_state;
+        if position.user_wallet == Pubkey::default() {
+            position.user_wallet = ctx.accounts.user_wallet.key();
+            position.engine_state_ref = ctx.accounts.engine_state.key();
+            position.reward_debt = current_engine_state.reward_per_token_cumulative;
+            position.bump = ctx.bumps.token_stake_state;
+        }
+
+        settle_token_rewards(ctx.accounts, &ctx.bumps, &current_engine_state)?;
+
+        token::transfer(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.user_haio_account.to_account_info(),
+                    to: ctx.accounts.haio_stake_vault.to_account_info(),
+                    authority: ctx.accounts.user_wallet.to_account_info(),
+                },
+            ),
+            amount,
+        )?;
+
+        if ctx.accounts.token_stake_state.deposited_amount == 0 {
+            revenue_engine::cpi::open_deposit(engine_cpi_ctx!(
+                &ctx.accounts.revenue_engine_program,
+                &ctx.accounts.engine_state,
+                &ctx.accounts.engine_caller,
+                ctx.bumps.engine_caller,
+            ))?;
+        }
+        let position = &mut ctx.accounts.token_stake_state;
+        let now = Clock::get()?.unix_timestamp;
+        position.eligible_from = merged_eligible_from(
+            &current_engine_state,
+            position.eligible_from,
+            position.deposited_amount,
+            amount,
+            now,
+        )?;
+        position.deposited_amount = position
+            .deposited_amount
+            .checked_add(amount)
+            .ok_or(StakingError::CalculationError)?;
+        position.last_staked_timestamp = now;
+        let weight = apply_token_weight(
+            &mut ctx.accounts.token_stake_state,
+            ctx.accounts.staking_config.token_stake_unit,
+            &ctx.accounts.revenue_engine_program,
+            &ctx.accounts.engine_state,
+            &ctx.accounts.engine_caller,
+            ctx.bumps.engine_caller,
+        )?;
+
+        msg!(
+            "Staked {} HAiO (deposit {}, weight {})",
+            amount,
+            ctx.accounts.token_stake_state.deposited_amount,
+            weight
+        );
+        Ok(())
+    }
+
+    /// Withdraws part or all of a fungible HAiO stake, paying pending rewards first.
+    pub fn unstake_tokens(ctx: Context<TokenStake>, amount: u64) -> Result<()> {
+        require!(amount > 0, StakingError::InvalidAmount);
+        require_keys_eq!(
+            ctx.accounts.token_stake_state.user_wallet,
+            ctx.accounts.user_wallet.key(),
+            StakingError::Unauthorized
+        );
+        require!(
+            amount <= ctx.accounts.token_stake_state.deposited_amount,
+            StakingError::InsufficientStake
+        );
+
+        let engine_state_data = ctx.accounts.engine_state.try_borrow_data()?;
+        let current_engine_state = EngineState::try_deserialize(&mut &engine_state_data[..])?;
+        drop(engine_state_data);
+
+        settle_token_rewards(ctx.accounts, &ctx.bumps, &current_engine_state)?;
+
+        ctx.accounts.token_stake_state.deposited_amount -= amount;
+        if ctx.accounts.token_stake_state.deposited_amount == 0 {
+            revenue_engine::cpi::close_deposit(engine_cpi_ctx!(
+                &ctx.accounts.revenue_engine_program,
+                &ctx.accounts.engine_state,
+                &ctx.accounts.engine_caller,
+                ctx.bumps.engine_caller,
+            ))?;
+        }
+        let weight = apply_token_weight(
+            &mut ctx.accounts.token_stake_state,
+            ctx.accounts.staking_config.token_stake_unit,
+            &ctx.accounts.revenue_engine_program,
+            &ctx.accounts.engine_state,
+            &ctx.accounts.engine_caller,
+            ctx.bumps.engine_caller,
+        )?;
+
+        let vault_bump = ctx.bumps.haio_vault_authority;
+        let seeds = &[b"haio_vault_authority".as_ref(), &[vault_bump][..]];
+        let signer_seeds = &[&seeds[..]];
+        token::transfer(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.haio_stake_vault.to_account_info(),
+                    to: ctx.accounts.user_haio_account.to_account_info(),
+                    authority: ctx.accounts.haio_vault_authority.to_account_info(),
+                },
+                signer_seeds,
+            ),
+            amount,
+        )?;
+
+        msg!(
+            "Unstaked {} HAiO (deposit {}, weight {})",
+            amount,
+            ctx.accounts.token_stake_state.deposited_amount,
+            weight
+        );
+        Ok(())
+    }
+
+    /// Pays out pending rewards of a fungible HAiO stake.
+    pub fn claim_token_rewards(ctx: Context<TokenStake>) -> Result<()> {
+        require_keys_eq!(
+            ctx.accounts.token_stake_state.user_wallet,
+            ctx.accounts.user_wallet.key(),
+            StakingError::Unauthorized
+        );
+
+        let engine_state_data = ctx.accounts.engine_state.try_borrow_data()?;
+        let current_engine_state = EngineState::try_deserialize(&mut &engine_state_data[..])?;
+        drop(engine_state_data);
+
+        let claimed = settle_token_rewards(ctx.accounts, &ctx.bumps, &current_engine_state)?;
+        require!(claimed > 0, StakingError::NoRewardsToClaim);
+
+        msg!("Claimed {} HAiO rewards on token stake", claimed);
+        Ok(())
+    }
+
+    /// Claims an NFT position's rewards together with the wallet's fungible stake rewards and
+    /// deposits both straight into the fungible stake, without the HAiO leaving the program.
+    /// Not available with vesting, since the compounded stake could be withdrawn at once.
+    pub fn claim_and_compound(ctx: Context<ClaimAndCompound>) -> Result<()> {
+        require!(
+            ctx.accounts.staking_config.token_stake_unit > 0,
+            StakingError::TokenStakingDisabled
+        );
+        require!(
+            ctx.accounts.staking_config.vesting_duration_secs == 0,
+            StakingError::InvalidVestingConfig
+        );
+
+        let engine_state_data = ctx.accounts.engine_state.try_borrow_data()?;
+        let current_engine_state = EngineState::try_deserialize(&mut &engine_state_data[..])?;
+        drop(engine_state_data);
+        let rate = current_engine_state.reward_per_token_cumulative;
+
+        let stake_state = &mut ctx.accounts.nft_stake_state;
+        require!(stake_state.is_staked, StakingError::NftNotStaked);
+        require_not_tokenized(stake_state)?;
+        require!(
+            stake_state.reward_beneficiary == Pubkey::default(),
+            StakingError::InvalidBeneficiary
+        );
+        let mut settlement = Settlement::new(ctx.accounts.reward_pool_pda.amount);
+        let nft_paid = settle_nft_rewards(&mut settlement, &mut referral_credit!(ctx.accounts), &current_engine_state, stake_state)?;
+
+        let position = &mut ctx.accounts.token_stake_state;
+        if position.user_wallet == Pubkey::default() {
+            position.user_wallet = ctx.accounts.user_wallet.key();
+            position.engine_state_ref = ctx.accounts.engine_state.key();
+            position.reward_debt = rate;
+            position.bump = ctx.bumps.token_stake_state;
+        }
+        settle_token_position(&mut settlement, &current_engine_state, position)?;
+
+        // Whatever the pool cannot cover stays owed on the two positions.
+        let compounded = settlement.paid;
+        require!(compounded > 0, StakingError::NoRewardsToClaim);
+
+        pay_settlement(
+            &mut reward_payout!(ctx.accounts, ctx.bumps, None, None),
+            &ctx.accounts.haio_stake_vault,
+            &settlement,
+        )?;
+        summary_add_claimed(ctx.accounts.user_stake_summary.as_deref_mut(), nft_paid)?;
+        if ctx.accounts.token_stake_state.deposited_amount == 0 {
+            revenue_engine::cpi::open_deposit(engine_cpi_ctx!(
+                &ctx.accounts.revenue_engine_program,
+                &ctx.accounts.engine_state,
+                &ctx.accounts.engine_caller,
+                ctx.bumps.engine_caller,
+            ))?;
+        }
+
+        let position = &mut ctx.accounts.token_stake_state;
+        let now = Clock::get()?.unix_timestamp;
+        position.eligible_from = merged_eligible_from(
+            &current_engine_state,
+            position.eligible_from,
+            position.deposited_amount,
+            compounded,
+            now,
+        )?;
+        position.deposited_amount = position
+            .deposited_amount
+            .checked_add(compounded)
+            .ok_or(StakingError::CalculationError)?;
+        position.last_staked_timestamp = now;
+        let weight = apply_token_weight(
+            position,
+            ctx.accounts.staking_config.token_stake_unit,
+            &ctx.accounts.revenue_engine_program,
+            &ctx.accounts.engine_state,
+            &ctx.accounts.engine_caller,
+            ctx.bumps.engine_caller,
+        )?;
+
+        msg!(
+            "Compounded {} HAiO into token stake (deposit {}, weight {})",
+            compounded,
+            ctx.accounts.token_stake_state.deposited_amount,
+            weight
+        );
+        Ok(())
+    }
+
+    /// Locks HAiO in vote-escrow until `unlock_timestamp` (rounded down to a week boundary,
+    /// at most `MAX_VE_LOCK_SECS` ahead). Longer locks start with more weight; it decays to
+    /// zero at unlock and the deposit cannot leave before then.
+    pub fn create_ve_lock(
+        ctx: Context<VeLockAction>,
+        amount: u64,
+        unlock_timestamp: i64,
+    ) -> Result<()> {
+        require!(amount > 0, StakingError::InvalidAmount);
+        let token_stake_unit = ctx.accounts.staking_config.token_stake_unit;
+        require!(token_stake_unit > 0, StakingError::TokenStakingDisabled);
+        require!(
+            ctx.accounts.ve_lock.deposited_amount == 0,
+            StakingError::VeLockActive
+        );
+
+        let now = Clock::get()?.unix_timestamp;
+        let unlock = ve_week_start(unlock_timestamp);
+        require!(
+            unlock > now && unlock <= now.saturating_add(MAX_VE_LOCK_SECS),
+            StakingError::InvalidVeLock
+        );
+        let weight = amount / token_stake_unit;
+        require!(weight > 0, StakingError::InvalidAmount);
+
+        token::transfer(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.user_haio_account.to_account_info(),
+                    to: ctx.accounts.haio_stake_vault.to_account_info(),
+                    authority: ctx.accounts.user_wallet.to_account_info(),
+                },
+            ),
+            amount,
+        )?;
+
+        let engine_state_data = ctx.accounts.engine_state.try_borrow_data()?;
+        let current_engine_state = EngineState::try_deserialize(&mut &engine_state_data[..])?;
+        drop(engine_state_data);
+
+        let lock = &mut ctx.accounts.ve_lock;
+        lock.user_wallet = ctx.accounts.user_wallet.key();
+        lock.engine_state_ref = ctx.accounts.engine_state.key();
+        lock.deposited_amount = amount;
+        lock.weight = weight;
+        lock.locked_at = now;
+        lock.unlock_timestamp = unlock;
+        lock.reward_per_weight_paid = current_engine_state.ve_reward_per_weight_cumulative;
+        lock.reward_time_paid = current_engine_state.ve_reward_time_cumulative;
+        lock.bump = ctx.bumps.ve_lock;
+        lock.position = Pubkey::default();
+        lock.unlock_settled = false;
+
+        revenue_engine::cpi::add_ve_lock(
+            CpiContext::new_with_signer(
+                ctx.accounts.revenue_engine_program.to_account_info(),
+                revenue_engine::cpi::accounts::UpdateVeLock {
+                    engine_state: ctx.accounts.engine_state.to_account_info(),
+                    ve_schedule: ctx.accounts.ve_schedule.to_account_info(),
+                    engine_caller: ctx.accounts.engine_caller.to_account_info(),
+                },
+                &[&[ENGINE_CALLER_SEED, &[ctx.bumps.engine_caller]]],
+            ),
+            weight,
+            unlock,
+        )?;
+        revenue_engine::cpi::open_deposit(engine_cpi_ctx!(
+            &ctx.accounts.revenue_engine_program,
+            &ctx.accounts.engine_state,
+            &ctx.accounts.engine_caller,
+            ctx.bumps.engine_caller,
+        ))?;
+
+        msg!(
+            "Locked {} HAiO until {} (weight {}, currently {})",
+            amount,
+            unlock,
+            weight,
+            ctx.accounts.ve_lock.weight_at(now)
+        );
+        Ok(())
+    }
+
+    /// Pays out a ve lock's rewards, including any IOU left from a short pool.
+    pub fn claim_ve_rewards(ctx: Context<VeLockAction>) -> Result<()> {
+        require_keys_eq!(
+            ctx.accounts.ve_lock.user_wallet,
+            ctx.accounts.user_wallet.key(),
+            StakingError::Unauthorized
+        );
+
+        let engine_state_data = ctx.accounts.engine_state.try_borrow_data()?;
+        let current_engine_state = EngineState::try_deserialize(&mut &engine_state_data[..])?;
+        drop(engine_state_data);
+
+        let claimed = settle_ve_rewards(
+            &mut reward_payout!(ctx.accounts, ctx.bumps),
+            &ctx.accounts.ve_schedule,
+            &mut ctx.accounts.ve_lock,
+            &ctx.accounts.user_haio_account,
+            &current_engine_state,
+        )?;
+        require!(claimed > 0, StakingError::NoRewardsToClaim);
+
+        msg!(
+            "Claimed {} HAiO ve rewards (unpaid {})",
+            claimed,
+            ctx.accounts.ve_lock.unpaid_rewards
+        );
+        Ok(())
+    }
+
+    /// Returns an unlocked ve deposit after paying its rewards. The lock account is closed
+    /// unless the reward pool was short, in which case it stays open to claim the IOU later.
+    pub fn withdraw_ve_lock(ctx: Context<VeLockAction>) -> Result<()> {
+        require_keys_eq!(
+            ctx.accounts.ve_lock.user_wallet,
+            ctx.accounts.user_wallet.key(),
+            StakingError::Unauthorized
+        );
+        let deposited = ctx.accounts.ve_lock.deposited_amount;
+        require!(deposited > 0, StakingError::InsufficientStake);
+        require!(
+            Clock::get()?.unix_timestamp >= ctx.accounts.ve_lock.unlock_timestamp,
+            StakingError::StillLocked
+        );
+
+        let engine_state_data = ctx.accounts.engine_state.try_borrow_data()?;
+        let current_engine_state = EngineState::try_deserialize(&mut &engine_state_data[..])?;
+        drop(engine_state_data);
+
+        settle_ve_rewards(
+            &mut reward_payout!(ctx.accounts, ctx.bumps),
+            &ctx.accounts.ve_schedule,
+            &mut ctx.accounts.ve_lock,
+            &ctx.accounts.user_haio_account,
+            &current_engine_state,
+        )?;
+
+        let vault_bump = ctx.bumps.haio_vault_authority;
+        let seeds = &[b"haio_vault_authority".as_ref(), &[vault_bump][..]];
+        let signer_seeds = &[&seeds[..]];
+        token::transfer(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.haio_stake_vault.to_account_info(),
+                    to: ctx.accounts.user_haio_account.to_account_info(),
+                    authority: ctx.accounts.haio_vault_authority.to_account_info(),
+                },
+                signer_seeds,
+            ),
+            deposited,
+        )?;
+        revenue_engine::cpi::close_deposit(engine_cpi_ctx!(
+            &ctx.accounts.revenue_engine_program,
+            &ctx.accounts.engine_state,
+            &ctx.accounts.engine_caller,
+            ctx.bumps.engine_caller,
+        ))?;
+
+        let lock = &mut ctx.accounts.ve_lock;
+        lock.deposited_amount = 0;
+        lock.weight = 0;
+        if lock.unpaid_rewards == 0 {
+            lock.close(ctx.accounts.user_wallet.to_account_info())?;
+        }
+
+        msg!("Withdrew {} HAiO from ve lock", deposited);
+        Ok(())
+    }
+
+    /// Moves a staked NFT position into vote-escrow until `unlock_timestamp` (rounded down to
+    /// a week boundary, at most `MAX_VE_LOCK_SECS` ahead). Its weight leaves regular staking
+    /// and decays to zero at unlock; the NFT cannot be unstaked until the lock is released.
+    /// Rewards earned so far stay owed on the position and are claimed as usual.
+    pub fn create_nft_ve_lock(ctx: Context<NftVeLockAction>, unlock_timestamp: i64) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        let unlock = ve_week_start(unlock_timestamp);
+        require!(
+            unlock > now && unlock <= now.saturating_add(MAX_VE_LOCK_SECS),
+            StakingError::InvalidVeLock
+        );
+        require!(ctx.accounts.ve_lock.weight == 0, StakingError::VeLockActive);
+
+        let engine_state_data = ctx.accounts.engine_state.try_borrow_data()?;
+        let current_engine_state = EngineState::try_deserialize(&mut &engine_state_data[..])?;
+        drop(engine_state_data);
+
+        let stake_state = &mut ctx.accounts.nft_stake_state;
+        require!(stake_state.is_staked, StakingError::NftNotStaked);
+        require_not_tokenized(stake_state)?;
+        require_unlocked(stake_state)?;
+        let weight = stake_state.staked_amount;
+        require!(weight > 0, StakingError::InvalidAmount);
+
+        // Settled against an empty pool, everything earned so far becomes the position's IOU.
+        let mut settlement = Settlement::new(0);
+        settle_nft_rewards(
+            &mut settlement,
+            &mut ReferralCredit {
+                config: Some(&mut *ctx.accounts.staking_config),
+                referral_account: None,
+                referral_budget: None,
+            },
+            &current_engine_state,
+            stake_state,
+        )?;
+        let mut payout = reward_payout!(ctx.accounts, ctx.bumps, None, None);
+        pay_rewards(
+            &mut payout,
+            &ctx.accounts.user_haio_account,
+            0,
+            settlement.settled_debt,
+            settlement.forfeited,
+        )?;
+        sync_reward_iou(
+            payout.revenue_engine_program,
+            payout.engine_state,
+            payout.engine_caller,
+            payout.engine_caller_bump,
+            settlement.iou_before,
+            settlement.iou_after,
+        )?;
+        revenue_engine::cpi::decrease_total_staked(
+            engine_cpi_ctx!(
+                &ctx.accounts.revenue_engine_program,
+                &ctx.accounts.engine_state,
+                &ctx.accounts.engine_caller,
+                ctx.bumps.engine_caller,
+            ),
+            weight,
+        )?;
+        revenue_engine::cpi::add_ve_lock(
+            CpiContext::new_with_signer(
+                ctx.accounts.revenue_engine_program.to_account_info(),
+                revenue_engine::cpi::accounts::UpdateVeLock {
+                    engine_state: ctx.accounts.engine_state.to_account_info(),
+                    ve_schedule: ctx.accounts.ve_schedule.to_account_info(),
+                    engine_caller: ctx.accounts.engine_caller.to_account_info(),
+                },
+                &[&[ENGINE_CALLER_SEED, &[ctx.bumps.engine_caller]]],
+            ),
+            weight,
+            unlock,
+        )?;
+        revenue_engine::cpi::open_deposit(engine_cpi_ctx!(
+            &ctx.accounts.revenue_engine_program,
+            &ctx.accounts.engine_state,
+            &ctx.accounts.engine_caller,
+            ctx.bumps.engine_caller,
+        ))?;
+        if let Some(summary) = ctx.accounts.user_stake_summary.as_deref_mut() {
+            summary.total_weight = summary.total_weight.saturating_sub(weight);
+        }
+
+        let stake_state = &mut ctx.accounts.nft_stake_state;
+        stake_state.staked_amount = 0;
+        stake_state.ve_unlock_timestamp = unlock;
+
+        let lock = &mut ctx.accounts.ve_lock;
+        lock.user_wallet = ctx.accounts.user_wallet.key();
+        lock.engine_state_ref = ctx.accounts.engine_state.key();
+        lock.deposited_amount = 0;
+        lock.weight = weight;
+        lock.locked_at = now;
+        lock.unlock_timestamp = unlock;
+        lock.reward_per_weight_paid = current_engine_state.ve_reward_per_weight_cumulative;
+        lock.reward_time_paid = current_engine_state.ve_reward_time_cumulative;
+        lock.bump = ctx.bumps.ve_lock;
+        lock.position = ctx.accounts.nft_stake_state.key();
+        lock.unlock_settled = false;
+
+        msg!(
+            "Locked position {} until {} (weight {}, currently {})",
+            ctx.accounts.nft_stake_state.nft_mint,
+            unlock,
+            weight,
+            ctx.accounts.ve_lock.weight_at(now)
+        );
+        Ok(())
+    }
+
+    /// Pays out an NFT ve lock's rewards, including any IOU left from a short pool.
+    pub fn claim_nft_ve_rewards(ctx: Context<NftVeLockAction>) -> Result<()> {
+        require_keys_eq!(
+            ctx.accounts.ve_lock.position,
+            ctx.accounts.nft_stake_state.key(),
+            StakingError::Unauthorized
+        );
+
+        let engine_state_data = ctx.accounts.engine_state.try_borrow_data()?;
+        let current_engine_state = EngineState::try_deserialize(&mut &engine_state_data[..])?;
+        drop(engine_state_data);
+
+        let claimed = settle_ve_rewards(
+            &mut reward_payout!(ctx.accounts, ctx.bumps),
+            &ctx.accounts.ve_schedule,
+            &mut ctx.accounts.ve_lock,
+            &ctx.accounts.user_haio_account,
+            &current_engine_state,
+        )?;
+        require!(claimed > 0, StakingError::NoRewardsToClaim);
+
+        msg!(
+            "Claimed {} HAiO ve rewards of position {} (unpaid {})",
+            claimed,
+            ctx.accounts.nft_stake_state.nft_mint,
+            ctx.accounts.ve_lock.unpaid_rewards
+        );
+        Ok(())
+    }
+
+    /// Returns an unlocked NFT ve lock's weight to regular staking after paying its rewards
+    /// and closes the lock; what the pool cannot cover moves to the leftover claim. The
+    /// position restarts as a fresh, untiered stake.
+    pub fn release_nft_ve_lock(ctx: Context<ReleaseNftVeLock>) -> Result<()> {
+        let lock = &ctx.accounts.ve_lock;
+        require_keys_eq!(
+            lock.position,
+            ctx.accounts.nft_stake_state.key(),
+            StakingError::Unauthorized
+        );
+        let weight = lock.weight;
+        require!(weight > 0, StakingError::InsufficientStake);
+        let now = Clock::get()?.unix_timestamp;
+        require!(now >= lock.unlock_timestamp, StakingError::StillLocked);
+
+        let engine_state_data = ctx.accounts.engine_state.try_borrow_data()?;
+        let current_engine_state = EngineState::try_deserialize(&mut &engine_state_data[..])?;
+        drop(engine_state_data);
+
+        settle_ve_rewards(
+            &mut reward_payout!(ctx.accounts, ctx.bumps),
+            &ctx.accounts.ve_schedule,
+            &mut ctx.accounts.ve_lock,
+            &ctx.accounts.user_haio_account,
+            &current_engine_state,
+        )?;
+        revenue_engine::cpi::increase_total_staked(
+            engine_cpi_ctx!(
+                &ctx.accounts.revenue_engine_program,
+                &ctx.accounts.engine_state,
+                &ctx.accounts.engine_caller,
+                ctx.bumps.engine_caller,
+            ),
+            weight,
+        )?;
+        revenue_engine::cpi::close_deposit(engine_cpi_ctx!(
+            &ctx.accounts.revenue_engine_program,
+            &ctx.accounts.engine_state,
+            &ctx.accounts.engine_caller,
+            ctx.bumps.engine_caller,
+        ))?;
+        if let Some(summary) = ctx.accounts.user_stake_summary.as_deref_mut() {
+            summary.total_weight = summary
+                .total_weight
+                .checked_add(weight)
+                .ok_or(StakingError::CalculationError)?;
+        }
+
+        // Restarting the stake also keeps the position from voting again on proposals its
+        // ve lock already voted on.
+        let stake_state = &mut ctx.accounts.nft_stake_state;
+        stake_state.staked_amount = weight;
+        stake_state.reward_debt = current_engine_state.reward_per_token_cumulative;
+        stake_state.last_staked_timestamp = now;
+        stake_state.lock_duration = 0;
+        stake_state.eligible_from = eligible_from(&current_engine_state, now);
+        stake_state.ve_unlock_timestamp = 0;
+
+        carry_to_leftover(
+            &mut leftover_destination!(ctx.accounts, ctx.bumps, user_wallet),
+            ctx.accounts.ve_lock.unpaid_rewards,
+        )?;
+        ctx.accounts.ve_lock.unpaid_rewards = 0;
+        ctx.accounts
+            .ve_lock
+            .close(ctx.accounts.user_wallet.to_account_info())?;
+
+        msg!(
+            "Released ve lock of position {} (weight {})",
+            ctx.accounts.nft_stake_state.nft_mint,
+            weight
+        );
+        Ok(())
+    }
+
+    /// Stakes several NFTs with one `increase_total_staked` CPI. `remaining_accounts` holds
+    /// `[nft_mint, nft_stake_state (writable, uninitialized PDA), nft_weight]` per NFT.
+    /// Each position creates an account (~25k CU), so about 6 NFTs fit the default 200k CU
+    /// budget; request more compute units and use a lookup table for up to `MAX_BATCH_SIZE`.
+    pub fn stake_batch<'info>(
+        ctx: Context<'_, '_, 'info, 'info, StakeBatch<'info>>,
+        lock_tier: Option<u8>,
+    ) -> Result<()> {
+        let engine_state_data = ctx.accounts.engine_state.try_borrow_data()?;
+        let current_engine_state = EngineState::try_deserialize(&mut &engine_state_data[..])?;
+        drop(engine_state_data);
+
+        let groups = ctx.remaining_accounts.chunks_exact(STAKE_BATCH_GROUP_SIZE);
+        require!(
+            groups.remainder().is_empty()
+                && groups.len() > 0
+                && groups.len() <= MAX_BATCH_SIZE,
+            StakingError::InvalidBatch
+        );
+
+        let tier = resolve_lock_tier(Some(&ctx.accounts.staking_config), lock_tier)?;
+        let user_key = ctx.accounts.user_wallet.key();
+        let rent = Rent::get()?;
+        let space = 8 + NftStakeState::MAX_SIZE;
+
+        let mut total_weight: u64 = 0;
+        for group in groups {
+            let (mint_info, stake_state_info, nft_weight_info) = (&group[0], &group[1], &group[2]);
+            let nft_mint: Account<'info, Mint> = Account::try_from(mint_info)?;
+
+            let (stake_state_key, stake_state_bump) = Pubkey::find_program_address(
+                &[b"nft_stake", user_key.as_ref(), nft_mint.key().as_ref()],
+                &crate::ID,
+            );
+            require_keys_eq!(stake_state_info.key(), stake_state_key, StakingError::InvalidBatch);
+            let (nft_weight_key, _) =
+                Pubkey::find_program_address(&[b"nft_weight", nft_mint.key().as_ref()], &crate::ID);
+            require_keys_eq!(nft_weight_info.key(), nft_weight_key, StakingError::InvalidBatch);
+
+            let nft_mint_key = nft_mint.key();
+            let signer_seeds: &[&[u8]] = &[
+                b"nft_stake",
+                user_key.as_ref(),
+                nft_mint_key.as_ref(),
+                &[stake_state_bump],
+            ];
+            create_pda_account(
+                &ctx.accounts.system_program,
+                &ctx.accounts.user_wallet.to_account_info(),
+                stake_state_info,
+                &rent,
+                space,
+                signer_seeds,
+            )?;
+
+            let mut stake_state = NftStakeState::default();
+            let weight = open_position(
+                &mut stake_state,
+                user_key,
+                nft_mint_key,
+                ctx.accounts.engine_state.key(),
+                &current_engine_state,
+                read_nft_weight(nft_weight_info)?,
+                tier,
+            )?;
+            stake_state.bump = stake_state_bump;
+            let mut data = stake_state_info.try_borrow_mut_data()?;
+            stake_state.try_serialize(&mut &mut data[..])?;
+
+            total_weight = total_weight
+                .checked_add(weight)
+                .ok_or(StakingError::CalculationError)?;
+        }
+
+        summary_add_stake(
+            &mut ctx.accounts.user_stake_summary,
+            user_key,
+            ctx.bumps.user_stake_summary,
+            (ctx.remaining_accounts.len() / STAKE_BATCH_GROUP_SIZE) as u32,
+            total_weight,
+        )?;
+
+        revenue_engine::cpi::increase_total_staked(
+            engine_cpi_ctx!(
+                &ctx.accounts.revenue_engine_program,
+                &ctx.accounts.engine_state,
+                &ctx.accounts.engine_caller,
+                ctx.bumps.engine_caller,
+            ),
+            total_weight,
+        )?;
+
+        msg!(
+            "Batch staked {} NFTs (total weight {})",
+            ctx.remaining_accounts.len() / STAKE_BATCH_GROUP_SIZE,
+            total_weight
+        );
+        Ok(())
+    }
+
+    /// Unstakes several positions with one reward transfer and one `decrease_total_staked`
+    /// CPI. `remaining_accounts` holds one writable `NftStakeState` per NFT. Roughly 8k CU per
+    /// position; up to `MAX_BATCH_SIZE` positions with a raised compute-unit limit.
+    pub fn unstake_batch<'info>(
+        ctx: Context<'_, '_, 'info, 'info, UnstakeBatch<'info>>,
+    ) -> Result<()> {
+        let mut staking_config = read_staking_config(&ctx.accounts.staking_config)?;
+        let engine_state_data = ctx.accounts.engine_state.try_borrow_data()?;
+        let current_engine_state = EngineState::try_deserialize(&mut &engine_state_data[..])?;
+        drop(engine_state_data);
+
+        require!(
+            unbonding_cooldown_secs(staking_config.as_ref()) == 0,
+            StakingError::UnbondingRequired
+        );
+        require!(
+            !ctx.remaining_accounts.is_empty() && ctx.remaining_accounts.len() <= MAX_BATCH_SIZE,
+            StakingError::InvalidBatch
+        );
+
+        // Pay what the pool can cover; the rest moves to the wallet's leftover claim.
+        let mut settlement = Settlement::new(ctx.accounts.reward_pool_pda.amount);
+        let mut total_weight: u64 = 0;
+        let mut leftover: u64 = 0;
+        for stake_state_info in ctx.remaining_accounts.iter() {
+            let mut stake_state = load_batch_position(
+                stake_state_info,
+                &ctx.accounts.user_wallet.key(),
+                &ctx.accounts.engine_state.key(),
+            )?;
+            require_custody_mode(&stake_state, CustodyMode::Untracked)?;
+            require_unlocked(&stake_state)?;
+            require_reward_destination(&stake_state, &ctx.accounts.user_haio_account)?;
+
+            settle_nft_rewards(&mut settlement, &mut referral_credit!(config = staking_config.as_mut(), ctx.accounts), &current_engine_state, &mut stake_state)?;
+            leftover = leftover
+                .checked_add(stake_state.unpaid_rewards)
+                .ok_or(StakingError::CalculationError)?;
+            total_weight = total_weight
+                .checked_add(stake_state.staked_amount)
+                .ok_or(StakingError::CalculationError)?;
+
+            stake_state.close(ctx.accounts.user_wallet.to_account_info())?;
+        }
+
+        pay_settlement(
+            &mut reward_payout!(config = staking_config.as_ref(), ctx.accounts, ctx.bumps),
+            &ctx.accounts.user_haio_account,
+            &settlement,
+        )?;
+        carry_to_leftover(
+            &mut leftover_destination!(ctx.accounts, ctx.bumps, user_wallet),
+            leftover,
+        )?;
+        revenue_engine::cpi::decrease_total_staked(
+            engine_cpi_ctx!(
+                &ctx.accounts.revenue_engine_program,
+                &ctx.accounts.engine_state,
+                &ctx.accounts.engine_caller,
+                ctx.bumps.engine_caller,
+            ),
+            total_weight,
+        )?;
+        summary_remove_stake(
+            ctx.accounts.user_stake_summary.as_deref_mut(),
+            ctx.remaining_accounts.len() as u32,
+            total_weight,
+            settlement.paid,
+        )?;
+
+        msg!(
+            "Batch unstaked {} NFTs (weight {}, rewards {})",
+            ctx.remaining_accounts.len(),
+            total_weight,
+            settlement.paid
+        );
+        write_staking_config(&ctx.accounts.staking_config, staking_config.as_ref())?;
+        Ok(())
+    }
+
+    /// Claims several positions with one reward transfer. `remaining_accounts` holds one
+    /// writable `NftStakeState` per NFT. Roughly 5k CU per position.
+    pub fn claim_rewards_batch<'info>(
+        ctx: Context<'_, '_, 'info, 'info, BatchPositions<'info>>,
+    ) -> Result<()> {
+        let mut staking_config = read_staking_config(&ctx.accounts.staking_config)?;
+        let engine_state_data = ctx.accounts.engine_state.try_borrow_data()?;
+        let current_engine_state = EngineState::try_deserialize(&mut &engine_state_data[..])?;
+        drop(engine_state_data);
+
+        require!(
+            !ctx.remaining_accounts.is_empty() && ctx.remaining_accounts.len() <= MAX_BATCH_SIZE,
+            StakingError::InvalidBatch
+        );
+
+        let mut settlement = Settlement::new(ctx.accounts.reward_pool_pda.amount);
+        for stake_state_info in ctx.remaining_accounts.iter() {
+            let mut stake_state = load_batch_position(
+                stake_state_info,
+                &ctx.accounts.user_wallet.key(),
+                &ctx.accounts.engine_state.key(),
+            )?;
+            require_reward_destination(&stake_state, &ctx.accounts.user_haio_account)?;
+
+            settle_nft_rewards(&mut settlement, &mut referral_credit!(config = staking_config.as_mut(), ctx.accounts), &current_engine_state, &mut stake_state)?;
+            stake_state.exit(&crate::ID)?;
+        }
+        require!(!settlement.is_empty(), StakingError::NoRewardsToClaim);
+
+        pay_settlement(
+            &mut reward_payout!(config = staking_config.as_ref(), ctx.accounts, ctx.bumps),
+            &ctx.accounts.user_haio_account,
+            &settlement,
+        )?;
+        summary_add_claimed(ctx.accounts.user_stake_summary.as_deref_mut(), settlement.paid)?;
+
+        msg!(
+            "Batch claimed {} HAiO over {} positions by {} ({} still owed)",
+            settlement.paid,
+            ctx.remaining_accounts.len(),
+            ctx.accounts.user_wallet.key(),
+            settlement.iou_after
+        );
+        write_staking_config(&ctx.accounts.staking_config, staking_config.as_ref())?;
+        Ok(())
+    }
+
+    /// Routes this position's rewards to `beneficiary`'s token accounts. Pass the default
+    /// pubkey to send rewards back to the staker's chosen account.
+    pub fn set_reward_beneficiary(
+        ctx: Context<SetRewardBeneficiary>,
+        beneficiary: Pubkey,
+    ) -> Result<()> {
+        let stake_state = &mut ctx.accounts.nft_stake_state;
+        require_not_tokenized(stake_state)?;
+        stake_state.reward_beneficiary = beneficiary;
+        msg!(
+            "Reward beneficiary for {} set to {}",
+            stake_state.nft_mint,
+            beneficiary
+        );
+        Ok(())
+    }
+
+    /// Permissionless harvest that can only pay the position's beneficiary.
+    pub fn crank_claim(ctx: Context<CrankClaim>) -> Result<()> {
+        let mut staking_config = read_staking_config(&ctx.accounts.staking_config)?;
+        let engine_state_data = ctx.accounts.engine_state.try_borrow_data()?;
+        let current_engine_state = EngineState::try_deserialize(&mut &engine_state_data[..])?;
+        drop(engine_state_data);
+
+        let stake_state = &mut ctx.accounts.nft_stake_state;
+        require!(stake_state.is_staked, StakingError::NftNotStaked);
+        require!(
+            stake_state.reward_beneficiary != Pubkey::default(),
+            StakingError::NoBeneficiary
+        );
+
+        let mut settlement = Settlement::new(ctx.accounts.reward_pool_pda.amount);
+        let paid = settle_nft_rewards(&mut settlement, &mut referral_credit!(config = staking_config.as_mut(), ctx.accounts), &current_engine_state, stake_state)?;
+        require!(!settlement.is_empty(), StakingError::NoRewardsToClaim);
+
+        pay_settlement(
+            &mut reward_payout!(config = staking_config.as_ref(), ctx.accounts, ctx.bumps),
+            &ctx.accounts.beneficiary_haio_account,
+            &settlement,
+        )?;
+        summary_add_claimed(ctx.accounts.user_stake_summary.as_deref_mut(), paid)?;
+
+        msg!(
+            "Cranked {} HAiO rewards for {} to beneficiary {} ({} still owed)",
+            paid,
+            stake_state.nft_mint,
+            stake_state.reward_beneficiary,
+            stake_state.unpaid_rewards
+        );
+        write_staking_config(&ctx.accounts.staking_config, staking_config.as_ref())?;
+        Ok(())
+    }
+
+    /// First step of the two-step unbond: pays out accrued rewards, stops accrual and
+    /// removes the position's weight from the engine. `complete_unstake` closes it later.
+    pub fn request_unstake(ctx: Context<RequestUnstake>) -> Result<()> {
+        let mut staking_config = read_staking_config(&ctx.accounts.staking_config)?;
+        let engine_state_data = ctx.accounts.engine_state.try_borrow_data()?;
+        let current_engine_state = EngineState::try_deserialize(&mut &engine_state_data[..])?;
+        drop(engine_state_data);
+
+        let stake_state = &mut ctx.accounts.nft_stake_state;
+        require!(stake_state.is_staked, StakingError::NftNotStaked);
+        require_not_tokenized(stake_state)?;
+        require_unlocked(stake_state)?;
+        require_reward_destination(stake_state, &ctx.accounts.user_haio_account)?;
+
+        // An IOU the pool cannot cover moves to the leftover claim, so the position leaves
+        // unbonding with nothing owed.
+        let paid = finish_unstake(
+            &mut reward_payout!(config = staking_config.as_ref(), ctx.accounts, ctx.bumps),
+            &mut referral_credit!(config = staking_config.as_mut(), ctx.accounts),
+            &current_engine_state,
+            stake_state,
+            &ctx.accounts.user_haio_account,
+            &mut leftover_destination!(ctx.accounts, ctx.bumps, user_wallet),
+            ctx.accounts.user_stake_summary.as_deref_mut(),
+        )?;
+
+        let now = Clock::get()?.unix_timestamp;
+        stake_state.is_staked = false;
+        stake_state.unbonding_ends_at = now
+            .checked_add(unbonding_cooldown_secs(staking_config.as_ref()))
+            .ok_or(StakingError::CalculationError)?;
+
+        msg!(
+            "Unbonding {} (paid {} rewards), withdrawable at {}",
+            stake_state.nft_mint,
+            paid,
+            stake_state.unbonding_ends_at
+        );
+        write_staking_config(&ctx.accounts.staking_config, staking_config.as_ref())?;
+        Ok(())
+    }
+
+    /// Closes an unbonded position. A position unstaked by its receipt holder releases its
+    /// NFT from the vault to that holder; the rent goes back to the staker who paid it.
+    pub fn complete_unstake(ctx: Context<CompleteUnstake>) -> Result<()> {
+        let stake_state = &ctx.accounts.nft_stake_state;
+        require!(
+            !stake_state.is_staked && stake_state.unbonding_ends_at > 0,
+            StakingError::NotUnbonding
+        );
+        require!(
+            Clock::get()?.unix_timestamp >= stake_state.unbonding_ends_at,
+            StakingError::UnbondingCooldownActive
+        );
+        if stake_state.custody_mode == CustodyMode::VaultEscrow {
+            require_keys_eq!(
+                ctx.accounts.recipient.key(),
+                stake_state.unbond_recipient,
+                StakingError::Unauthorized
+            );
+            let (
+                Some(nft_vault),
+                Some(recipient_nft_account),
+                Some(receipt_authority),
+                Some(token_program),
+            ) = (
+                ctx.accounts.nft_vault.as_ref(),
+                ctx.accounts.recipient_nft_account.as_ref(),
+                ctx.accounts.receipt_authority.as_ref(),
+                ctx.accounts.token_program.as_ref(),
+            )
+            else {
+                return err!(StakingError::ReceiptAccountsRequired);
+            };
+            let Some(authority_bump) = ctx.bumps.receipt_authority else {
+                return err!(StakingError::ReceiptAccountsRequired);
+            };
+            release_nft_vault(
+                token_program,
+                nft_vault,
+                recipient_nft_account,
+                receipt_authority,
+                authority_bump,
+                &ctx.accounts.rent_receiver,
+            )?;
+        } else {
+            require_custody_mode(stake_state, CustodyMode::Untracked)?;
+        }
+
+        msg!("NFT unstaked after unbonding: {}", stake_state.nft_mint);
+        Ok(())
+    }
+
+    pub fn claim_rewards_by_receipt(ctx: Context<ClaimRewardsByReceipt>) -> Result<()> {
+        let mut staking_config = read_staking_config(&ctx.accounts.staking_config)?;
+        let engine_state_data = ctx.accounts.engine_state.try_borrow_data()?;
+        let current_engine_state = EngineState::try_deserialize(&mut &engine_state_data[..])?;
+        drop(engine_state_data);
+
+        let stake_state = &mut ctx.accounts.nft_stake_state;
+        require!(stake_state.is_staked, StakingError::NftNotStaked);
+
+        let mut settlement = Settlement::new(ctx.accounts.reward_pool_pda.amount);
+        let paid = settle_nft_rewards(&mut settlement, &mut referral_credit!(config = staking_config.as_mut(), ctx.accounts), &current_engine_state, stake_state)?;
+        require!(!settlement.is_empty(), StakingError::NoRewardsToClaim);
+
+        pay_settlement(
+            &mut reward_payout!(config = staking_config.as_ref(), ctx.accounts, ctx.bumps),
+            &ctx.accounts.holder_haio_account,
+            &settlement,
+        )?;
+
+        msg!(
+            "Claimed {} HAiO rewards for {} by receipt holder {} ({} still owed)",
+            paid,
+            stake_state.nft_mint,
+            ctx.accounts.holder.key(),
+            stake_state.unpaid_rewards
+        );
+        write_staking_config(&ctx.accounts.staking_config, staking_config.as_ref())?;
+        Ok(())
+    }
+
+    /// Burns the receipt and exits the position. Without an unbonding cooldown the NFT goes
+    /// to the holder right away; otherwise the holder collects it with `complete_unstake`.
+    pub fn unstake_by_receipt(ctx: Context<UnstakeByReceipt>) -> Result<()> {
+        let mut staking_config = read_staking_config(&ctx.accounts.staking_config)?;
+        let engine_state_data = ctx.accounts.engine_state.try_borrow_data()?;
+        let current_engine_state = EngineState::try_deserialize(&mut &engine_state_data[..])?;
+        drop(engine_state_data);
+
+        let stake_state = &mut ctx.accounts.nft_stake_state;
+        require!(stake_state.is_staked, StakingError::NftNotStaked);
+        require_custody_mode(stake_state, CustodyMode::VaultEscrow)?;
+        require_unlocked(stake_state)?;
+
+        // What the pool cannot cover moves to the holder's leftover claim. The position was
+        // already taken off the staker's summary when it was tokenized.
+        finish_unstake(
+            &mut reward_payout!(config = staking_config.as_ref(), ctx.accounts, ctx.bumps),
+            &mut referral_credit!(config = staking_config.as_mut(), ctx.accounts),
+            &current_engine_state,
+            stake_state,
+            &ctx.accounts.holder_haio_account,
+            &mut leftover_destination!(ctx.accounts, ctx.bumps, holder),
+            None,
+        )?;
+
+        token::burn(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                Burn {
+                    mint: ctx.accounts.receipt_mint.to_account_info(),
+                    from: ctx.accounts.receipt_token_account.to_account_info(),
+                    authority: ctx.accounts.holder.to_account_info(),
+                },
+            ),
+            1,
+        )?;
+
+        // The staker paid the position's rent and gets it back; the holder gets the NFT.
+        let cooldown = unbonding_cooldown_secs(staking_config.as_ref());
+        let nft_mint = stake_state.nft_mint;
+        if cooldown == 0 {
+            release_nft_vault(
+                &ctx.accounts.token_program,
+                &ctx.accounts.nft_vault,
+                &ctx.accounts.holder_nft_account,
+                &ctx.accounts.receipt_authority,
+                ctx.bumps.receipt_authority,
+                &ctx.accounts.rent_receiver,
+            )?;
+            stake_state.close(ctx.accounts.rent_receiver.to_account_info())?;
+            msg!("NFT unstaked by receipt holder: {}", nft_mint);
+        } else {
+            stake_state.is_staked = false;
+            stake_state.receipt_mint = Pubkey::default();
+            stake_state.unbond_recipient = ctx.accounts.holder.key();
+            stake_state.unbonding_ends_at = Clock::get()?
+                .unix_timestamp
+                .checked_add(cooldown)
+                .ok_or(StakingError::CalculationError)?;
+            msg!(
+                "Unbonding {} for receipt holder {}, withdrawable at {}",
+                nft_mint,
+                ctx.accounts.holder.key(),
+                stake_state.unbonding_ends_at
+            );
+        }
+        write_staking_config(&ctx.accounts.staking_config, staking_config.as_ref())?;
+        Ok(())
+    }
+
+    pub fn claim_rewards(ctx: Context<ClaimRewards>) -> Result<()> {
+        let mut staking_config = read_staking_config(&ctx.accounts.staking_config)?;
+        let settlement = claim_nft_rewards(
+            &mut reward_payout!(config = staking_config.as_ref(), ctx.accounts, ctx.bumps),
+            &mut referral_credit!(config = staking_config.as_mut(), ctx.accounts),
+            &mut ctx.accounts.nft_stake_state,
+            ctx.accounts.user_wallet.key(),
+            &ctx.accounts.user_haio_account,
+            ctx.accounts.user_stake_summary.as_deref_mut(),
+        )?;
+        msg!(
+            "Claimed {} HAiO rewards by {} ({} still owed)",
+            settlement.paid,
+            ctx.accounts.user_wallet.key(),
+            settlement.iou_after
+        );
+        write_staking_config(&ctx.accounts.staking_config, staking_config.as_ref())?;
+        Ok(())
+    }
+
+    /// Claims rewards and swaps them to `output_mint` through the swap program in the same
+    /// instruction. Fails unless the wallet's output account grows by at least `min_out`.
+    /// Not available with vesting or a reward beneficiary, where HAiO must not reach the wallet.
+    pub fn claim_rewards_as(
+        ctx: Context<ClaimRewardsAs>,
+        output_mint: Pubkey,
+        min_out: u64,
+    ) -> Result<()> {
+        let mut staking_config = read_staking_config(&ctx.accounts.staking_config)?;
+        require_keys_eq!(
+            ctx.accounts.output_mint.key(),
+            output_mint,
+            StakingError::InvalidOutputMint
+        );
+        require!(
+            output_mint != ctx.accounts.haio_mint.key(),
+            StakingError::InvalidOutputMint
+        );
+        require!(
+            vesting_duration_secs(staking_config.as_ref()) == 0,
+            StakingError::InvalidVestingConfig
+        );
+        require!(
+            ctx.accounts.nft_stake_state.reward_beneficiary == Pubkey::default(),
+            StakingError::InvalidBeneficiary
+        );
+
+        let settlement = claim_nft_rewards(
+            &mut reward_payout!(config = staking_config.as_ref(), ctx.accounts, ctx.bumps, None, None),
+            &mut referral_credit!(config = staking_config.as_mut(), ctx.accounts),
+            &mut ctx.accounts.nft_stake_state,
+            ctx.accounts.user_wallet.key(),
+            &ctx.accounts.user_haio_account,
+            ctx.accounts.user_stake_summary.as_deref_mut(),
+        )?;
+        let paid = settlement.paid;
+        require!(paid > 0, StakingError::NoRewardsToClaim);
+
+        let output_before = ctx.accounts.user_output_account.amount;
+        mock_swap_program::cpi::swap_haio_for_usdc(
+            CpiContext::new(
+                ctx.accounts.swap_program.to_account_info(),
+                mock_swap_program::cpi::accounts::SwapHaioForUsdc {
+                    user_or_op_wallet: ctx.accounts.user_wallet.to_account_info(),
+                    vault_authority: ctx.accounts.swap_vault_authority.to_account_info(),
+                    user_usdc_account: ctx.accounts.user_output_account.to_account_info(),
+                    user_haio_account: ctx.accounts.user_haio_account.to_account_info(),
+                    usdc_vault: ctx.accounts.swap_output_vault.to_account_info(),
+                    haio_vault: ctx.accounts.swap_haio_vault.to_account_info(),
+                    token_program: ctx.accounts.token_program.to_account_info(),
+                    usdc_mint: ctx.accounts.output_mint.to_account_info(),
+                    haio_mint: ctx.accounts.haio_mint.to_account_info(),
+                },
+            ),
+            paid,
+        )?;
+        ctx.accounts.user_output_account.reload()?;
+        let received = ctx
+            .accounts
+            .user_output_account
+            .amount
+            .checked_sub(output_before)
+            .ok_or(StakingError::CalculationError)?;
+        require!(received >= min_out, StakingError::SlippageExceeded);
+
+        msg!(
+            "Claimed {} HAiO rewards by {} as {} of {} ({} still owed)",
+            paid,
+            ctx.accounts.user_wallet.key(),
+            received,
+            output_mint,
+            settlement.iou_after
+        );
+        write_staking_config(&ctx.accounts.staking_config, staking_config.as_ref())?;
+        Ok(())
+    }
+
+    /// Pays out a leftover claim as far as the reward pool allows; closes it once settled.
+    pub fn claim_leftover(ctx: Context<ClaimLeftover>) -> Result<()> {
+        let staking_config = read_staking_config(&ctx.accounts.staking_config)?;
+        let leftover_claim = &mut ctx.accounts.leftover_claim;
+        let paid = leftover_claim.amount.min(ctx.accounts.reward_pool_pda.amount);
+        require!(paid > 0, StakingError::NoRewardsToClaim);
+
+        pay_rewards(
+            &mut reward_payout!(config = staking_config.as_ref(), ctx.accounts, ctx.bumps),
+            &ctx.accounts.user_haio_account,
+            paid,
+            0,
+            0,
+        )?;
+        sync_reward_iou(
+            &ctx.accounts.revenue_engine_program,
+            &ctx.accounts.engine_state,
+            &ctx.accounts.engine_caller,
+            ctx.bumps.engine_caller,
+            leftover_claim.amount,
+            leftover_claim.amount - paid,
+        )?;
+
+        leftover_claim.amount -= paid;
+        msg!(
+            "Paid {} HAiO of leftover claim ({} still owed)",
+            paid,
+            leftover_claim.amount
+        );
+        if leftover_claim.amount == 0 {
+            leftover_claim.close(ctx.accounts.user_wallet.to_account_info())?;
+        }
+        Ok(())
+    }
+
+    /// Creates the caller's vesting escrow (and the shared vesting vault on first use).
+    pub fn open_vesting_escrow(ctx: Context<OpenVestingEscrow>) -> Result<()> {
+        let escrow = &mut ctx.accounts.vesting_escrow;
+        escrow.user_wallet = ctx.accounts.user_wallet.key();
+        escrow.locked_amount = 0;
+        escrow.released_amount = 0;
+        escrow.unlocked_amount = 0;
+        escrow.start_timestamp = 0;
+        escrow.end_timestamp = 0;
+        escrow.bump = ctx.bumps.vesting_escrow;
+        msg!("Vesting escrow opened for {}", escrow.user_wallet);
+        Ok(())
+    }
+
+    /// Withdraws everything that has vested so far, without penalty.
+    pub fn withdraw_vested(ctx: Context<WithdrawVesting>) -> Result<()> {
+        let escrow = &mut ctx.accounts.vesting_escrow;
+        checkpoint_vesting(escrow, Clock::get()?.unix_timestamp)?;
+        let amount = escrow.unlocked_amount;
+        require!(amount > 0, StakingError::NothingVested);
+        escrow.unlocked_amount = 0;
+
+        vesting_vault_transfer(
+            &ctx.accounts.token_program,
+            &ctx.accounts.vesting_vault,
+            &ctx.accounts.user_haio_account.to_account_info(),
+            &ctx.accounts.vesting_vault_authority,
+            ctx.bumps.vesting_vault_authority,
+            amount,
+        )?;
+        msg!("Withdrew {} vested HAiO", amount);
+        Ok(())
+    }
+
+    /// Withdraws the whole escrow now. The still-unvested part is cut by
+    /// `early_exit_penalty_bps`; the forfeit returns to the reward pool and is shared among
+    /// current stakers through the engine's reward rate.
+    pub fn exit_vesting_early(ctx: Context<WithdrawVesting>) -> Result<()> {
+        let escrow = &mut ctx.accounts.vesting_escrow;
+        checkpoint_vesting(escrow, Clock::get()?.unix_timestamp)?;
+        let (payout, forfeit) =
+            early_exit_amounts(escrow, ctx.accounts.staking_config.early_exit_penalty_bps)?;
+        require!(payout > 0 || forfeit > 0, StakingError::NothingVested);
+        escrow.locked_amount = 0;
+        escrow.released_amount = 0;
+        escrow.unlocked_amount = 0;
+        escrow.end_timestamp = escrow.start_timestamp;
+
+        if payout > 0 {
+            vesting_vault_transfer(
+                &ctx.accounts.token_program,
+                &ctx.accounts.vesting_vault,
+                &ctx.accounts.user_haio_account.to_account_info(),
+                &ctx.accounts.vesting_vault_authority,
+                ctx.bumps.vesting_vault_authority,
+                payout,
+            )?;
+        }
+        if forfeit > 0 {
+            vesting_vault_transfer(
+                &ctx.accounts.token_program,
+                &ctx.accounts.vesting_vault,
+                &ctx.accounts.reward_pool_pda.to_account_info(),
+                &ctx.accounts.vesting_vault_authority,
+                ctx.bumps.vesting_vault_authority,
+                forfeit,
+            )?;
+            // With nobody staked the forfeit simply stays in the pool as surplus.
+            if ctx.accounts.engine_state_loader.total_staked_amount > 0 {
+                revenue_engine::cpi::redistribute_to_stakers(
+                    engine_cpi_ctx!(
+                        &ctx.accounts.revenue_engine_program,
+                        &ctx.accounts.engine_state,
+                        &ctx.accounts.engine_caller,
+                        ctx.bumps.engine_caller,
+                    ),
+                    forfeit,
+                )?;
+            }
+        }
+        msg!(
+            "Early vesting exit: paid {} HAiO, forfeited {}",
+            payout,
+            forfeit
+        );
+        Ok(())
+    }
+
+    pub fn start_reconcile(ctx: Context<StartReconcile>) -> Result<()> {
+        let engine_state_info = &ctx.accounts.engine_state;
+        let engine_state_data = engine_state_info.try_borrow_data()?;
+        let current_engine_state = EngineState::try_deserialize(&mut &engine_state_data[..])?;
+        drop(engine_state_data);
+
+        let reconcile_state = &mut ctx.accounts.reconcile_state;
+        let is_stale =
+            reconcile_state.start_update_count != current_engine_state.staked_update_count;
+        require!(
+            !reconcile_state.in_progress || is_stale,
+            StakingError::ReconcileInProgress
+        );
+
+        reconcile_state.engine_state_ref = engine_state_info.key();
+        reconcile_state.epoch = reconcile_state
+            .epoch
+            .checked_add(1)
+            .ok_or(StakingError::CalculationError)?;
+        reconcile_state.in_progress = true;
+        reconcile_state.started_at = Clock::get()?.unix_timestamp;
+        reconcile_state.start_update_count = current_engine_state.staked_update_count;
+        reconcile_state.counted_total = 0;
+        reconcile_state.counted_reward_debt = 0;
+        reconcile_state.positions_counted = 0;
+        reconcile_state.bump = ctx.bumps.reconcile_state;
+
+        msg!(
+            "Reconcile epoch {} started against total_staked_amount {}",
+            reconcile_state.epoch,
+            current_engine_state.total_staked_amount
+        );
+        Ok(())
+    }
+
+    /// Counts the `NftStakeState` accounts passed (writable) in `remaining_accounts`.
+    /// Each position is counted at most once per epoch, so batches can be resubmitted safely.
+    pub fn reconcile_staked<'info>(
+        ctx: Context<'_, '_, 'info, 'info, ReconcileStaked<'info>>,
+    ) -> Result<()> {
+        let reconcile_state = &mut ctx.accounts.reconcile_state;
+        require!(
+            reconcile_state.in_progress,
+            StakingError::ReconcileNotInProgress
+        );
+        let epoch = reconcile_state.epoch;
+
+        let mut counted_in_batch: u64 = 0;
+        for account_info in ctx.remaining_accounts.iter() {
+            require!(account_info.is_writable, StakingError::AccountNotWritable);
+            let is_token_position = account_info
+                .try_borrow_data()?
+                .starts_with(TokenStakeState::DISCRIMINATOR);
+            if is_token_position {
+                let mut position: Account<'info, TokenStakeState> =
+                    Account::try_from(account_info)?;
+                if position.staked_amount == 0
+                    || position.engine_state_ref != reconcile_state.engine_state_ref
+                    || position.reconcile_epoch == epoch
+                {
+                    continue;
+                }
+                count_reconcile_position(
+                    reconcile_state,
+                    position.staked_amount,
+                    position.reward_debt,
+                )?;
+                position.reconcile_epoch = epoch;
+                position.exit(&crate::ID)?;
+            } else {
+                let mut position: Account<'info, NftStakeState> = Account::try_from(account_info)?;
+                if !position.is_staked
+                    || position.engine_state_ref != reconcile_state.engine_state_ref
+                    || position.reconcile_epoch == epoch
+                {
+                    continue;
+                }
+                count_reconcile_position(
+                    reconcile_state,
+                    position.staked_amount,
+                    position.reward_debt,
+                )?;
+                position.reconcile_epoch = epoch;
+                position.exit(&crate::ID)?;
+            }
+            counted_in_batch += 1;
+        }
+
+        reconcile_state.positions_counted = reconcile_state
+            .positions_counted
+            .checked_add(counted_in_batch)
+            .ok_or(StakingError::CalculationError)?;
+        msg!(
+            "Reconcile epoch {}: counted {} positions (total {}), running staked total {}",
+            epoch,
+            counted_in_batch,
+            reconcile_state.positions_counted,
+            reconcile_state.counted_total
+        );
+        Ok(())
+    }
+
+    pub fn finalize_reconcile(ctx: Context<FinalizeReconcile>) -> Result<()> {
+        let engine_state_data = ctx.accounts.engine_state.try_borrow_data()?;
+        let current_engine_state = EngineState::try_deserialize(&mut &engine_state_data[..])?;
+        drop(engine_state_data);
+
+        require_keys_eq!(
+            current_engine_state.authority,
+            ctx.accounts.authority.key(),
+            StakingError::Unauthorized
+        );
+
+        let reconcile_state = &mut ctx.accounts.reconcile_state;
+        require!(
+            reconcile_state.in_progress,
+            StakingError::ReconcileNotInProgress
+        );
+        reconcile_state.in_progress = false;
+
+        if current_engine_state.staked_update_count != reconcile_state.start_update_count {
+            msg!(
+                "Reconcile epoch {} is stale: staked totals changed during the pass. Restart required.",
+                reconcile_state.epoch
+            );
+            return Ok(());
+        }
+
+        let discrepancy = current_engine_state.total_staked_amount as i128
+            - reconcile_state.counted_total as i128;
+        reconcile_state.last_finalized_at = Clock::get()?.unix_timestamp;
+        reconcile_state.last_counted_total = reconcile_state.counted_total;
+        reconcile_state.last_counted_reward_debt = reconcile_state.counted_reward_debt;
+        reconcile_state.last_positions_counted = reconcile_state.positions_counted;
+        reconcile_state.last_discrepancy =
+            i64::try_from(discrepancy).map_err(|_| StakingError::CalculationError)?;
+        reconcile_state.last_update_count = current_engine_state.staked_update_count;
+
+        if discrepancy != 0 {
+            msg!(
+                "DISCREPANCY: engine total_staked_amount {} vs counted {} over {} positions (diff {})",
+                current_engine_state.total_staked_amount,
+                reconcile_state.counted_total,
+                reconcile_state.positions_counted,
+                discrepancy
+            );
+        } else {
+            msg!(
+                "Reconcile epoch {} matched: {} staked over {} positions",
+                reconcile_state.epoch,
+                reconcile_state.counted_total,
+                reconcile_state.positions_counted
+            );
+        }
+        Ok(())
+    }
+
+    /// Writes the last finalized reconcile result to the engine. The authority passes the
+    /// totals it reviewed, so a result finalized after that review cannot be applied blindly.
+    pub fn apply_reconcile_correction(
+        ctx: Context<ApplyReconcileCorrection>,
+        expected_total: u64,
+        expected_reward_debt: u128,
+        expected_positions: u64,
+    ) -> Result<()> {
+        let engine_state_data = ctx.accounts.engine_state.try_borrow_data()?;
+        let current_engine_state = EngineState::try_deserialize(&mut &engine_state_data[..])?;
+        drop(engine_state_data);
+
+        require_keys_eq!(
+            current_engine_state.authority,
+            ctx.accounts.authority.key(),
+            StakingError::Unauthorized
+        );
+
+        let reconcile_state = &mut ctx.accounts.reconcile_state;
+        require!(
+            reconcile_state.last_finalized_at > 0
+                && reconcile_state.last_update_count == current_engine_state.staked_update_count,
+            StakingError::ReconcileStale
+        );
+        require!(
+            reconcile_state.last_counted_total == expected_total
+                && reconcile_state.last_counted_reward_debt == expected_reward_debt
+                && reconcile_state.last_positions_counted == expected_positions,
+            StakingError::ReconcileResultMismatch
+        );
+
+        let cpi_program = ctx.accounts.revenue_engine_program.to_account_info();
+        let cpi_accounts = revenue_engine::cpi::accounts::CorrectTotalStaked {
+            engine_state: ctx.accounts.engine_state.to_account_info(),
+            authority: ctx.accounts.authority.to_account_info(),
+            engine_caller: ctx.accounts.engine_caller.to_account_info(),
+        };
+        let engine_caller_seeds: &[&[u8]] = &[ENGINE_CALLER_SEED, &[ctx.bumps.engine_caller]];
+        let signer_seeds = &[engine_caller_seeds];
+        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer_seeds);
+        revenue_engine::cpi::correct_total_staked(
+            cpi_ctx,
+            reconcile_state.last_counted_total,
+            reconcile_state.last_counted_reward_debt,
+        )?;
+
+        msg!(
+            "Applied reconcile epoch {}: total_staked_amount set to {}",
+            reconcile_state.epoch,
+            reconcile_state.last_counted_total
+        );
+        reconcile_state.last_discrepancy = 0;
+        Ok(())
+    }
+
+    /// Empties the Reward Pool once the engine is retired, ahead of `close_engine`. The pool
+    /// stays open because `close_engine` still loads it to check that it is empty. Leftover
+    /// claims, position IOUs and ve rewards must be paid out first.
+    pub fn sweep_reward_pool(ctx: Context<SweepRewardPool>) -> Result<()> {
+        let engine_state = &ctx.accounts.engine_state;
+        require_keys_eq!(
+            engine_state.authority,
+            ctx.accounts.authority.key(),
+            StakingError::Unauthorized
+        );
+        require!(engine_state.paused, StakingError::EngineNotRetired);
+        let closable_at = engine_state
+            .paused_at
+            .checked_add(CLOSE_GRACE_PERIOD_SECS)
+            .ok_or(StakingError::CalculationError)?;
+        require!(
+            Clock::get()?.unix_timestamp >= closable_at && engine_state.total_staked_amount == 0,
+            StakingError::EngineNotRetired
+        );
+        require!(
+            engine_state.total_reward_iou == 0 && engine_state.ve_rewards_outstanding == 0,
+            StakingError::RewardsOutstanding
+        );
+
+        let authority_bump = ctx.bumps.reward_pool_authority;
+        let seeds = &[
+            b"reward_pool_authority_seed".as_ref(),
+            &[authority_bump][..],
+        ];
+        let signer_seeds = &[&seeds[..]];
+
+        let remaining = ctx.accounts.reward_pool_pda.amount;
+        if remaining > 0 {
+            token::transfer(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    Transfer {
+                        from: ctx.accounts.reward_pool_pda.to_account_info(),
+                        to: ctx.accounts.destination.to_account_info(),
+                        authority: ctx.accounts.reward_pool_authority.to_account_info(),
+                    },
+                    signer_seeds,
+                ),
+                remaining,
+            )?;
+        }
+
+        msg!(
+            "Swept {} HAiO from Reward Pool to {}",
+            remaining,
+            ctx.accounts.destination.key()
+        );
+        Ok(())
+    }
+
+    pub fn initialize_staking_config(
+        ctx: Context<InitializeStakingConfig>,
+        curator: Pubkey,
+    ) -> Result<()> {
+        require_keys_eq!(
+            ctx.accounts.engine_state.authority,
+            ctx.accounts.authority.key(),
+            StakingError::Unauthorized
+        );
+
+        let config = &mut ctx.accounts.staking_config;
+        config.authority = ctx.accounts.authority.key();
+        config.curator = curator;
+        config.engine_state_ref = ctx.accounts.engine_state.key();
+        config.bump = ctx.bumps.staking_config;
+        config.lock_tier_count = 0;
+        config.lock_tiers = [LockTier::default(); MAX_LOCK_TIERS];
+        config.unbonding_cooldown_secs = 0;
+        config.core_collection = Pubkey::default();
+        config.cnft_collection = Pubkey::default();
+        config.token_stake_unit = 0;
+        config.vesting_duration_secs = 0;
+        config.early_exit_penalty_bps = 0;
+
+        msg!("Staking config initialized. Curator: {}", curator);
+        Ok(())
+    }
+
+    pub fn set_curator(ctx: Context<UpdateStakingConfig>, new_curator: Pubkey) -> Result<()> {
+        ctx.accounts.staking_config.curator = new_curator;
+        msg!("Curator set to {}", new_curator);
+        Ok(())
+    }
+
+    pub fn set_lock_tiers(ctx: Context<UpdateStakingConfig>, tiers: Vec<LockTier>) -> Result<()> {
+        require!(tiers.len() <= MAX_LOCK_TIERS, StakingError::InvalidLockTiers);
+        for tier in tiers.iter() {
+            require!(
+                tier.duration_secs > 0 && tier.boost_bps >= 10000,
+                StakingError::InvalidLockTiers
+            );
+        }
+
+        let config = &mut ctx.accounts.staking_config;
+        let mut lock_tiers = [LockTier::default(); MAX_LOCK_TIERS];
+        lock_tiers[..tiers.len()].copy_from_slice(&tiers);
+        config.lock_tiers = lock_tiers;
+        config.lock_tier_count = tiers.len() as u8;
+
+        msg!("Lock tiers updated: {} tiers", tiers.len());
+        Ok(())
+    }
+
+    pub fn set_unbonding_cooldown(
+        ctx: Context<UpdateStakingConfig>,
+        cooldown_secs: i64,
+    ) -> Result<()> {
+        require!(cooldown_secs >= 0, StakingError::InvalidCooldown);
+        ctx.accounts.staking_config.unbonding_cooldown_secs = cooldown_secs;
+        msg!("Unbonding cooldown set to {} seconds", cooldown_secs);
+        Ok(())
+    }
+
+    /// Sets the Metaplex Core collection whose assets may be staked. Default disables Core staking.
+    pub fn set_core_collection(
+        ctx: Context<UpdateStakingConfig>,
+        collection: Pubkey,
+    ) -> Result<()> {
+        ctx.accounts.staking_config.core_collection = collection;
+        msg!("Core collection set to {}", collection);
+        Ok(())
+    }
+
+    /// Sets the verified collection compressed NFTs must belong to. Default disables cNFT staking.
+    pub fn set_cnft_collection(
+        ctx: Context<UpdateStakingConfig>,
+        collection: Pubkey,
+    ) -> Result<()> {
+        ctx.accounts.staking_config.cnft_collection = collection;
+        msg!("cNFT collection set to {}", collection);
+        Ok(())
+    }
+
+    /// Sets how many HAiO base units make one unit of fungible staking weight. Zero disables
+    /// new deposits; existing positions keep their weight until they are next touched.
+    pub fn set_token_stake_unit(ctx: Context<UpdateStakingConfig>, unit: u64) -> Result<()> {
+        ctx.accounts.staking_config.token_stake_unit = unit;
+        msg!("Token stake unit set to {}", unit);
+        Ok(())
+    }
+
+    /// Configures vesting of reward payouts. A zero duration pays out directly; otherwise
+    /// every claim and unstake vests into the recipient's escrow, merged into its unvested
+    /// balance as described on `VestingEscrow`.
+    pub fn set_vesting_config(
+        ctx: Context<UpdateStakingConfig>,
+        duration_secs: i64,
+        early_exit_penalty_bps: u16,
+    ) -> Result<()> {
+        require!(
+            duration_secs >= 0 && early_exit_penalty_bps <= 10000,
+            StakingError::InvalidVestingConfig
+        );
+        let config = &mut ctx.accounts.staking_config;
+        config.vesting_duration_secs = duration_secs;
+        config.early_exit_penalty_bps = early_exit_penalty_bps;
+        msg!(
+            "Vesting set to {} seconds, early exit penalty {} bps",
+            duration_secs,
+            early_exit_penalty_bps
+        );
+        Ok(())
+    }
+
+    /// Sets the share of every reward payout of a referred position credited to its referrer.
+    pub fn set_referral_bps(ctx: Context<UpdateStakingConfig>, referral_bps: u16) -> Result<()> {
+        require!(referral_bps <= 10000, StakingError::InvalidReferralConfig);
+        ctx.accounts.staking_config.referral_bps = referral_bps;
+        msg!("Referral share set to {} bps", referral_bps);
+        Ok(())
+    }
+
+    /// Creates the referral account a wallet needs before stakers can name it as referrer.
+    pub fn register_referrer(ctx: Context<RegisterReferrer>) -> Result<()> {
+        let referral_account = &mut ctx.accounts.referral_account;
+        referral_account.referrer = ctx.accounts.referrer.key();
+        referral_account.bump = ctx.bumps.referral_account;
+        msg!("Referrer registered: {}", referral_account.referrer);
+        Ok(())
+    }
+
+    /// Adds HAiO to the referral budget. Referral credits never exceed its unreserved balance.
+    pub fn fund_referral_budget(ctx: Context<FundReferralBudget>, amount: u64) -> Result<()> {
+        require!(amount > 0, StakingError::InvalidAmount);
+        token::transfer(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.funder_haio_account.to_account_info(),
+                    to: ctx.accounts.referral_budget.to_account_info(),
+                    authority: ctx.accounts.funder.to_account_info(),
+                },
+            ),
+            amount,
+        )?;
+        msg!("Referral budget funded with {} HAiO", amount);
+        Ok(())
+    }
+
+    /// Pays out a referrer's credited referral rewards.
+    pub fn claim_referral_rewards(ctx: Context<ClaimReferralRewards>) -> Result<()> {
+        let amount = ctx.accounts.referral_account.claimable;
+        require!(amount > 0, StakingError::NoRewardsToClaim);
+
+        let bump = ctx.bumps.referral_budget_authority;
+        let seeds = &[b"referral_budget_authority".as_ref(), &[bump][..]];
+        let signer_seeds = &[&seeds[..]];
+        token::transfer(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.referral_budget.to_account_info(),
+                    to: ctx.accounts.referrer_haio_account.to_account_info(),
+                    authority: ctx.accounts.referral_budget_authority.to_account_info(),
+                },
+                signer_seeds,
+            ),
+            amount,
+        )?;
+
+        let config = &mut ctx.accounts.staking_config;
+        config.referral_liabilities = config
+            .referral_liabilities
+            .checked_sub(amount)
+            .ok_or(StakingError::CalculationError)?;
+        ctx.accounts.referral_account.claimable = 0;
+        msg!(
+            "Referrer {} claimed {} HAiO",
+            ctx.accounts.referrer.key(),
+            amount
+        );
+        Ok(())
+    }
+
+    /// Sets the staking weight for a mint, cNFT asset ID or Core asset. Only affects
+    /// positions staked afterwards.
+    pub fn set_nft_weight(ctx: Context<SetNftWeight>, nft_mint: Pubkey, weight: u64) -> Result<()> {
+        require!(weight > 0, StakingError::InvalidWeight);
+
+        let nft_weight = &mut ctx.accounts.nft_weight;
+        nft_weight.nft_mint = nft_mint;
+        nft_weight.weight = weight;
+        nft_weight.bump = ctx.bumps.nft_weight;
+
+        msg!("Weight for {} set to {}", nft_weight.nft_mint, weight);
+        Ok(())
+    }
+
+    /// Grows an `NftStakeState` created by an earlier program version to the current layout,
+    /// so the position can be reconciled, claimed and unstaked again. Fields added since were
+    /// appended and start zeroed; `migrate_nft_stake_state` fills the ones that cannot stay
+    /// zero. Anyone may pay the extra rent; a current account is left alone.
+    pub fn migrate_account(ctx: Context<MigrateAccount>) -> Result<()> {
+        let account = ctx.accounts.account.to_account_info();
+        require!(
+            account
+                .try_borrow_data()?
+                .starts_with(NftStakeState::DISCRIMINATOR),
+            StakingError::AccountNotMigratable
+        );
+        let space = 8 + NftStakeState::MAX_SIZE;
+        let old_len = account.data_len();
+        if old_len >= space {
+            msg!(
+                "Account {} is already at the current layout ({} bytes)",
+                account.key(),
+                old_len
+            );
+            return Ok(());
+        }
+
+        let top_up = Rent::get()?
+            .minimum_balance(space)
+            .saturating_sub(account.lamports());
+        if top_up > 0 {
+            system_program::transfer(
+                CpiContext::new(
+                    ctx.accounts.system_program.to_account_info(),
+                    system_program::Transfer {
+                        from: ctx.accounts.payer.to_account_info(),
+                        to: account.clone(),
+                    },
+                ),
+                top_up,
+            )?;
+        }
+        account.realloc(space, true)?;
+
+        let mut stake_state = NftStakeState::try_deserialize(&mut &account.try_borrow_data()?[..])?;
+        migrate_nft_stake_state(&mut stake_state);
+        stake_state.try_serialize(&mut &mut account.try_borrow_mut_data()?[..])?;
+
+        msg!(
+            "Migrated account {} from {} to {} bytes",
+            account.key(),
+            old_len,
+            space
+        );
+        Ok(())
+    }
+}
+
+/// Reads a staking config that may not exist yet. Positions staked before the config was
+/// set up still unstake and claim without it: no cooldown, no vesting, no referral credit.
+fn read_staking_config(staking_config_info: &AccountInfo) -> Result<Option<StakingConfig>> {
+    if staking_config_info.owner != &crate::ID || staking_config_info.data_is_empty() {
+        return Ok(None);
+    }
+    let data = staking_config_info.try_borrow_data()?;
+    Ok(Some(StakingConfig::try_deserialize(&mut &data[..])?))
+}
+
+/// Stores back a config read with `read_staking_config`, e.g. after a referral credit.
+fn write_staking_config(staking_config_info: &AccountInfo, config: Option<&StakingConfig>) -> Result<()> {
+    if let Some(config) = config {
+        config.try_serialize(&mut &mut staking_config_info.try_borrow_mut_data()?[..])?;
+    }
+    Ok(())
+}
+
+fn unbonding_cooldown_secs(config: Option<&StakingConfig>) -> i64 {
+    config.map_or(0, |config| config.unbonding_cooldown_secs)
+}
+
+fn vesting_duration_secs(config: Option<&StakingConfig>) -> i64 {
+    config.map_or(0, |config| config.vesting_duration_secs)
+}
+
+fn read_nft_weight(nft_weight_info: &AccountInfo) -> Result<u64> {
+    if nft_weight_info.owner != &crate::ID || nft_weight_info.data_is_empty() {
+        return Ok(DEFAULT_NFT_WEIGHT);
+    }
+    let data = nft_weight_info.try_borrow_data()?;
+    let nft_weight = NftWeight::try_deserialize(&mut &data[..])?;
+    Ok(nft_weight.weight)
+}
+
+fn boosted_weight(base_weight: u64, boost_bps: u16) -> Result<u64> {
+    let weight = (base_weight as u128)
+        .checked_mul(boost_bps as u128)
+        .map(|w| w / 10000)
+        .ok_or(StakingError::CalculationError)?;
+    require!(
+        weight > 0 && weight <= u64::MAX as u128,
+        StakingError::CalculationError
+    );
+    Ok(weight as u64)
+}
+
+fn resolve_lock_tier(config: Option<&StakingConfig>, lock_tier: Option<u8>) -> Result<LockTier> {
+    match lock_tier {
+        Some(index) => {
+            let config = config.ok_or(StakingError::StakingConfigRequired)?;
+            require!(
+                index < config.lock_tier_count,
+                StakingError::InvalidLockTier
+            );
+            Ok(config.lock_tiers[index as usize])
+        }
+        None => Ok(LockTier {
+            duration_secs: 0,
+            boost_bps: 10000,
+        }),
+    }
+}
+
+/// Creates a program-owned PDA account the way Anchor's `init` does: a plain
+/// `create_account` fails if someone has already sent lamports to the address, so a
+/// pre-funded PDA is topped up to rent exemption, allocated and assigned instead.
+fn create_pda_account<'info>(
+    system_program: &Program<'info, System>,
+    payer: &AccountInfo<'info>,
+    account: &AccountInfo<'info>,
+    rent: &Rent,
+    space: usize,
+    signer_seeds: &[&[u8]],
+) -> Result<()> {
+    let required_lamports = rent.minimum_balance(space);
+    let current_lamports = account.lamports();
+    if current_lamports == 0 {
+        return system_program::create_account(
+            CpiContext::new_with_signer(
+                system_program.to_account_info(),
+                system_program::CreateAccount {
+                    from: payer.clone(),
+                    to: account.clone(),
+                },
+                &[signer_seeds],
+            ),
+            required_lamports,
+            space as u64,
+            &crate::ID,
+        );
+    }
+
+    let top_up = required_lamports.saturating_sub(current_lamports);
+    if top_up > 0 {
+        system_program::transfer(
+            CpiContext::new(
+                system_program.to_account_info(),
+                system_program::Transfer {
+                    from: payer.clone(),
+                    to: account.clone(),
+                },
+            ),
+            top_up,
+        )?;
+    }
+    system_program::allocate(
+        CpiContext::new_with_signer(
+            system_program.to_account_info(),
+            system_program::Allocate {
+                account_to_allocate: account.clone(),
+            },
+            &[signer_seeds],
+        ),
+        space as u64,
+    )?;
+    system_program::assign(
+        CpiContext::new_with_signer(
+            system_program.to_account_info(),
+            system_program::Assign {
+                account_to_assign: account.clone(),
+            },
+            &[signer_seeds],
+        ),
+        &crate::ID,
+    )
+}
+
+/// Fills a freshly created position and returns its effective (boosted) weight.
+fn open_position(
+    stake_state: &mut NftStakeState,
+    user_wallet: Pubkey,
+    nft_mint: Pubkey,
+    engine_state_ref: Pubkey,
+    engine_state: &EngineState,
+    base_weight: u64,
+    tier: LockTier,
+) -> Result<u64> {
+    let weight = boosted_weight(base_weight, tier.boost_bps)?;
+    let now = Clock::get()?.unix_timestamp;
+
+    stake_state.user_wallet = user_wallet;
+    stake_state.nft_mint = nft_mint;
+    stake_state.staked_amount = weight;
+    stake_state.last_staked_timestamp = now;
+    stake_state.is_staked = true;
+    stake_state.reward_debt = engine_state.reward_per_token_cumulative;
+    stake_state.engine_state_ref = engine_state_ref;
+    stake_state.reconcile_epoch = 0;
+    stake_state.base_weight = base_weight;
+    stake_state.lock_duration = tier.duration_secs;
+    stake_state.lock_boost_bps = tier.boost_bps;
+    stake_state.unbonding_ends_at = 0;
+    stake_state.reward_beneficiary = Pubkey::default();
+    stake_state.receipt_mint = Pubkey::default();
+    stake_state.unbond_recipient = Pubkey::default();
+    stake_state.custody_mode = CustodyMode::Untracked;
+    stake_state.unpaid_rewards = 0;
+    stake_state.referrer = Pubkey::default();
+    stake_state.eligible_from = eligible_from(engine_state, now);
+    stake_state.ve_unlock_timestamp = 0;
+    Ok(weight)
+}
+
+/// Loads a writable position from `remaining_accounts` and checks it belongs to `user_wallet`.
+fn load_batch_position<'info>(
+    stake_state_info: &'info AccountInfo<'info>,
+    user_wallet: &Pubkey,
+    engine_state: &Pubkey,
+) -> Result<Account<'info, NftStakeState>> {
+    require!(stake_state_info.is_writable, StakingError::AccountNotWritable);
+    let stake_state: Account<'info, NftStakeState> = Account::try_from(stake_state_info)?;
+    require_keys_eq!(stake_state.user_wallet, *user_wallet, StakingError::Unauthorized);
+    require_keys_eq!(stake_state.engine_state_ref, *engine_state, StakingError::Unauthorized);
+    require!(stake_state.is_staked, StakingError::NftNotStaked);
+    require_not_tokenized(&stake_state)?;
+
+    let expected_key = Pubkey::create_program_address(
+        &[
+            b"nft_stake",
+            user_wallet.as_ref(),
+            stake_state.nft_mint.as_ref(),
+            &[stake_state.bump],
+        ],
+        &crate::ID,
+    )
+    .map_err(|_| StakingError::InvalidBatch)?;
+    require_keys_eq!(stake_state_info.key(), expected_key, StakingError::InvalidBatch);
+    Ok(stake_state)
+}
+
+fn summary_add_stake(
+    summary: &mut UserStakeSummary,
+    user_wallet: Pubkey,
+    bump: u8,
+    count: u32,
+    weight: u64,
+) -> Result<()> {
+    summary.user_wallet = user_wallet;
+    summary.bump = bump;
+    if summary.first_stake_timestamp == 0 {
+        summary.first_stake_timestamp = Clock::get()?.unix_timestamp;
+    }
+    summary.staked_count = summary
+        .staked_count
+        .checked_add(count)
+        .ok_or(StakingError::CalculationError)?;
+    summary.total_weight = summary
+        .total_weight
+        .checked_add(weight)
+        .ok_or(StakingError::CalculationError)?;
+    Ok(())
+}
+
+/// Positions staked before summaries existed are not tracked, hence the optional summary
+/// and the saturating math.
+fn summary_remove_stake(
+    summary: Option<&mut UserStakeSummary>,
+    count: u32,
+    weight: u64,
+    claimed: u64,
+) -> Result<()> {
+    let Some(summary) = summary else {
+        return Ok(());
+    };
+    summary.staked_count = summary.staked_count.saturating_sub(count);
+    summary.total_weight = summary.total_weight.saturating_sub(weight);
+    summary_add_claimed(Some(summary), claimed)
+}
+
+fn summary_add_claimed(summary: Option<&mut UserStakeSummary>, claimed: u64) -> Result<()> {
+    let Some(summary) = summary else {
+        return Ok(());
+    };
+    summary.lifetime_claimed = summary
+        .lifetime_claimed
+        .checked_add(claimed)
+        .ok_or(StakingError::CalculationError)?;
+    Ok(())
+}
+
+/// Moves a tokenized position's NFT from its vault to `destination` and closes the vault,
+/// returning its rent to `rent_receiver`.
+fn release_nft_vault<'info>(
+    token_program: &Program<'info, Token>,
+    nft_vault: &Account<'info, TokenAccount>,
+    destination: &Account<'info, TokenAccount>,
+    receipt_authority: &AccountInfo<'info>,
+    receipt_authority_bump: u8,
+    rent_receiver: &AccountInfo<'info>,
+) -> Result<()> {
+    let seeds = &[b"receipt_authority".as_ref(), &[receipt_authority_bump][..]];
+    let signer_seeds = &[&seeds[..]];
+    token::transfer(
+        CpiContext::new_with_signer(
+            token_program.to_account_info(),
+            Transfer {
+                from: nft_vault.to_account_info(),
+                to: destination.to_account_info(),
+                authority: receipt_authority.clone(),
+            },
+            signer_seeds,
+        ),
+        1,
+    )?;
+    token::close_account(CpiContext::new_with_signer(
+        token_program.to_account_info(),
+        CloseAccount {
+            account: nft_vault.to_account_info(),
+            destination: rent_receiver.clone(),
+            authority: receipt_authority.clone(),
+        },
+        signer_seeds,
+    ))
+}
+
+fn require_custody_mode(stake_state: &NftStakeState, mode: CustodyMode) -> Result<()> {
+    require!(
+        stake_state.custody_mode == mode,
+        StakingError::WrongCustodyMode
+    );
+    Ok(())
+}
+
+/// Checks that `nft_metadata` belongs to a programmable NFT and that the supplied
+/// authorization rules account (if any) is the one named in its programmable config.
+fn require_programmable_metadata(
+    nft_metadata: &AccountInfo,
+    authorization_rules: Option<&UncheckedAccount>,
+) -> Result<()> {
+    let data = nft_metadata.try_borrow_data()?;
+    let metadata = mpl_token_metadata::accounts::Metadata::safe_deserialize(&data)
+        .map_err(|_| error!(StakingError::NotProgrammableNft))?;
+    require!(
+        metadata.token_standard == Some(TokenStandard::ProgrammableNonFungible),
+        StakingError::NotProgrammableNft
+    );
+    let rule_set = match metadata.programmable_config {
+        Some(ProgrammableConfig::V1 { rule_set }) => rule_set,
+        None => None,
+    };
+    match (rule_set, authorization_rules) {
+        (Some(expected), Some(provided)) => require_keys_eq!(
+            provided.key(),
+            expected,
+            StakingError::InvalidAuthorizationRules
+        ),
+        (Some(_), None) => return err!(StakingError::InvalidAuthorizationRules),
+        (None, _) => {}
+    }
+    Ok(())
+}
+
+/// Checks that a Core asset is owned by `owner` and belongs to the configured collection.
+fn require_core_asset(
+    asset: &AccountInfo,
+    collection: &AccountInfo,
+    config: &StakingConfig,
+    owner: &Pubkey,
+) -> Result<()> {
+    require!(
+        config.core_collection != Pubkey::default()
+            && collection.key() == config.core_collection,
+        StakingError::InvalidCollection
+    );
+    let data = asset.try_borrow_data()?;
+    let base = BaseAssetV1::from_bytes(&data).map_err(|_| error!(StakingError::InvalidNftAccount))?;
+    require_keys_eq!(base.owner, *owner, StakingError::InvalidNftAccount);
+    require!(
+        base.update_authority == UpdateAuthority::Collection(config.core_collection),
+        StakingError::InvalidCollection
+    );
+    Ok(())
+}
+
+/// Checks that `metadata` (Borsh-serialized Bubblegum `MetadataArgs`) is the metadata behind
+/// `leaf` and carries a verified membership of the configured cNFT collection. Bubblegum then
+/// proves the leaf itself during the transfer.
+fn require_cnft_collection(
+    metadata: &[u8],
+    leaf: &CompressedLeaf,
+    config: &StakingConfig,
+) -> Result<()> {
+    let metadata = MetadataArgs::try_from_slice(metadata)
+        .map_err(|_| error!(StakingError::InvalidNftAccount))?;
+    let data_hash = mpl_bubblegum::hash::hash_metadata(&metadata)
+        .map_err(|_| error!(StakingError::InvalidNftAccount))?;
+    require!(data_hash == leaf.data_hash, StakingError::InvalidNftAccount);
+    require!(
+        config.cnft_collection != Pubkey::default()
+            && metadata
+                .collection
+                .is_some_and(|c| c.verified && c.key == config.cnft_collection),
+        StakingError::InvalidCollection
+    );
+    Ok(())
+}
+
+/// Reports a change of a reward IOU (`previous` -> `current`) to the engine.
+fn sync_reward_iou<'info>(
+    revenue_engine_program: &Program<'info, RevenueEngine>,
+    engine_state: &AccountInfo<'info>,
+    engine_caller: &AccountInfo<'info>,
+    engine_caller_bump: u8,
+    previous: u64,
+    current: u64,
+) -> Result<()> {
+    if previous == current {
+        return Ok(());
+    }
+    revenue_engine::cpi::record_reward_iou(
+        engine_cpi_ctx!(
+            revenue_engine_program,
+            engine_state,
+            engine_caller,
+            engine_caller_bump,
+        ),
+        current.saturating_sub(previous),
+        previous.saturating_sub(current),
+    )
+}
+
+fn require_not_tokenized(stake_state: &NftStakeState) -> Result<()> {
+    require!(
+        stake_state.receipt_mint == Pubkey::default(),
+        StakingError::PositionTokenized
+    );
+    Ok(())
+}
+
+/// Checks that a position may leave now: a staked one needs no unbonding cooldown, no ve
+/// lock and rewards going to `destination`; an unbonding one must be past its cooldown.
+fn require_unstakable(
+    stake_state: &NftStakeState,
+    unbonding_cooldown_secs: i64,
+    destination: &Account<TokenAccount>,
+) -> Result<()> {
+    if stake_state.is_staked {
+        require!(unbonding_cooldown_secs == 0, StakingError::UnbondingRequired);
+        require_unlocked(stake_state)?;
+        return require_reward_destination(stake_state, destination);
+    }
+    require!(stake_state.unbonding_ends_at > 0, StakingError::NotUnbonding);
+    require!(
+        Clock::get()?.unix_timestamp >= stake_state.unbonding_ends_at,
+        StakingError::UnbondingCooldownActive
+    );
+    Ok(())
+}
+
+fn require_reward_destination(
+    stake_state: &NftStakeState,
+    destination: &Account<TokenAccount>,
+) -> Result<()> {
+    if stake_state.reward_beneficiary != Pubkey::default() {
+        require_keys_eq!(
+            destination.owner,
+            stake_state.reward_beneficiary,
+            StakingError::InvalidBeneficiary
+        );
+    }
+    Ok(())
+}
+
+fn require_unlocked(stake_state: &NftStakeState) -> Result<()> {
+    require!(
+        stake_state.ve_unlock_timestamp == 0,
+        StakingError::PositionVeLocked
+    );
+    let unlocks_at = stake_state
+        .last_staked_timestamp
+        .checked_add(stake_state.lock_duration)
+        .ok_or(StakingError::CalculationError)?;
+    require!(
+        Clock::get()?.unix_timestamp >= unlocks_at,
+        StakingError::StillLocked
+    );
+    Ok(())
+}
+
+fn reward_pool_transfer<'info>(
+    token_program: &Program<'info, Token>,
+    reward_pool_pda: &Account<'info, TokenAccount>,
+    destination: &AccountInfo<'info>,
+    reward_pool_authority: &AccountInfo<'info>,
+    authority_bump: u8,
+    amount: u64,
+) -> Result<()> {
+    let seeds = &[
+        b"reward_pool_authority_seed".as_ref(),
+        &[authority_bump][..],
+    ];
+    let signer_seeds = &[&seeds[..]];
+    token::transfer(
+        CpiContext::new_with_signer(
+            token_program.to_account_info(),
+            Transfer {
+                from: reward_pool_pda.to_account_info(),
+                to: destination.clone(),
+                authority: reward_pool_authority.clone(),
+            },
+            signer_seeds,
+        ),
+        amount,
+    )
+}
+
+fn vesting_vault_transfer<'info>(
+    token_program: &Program<'info, Token>,
+    vesting_vault: &Account<'info, TokenAccount>,
+    destination: &AccountInfo<'info>,
+    vesting_vault_authority: &AccountInfo<'info>,
+    authority_bump: u8,
+    amount: u64,
+) -> Result<()> {
+    let seeds = &[b"vesting_vault_authority".as_ref(), &[authority_bump][..]];
+    let signer_seeds = &[&seeds[..]];
+    token::transfer(
+        CpiContext::new_with_signer(
+            token_program.to_account_info(),
+            Transfer {
+                from: vesting_vault.to_account_info(),
+                to: destination.clone(),
+                authority: vesting_vault_authority.clone(),
+            },
+            signer_seeds,
+        ),
+        amount,
+    )
+}
+
+/// Accounts every reward payout goes through. While vesting is enabled rewards land in
+/// the recipient's vesting escrow instead of their token account.
+struct RewardPayout<'a, 'info> {
+    token_program: &'a Program<'info, Token>,
+    reward_pool_pda: &'a Account<'info, TokenAccount>,
+    reward_pool_authority: &'a AccountInfo<'info>,
+    reward_pool_authority_bump: u8,
+    revenue_engine_program: &'a Program<'info, RevenueEngine>,
+    engine_state: &'a AccountInfo<'info>,
+    engine_caller: &'a AccountInfo<'info>,
+    engine_caller_bump: u8,
+    vesting_duration_secs: i64,
+    vesting_escrow: Option<&'a mut Account<'info, VestingEscrow>>,
+    vesting_vault: Option<&'a Account<'info, TokenAccount>>,
+}
+
+/// Pays `paid` rewards to `destination`, or into the vesting escrow of the destination's
+/// owner while vesting is enabled, and records the claim with the engine.
+fn pay_rewards<'info>(
+    payout: &mut RewardPayout<'_, 'info>,
+    destination: &Account<'info, TokenAccount>,
+    paid: u64,
+    settled_debt: u128,
+    forfeited: u64,
+) -> Result<()> {
+    transfer_payout(payout, destination, paid)?;
+    if paid > 0 || settled_debt > 0 {
+        revenue_engine::cpi::record_claim(
+            engine_cpi_ctx!(
+                payout.revenue_engine_program,
+                payout.engine_state,
+                payout.engine_caller,
+                payout.engine_caller_bump,
+            ),
+            paid,
+            settled_debt,
+            forfeited,
+        )?;
+    }
+    Ok(())
+}
+
+/// Moves `paid` rewards from the pool to `destination`, or into the vesting escrow of the
+/// destination's owner while vesting is enabled.
+fn transfer_payout<'info>(
+    payout: &mut RewardPayout<'_, 'info>,
+    destination: &Account<'info, TokenAccount>,
+    paid: u64,
+) -> Result<()> {
+    if paid > 0 {
+        if payout.vesting_duration_secs > 0 {
+            let (Some(escrow), Some(vesting_vault)) = (
+                payout.vesting_escrow.as_deref_mut(),
+                payout.vesting_vault,
+            ) else {
+                return err!(StakingError::VestingAccountsRequired);
+            };
+            require_keys_eq!(
+                escrow.user_wallet,
+                destination.owner,
+                StakingError::VestingEscrowMismatch
+            );
+            reward_pool_transfer(
+                payout.token_program,
+                payout.reward_pool_pda,
+                &vesting_vault.to_account_info(),
+                payout.reward_pool_authority,
+                payout.reward_pool_authority_bump,
+                paid,
+            )?;
+            vest_claim(
+                escrow,
+                paid,
+                payout.vesting_duration_secs,
+                Clock::get()?.unix_timestamp,
+            )?;
+            msg!(
+                "{} HAiO credited to vesting escrow until {}",
+                paid,
+                escrow.end_timestamp
+            );
+        } else {
+            reward_pool_transfer(
+                payout.token_program,
+                payout.reward_pool_pda,
+                &destination.to_account_info(),
+                payout.reward_pool_authority,
+                payout.reward_pool_authority_bump,
+                paid,
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Running totals of the rewards one instruction settles, paid out together by
+/// `pay_settlement`.
+struct Settlement {
+    /// Pool balance not yet promised to positions settled earlier in the instruction.
+    pool_available: u64,
+    paid: u64,
+    /// Rewards skipped for distributions before a position's eligibility window.
+    forfeited: u64,
+    settled_debt: u128,
+    iou_before: u64,
+    iou_after: u64,
+}
+
+impl Settlement {
+    fn new(pool_balance: u64) -> Self {
+        Settlement {
+            pool_available: pool_balance,
+            paid: 0,
+            forfeited: 0,
+            settled_debt: 0,
+            iou_before: 0,
+            iou_after: 0,
+        }
+    }
+
+    /// Books `claimable` new rewards plus a position's earlier IOU, paying as much as the
+    /// pool still holds. Returns `(paid, unpaid)`; `unpaid` is the position's new IOU.
+    fn settle(
+        &mut self,
+        claimable: u64,
+        forfeited: u64,
+        settled_debt: u128,
+        previous_unpaid: u64,
+    ) -> Result<(u64, u64)> {
+        let owed = claimable
+            .checked_add(previous_unpaid)
+            .ok_or(StakingError::CalculationError)?;
+        let paid = owed.min(self.pool_available);
+        let unpaid = owed - paid;
+        self.pool_available -= paid;
+        self.paid = self
+            .paid
+            .checked_add(paid)
+            .ok_or(StakingError::CalculationError)?;
+        self.forfeited = self
+            .forfeited
+            .checked_add(forfeited)
+            .ok_or(StakingError::CalculationError)?;
+        self.settled_debt = self
+            .settled_debt
+            .checked_add(settled_debt)
+            .ok_or(StakingError::CalculationError)?;
+        self.iou_before = self
+            .iou_before
+            .checked_add(previous_unpaid)
+            .ok_or(StakingError::CalculationError)?;
+        self.iou_after = self
+            .iou_after
+            .checked_add(unpaid)
+            .ok_or(StakingError::CalculationError)?;
+        Ok((paid, unpaid))
+    }
+
+    /// True when the settled positions were owed nothing at all.
+    fn is_empty(&self) -> bool {
+        self.paid == 0 && self.iou_after == 0
+    }
+}
+
+/// Referral accounts credited for the referred positions an instruction settles.
+struct ReferralCredit<'a, 'info> {
+    config: Option<&'a mut StakingConfig>,
+    referral_account: Option<&'a mut Account<'info, ReferralAccount>>,
+    referral_budget: Option<&'a Account<'info, TokenAccount>>,
+}
+
+/// Settles an NFT position up to the current rate, keeping what the pool cannot cover as
+/// the position's IOU, and credits its referrer a share of the payout. Returns the amount paid.
+fn settle_nft_rewards(
+    settlement: &mut Settlement,
+    referral: &mut ReferralCredit,
+    engine_state: &EngineState,
+    stake_state: &mut NftStakeState,
+) -> Result<u64> {
+    let (claimable, forfeited) = eligible_rewards(
+        engine_state,
+        stake_state.reward_debt,
+        stake_state.eligible_from,
+        stake_state.staked_amount,
+    )?;
+    let settled_debt = settled_reward_debt(engine_state, stake_state)?;
+    let (paid, unpaid) =
+        settlement.settle(claimable, forfeited, settled_debt, stake_state.unpaid_rewards)?;
+    stake_state.reward_debt = engine_state.reward_per_token_cumulative;
+    stake_state.unpaid_rewards = unpaid;
+    close_eligibility_window(&mut stake_state.eligible_from)?;
+
+    if stake_state.referrer != Pubkey::default() && paid > 0 {
+        // Without a staking config there is no referral share to credit.
+        let Some(config) = referral.config.as_deref_mut() else {
+            return Ok(paid);
+        };
+        let (Some(referral_account), Some(referral_budget)) = (
+            referral.referral_account.as_deref_mut(),
+            referral.referral_budget,
+        ) else {
+            return err!(StakingError::ReferralAccountsRequired);
+        };
+        require_keys_eq!(
+            referral_account.referrer,
+            stake_state.referrer,
+            StakingError::InvalidReferrer
+        );
+        credit_referral(config, referral_account, referral_budget, paid)?;
+    }
+    Ok(paid)
+}
+
+/// Pays out a settlement and reports its IOU change to the engine.
+fn pay_settlement<'info>(
+    payout: &mut RewardPayout<'_, 'info>,
+    destination: &Account<'info, TokenAccount>,
+    settlement: &Settlement,
+) -> Result<()> {
+    if settlement.paid == 0 && settlement.iou_after > 0 {
+        msg!("Reward pool is empty; {} HAiO kept as IOU", settlement.iou_after);
+    }
+    pay_rewards(
+        payout,
+        destination,
+        settlement.paid,
+        settlement.settled_debt,
+        settlement.forfeited,
+    )?;
+    sync_reward_iou(
+        payout.revenue_engine_program,
+        payout.engine_state,
+        payout.engine_caller,
+        payout.engine_caller_bump,
+        settlement.iou_before,
+        settlement.iou_after,
+    )
+}
+
+/// Settles a position that leaves staking, whether right away or after unbonding. A staked
+/// position is paid what the pool covers and its weight leaves the engine and `summary`;
+/// whatever it is still owed then moves to the leftover claim. Returns the amount paid.
+fn finish_unstake<'info>(
+    payout: &mut RewardPayout<'_, 'info>,
+    referral: &mut ReferralCredit,
+    engine_state: &EngineState,
+    stake_state: &mut NftStakeState,
+    destination: &Account<'info, TokenAccount>,
+    leftover: &mut LeftoverDestination<'_, 'info>,
+    summary: Option<&mut UserStakeSummary>,
+) -> Result<u64> {
+    let mut paid = 0;
+    if stake_state.is_staked {
+        let mut settlement = Settlement::new(payout.reward_pool_pda.amount);
+        paid = settle_nft_rewards(&mut settlement, referral, engine_state, stake_state)?;
+        pay_settlement(payout, destination, &settlement)?;
+        revenue_engine::cpi::decrease_total_staked(
+            engine_cpi_ctx!(
+                payout.revenue_engine_program,
+                payout.engine_state,
+                payout.engine_caller,
+                payout.engine_caller_bump,
+            ),
+            stake_state.staked_amount,
+        )?;
+        summary_remove_stake(summary, 1, stake_state.staked_amount, paid)?;
+    }
+    carry_to_leftover(leftover, stake_state.unpaid_rewards)?;
+    stake_state.unpaid_rewards = 0;
+    Ok(paid)
+}
+
+/// Leftover claim of `owner`, which takes over what a leaving position is still owed by
+/// `engine_state_ref`. `payer` funds the claim's rent if it has to be created.
+struct LeftoverDestination<'a, 'info> {
+    claim: AccountInfo<'info>,
+    owner: Pubkey,
+    bump: u8,
+    engine_state_ref: Pubkey,
+    payer: AccountInfo<'info>,
+    system_program: &'a Program<'info, System>,
+}
+
+/// Adds the IOU of a position that is leaving its owner to the owner's leftover claim,
+/// where the engine keeps counting it. The claim is only created once something is owed.
+fn carry_to_leftover(leftover: &mut LeftoverDestination, amount: u64) -> Result<()> {
+    if amount == 0 {
+        return Ok(());
+    }
+    let mut leftover_claim = if leftover.claim.data_is_empty() {
+        create_pda_account(
+            leftover.system_program,
+            &leftover.payer,
+            &leftover.claim,
+            &Rent::get()?,
+            8 + LeftoverClaim::MAX_SIZE,
+            &[b"leftover_claim", leftover.owner.as_ref(), &[leftover.bump]],
+        )?;
+        LeftoverClaim {
+            user_wallet: leftover.owner,
+            amount: 0,
+            bump: leftover.bump,
+            engine_state_ref: leftover.engine_state_ref,
+        }
+    } else {
+        LeftoverClaim::try_deserialize(&mut &leftover.claim.try_borrow_data()?[..])?
+    };
+    require_keys_eq!(
+        leftover_claim.engine_state_ref,
+        leftover.engine_state_ref,
+        StakingError::LeftoverEngineMismatch
+    );
+    leftover_claim.amount = leftover_claim
+        .amount
+        .checked_add(amount)
+        .ok_or(StakingError::CalculationError)?;
+    leftover_claim.try_serialize(&mut &mut leftover.claim.try_borrow_mut_data()?[..])?;
+    msg!("{} HAiO still owed kept as leftover claim", amount);
+    Ok(())
+}
+
+/// Splits a checkpointed escrow on early exit into what its owner receives (everything
+/// unlocked plus the unvested balance after the penalty) and what is forfeited.
+fn early_exit_amounts(escrow: &VestingEscrow, penalty_bps: u16) -> Result<(u64, u64)> {
+    let unvested = escrow
+        .locked_amount
+        .checked_sub(escrow.released_amount)
+        .ok_or(StakingError::CalculationError)?;
+    let forfeit = (unvested as u128)
+        .checked_mul(penalty_bps as u128)
+        .map(|v| v / 10000)
+        .ok_or(StakingError::CalculationError)? as u64;
+    let payout = escrow
+        .unlocked_amount
+        .checked_add(unvested - forfeit)
+        .ok_or(StakingError::CalculationError)?;
+    Ok((payout, forfeit))
+}
+
+/// Adds `amount` to an escrow's unvested balance. The merged balance vests linearly from
+/// `now` until the amount-weighted average of the old balance's remaining time and a full
+/// `duration`, so a new claim delays what was already vesting only by its share of the total.
+fn vest_claim(escrow: &mut VestingEscrow, amount: u64, duration: i64, now: i64) -> Result<()> {
+    checkpoint_vesting(escrow, now)?;
+    let unvested = escrow
+        .locked_amount
+        .checked_sub(escrow.released_amount)
+        .ok_or(StakingError::CalculationError)?;
+    let locked = unvested
+        .checked_add(amount)
+        .ok_or(StakingError::CalculationError)?;
+    if locked == 0 {
+        return Ok(());
+    }
+    let remaining = escrow.end_timestamp.saturating_sub(now).max(0) as u128;
+    let merged_remaining = (unvested as u128)
+        .checked_mul(remaining)
+        .and_then(|v| v.checked_add((amount as u128).checked_mul(duration.max(0) as u128)?))
+        .map(|v| v / locked as u128)
+        .ok_or(StakingError::CalculationError)?;
+    escrow.locked_amount = locked;
+    escrow.released_amount = 0;
+    escrow.start_timestamp = now;
+    escrow.end_timestamp = now
+        .checked_add(merged_remaining as i64)
+        .ok_or(StakingError::CalculationError)?;
+    Ok(())
+}
+
+/// Moves whatever has vested since the last checkpoint from locked to unlocked.
+fn checkpoint_vesting(escrow: &mut VestingEscrow, now: i64) -> Result<()> {
+    let vested_total = if now >= escrow.end_timestamp {
+        escrow.locked_amount
+    } else if now <= escrow.start_timestamp {
+        0
+    } else {
+        let elapsed = (now - escrow.start_timestamp) as u128;
+        let duration = (escrow.end_timestamp - escrow.start_timestamp) as u128;
+        ((escrow.locked_amount as u128)
+            .checked_mul(elapsed)
+            .ok_or(StakingError::CalculationError)?
+            / duration) as u64
+    };
+    let newly_vested = vested_total
+        .checked_sub(escrow.released_amount)
+        .ok_or(StakingError::CalculationError)?;
+    escrow.unlocked_amount = escrow
+        .unlocked_amount
+        .checked_add(newly_vested)
+        .ok_or(StakingError::CalculationError)?;
+    escrow.released_amount = vested_total;
+    Ok(())
+}
+
+/// Start of a new stake's eligibility window: distributions made before it are skipped.
+/// 0 when the engine has no minimum stake age.
+fn eligible_from(engine_state: &EngineState, now: i64) -> i64 {
+    if engine_state.min_stake_age_secs > 0 {
+        now.saturating_add(engine_state.min_stake_age_secs)
+    } else {
+        0
+    }
+}
+
+/// Eligibility start of a token position after `added` joins the `existing` deposit: the
+/// deposit-weighted average of the existing window (`now` once it has passed) and a fresh
+/// window for the new amount, so a top-up does not restart the age of the whole position.
+fn merged_eligible_from(
+    engine_state: &EngineState,
+    current: i64,
+    existing: u64,
+    added: u64,
+    now: i64,
+) -> Result<i64> {
+    let fresh = eligible_from(engine_state, now);
+    if fresh == 0 || existing == 0 {
+        return Ok(if existing == 0 { fresh } else { current });
+    }
+    let current = current.max(now) as i128;
+    let merged = (current * existing as i128 + fresh as i128 * added as i128)
+        .checked_div(existing as i128 + added as i128)
+        .ok_or(StakingError::CalculationError)?;
+    Ok(merged as i64)
+}
+
+/// Reward debt a position earns from: distributions before `eligible_from` are skipped, and
+/// their share stays in the pool as surplus because the position is still settled at the
+/// full rate.
+fn eligible_reward_debt(engine_state: &EngineState, reward_debt: u128, eligible_from: i64) -> u128 {
+    if eligible_from == 0 {
+        return reward_debt;
+    }
+    engine_state
+        .rate_before(eligible_from)
+        .map_or(reward_debt, |ineligible_rate| reward_debt.max(ineligible_rate))
+}
+
+/// Clears a position's eligibility window once it has been settled past it; from then on
+/// its reward debt is at or above every rate it was not eligible for.
+fn close_eligibility_window(eligible_from: &mut i64) -> Result<()> {
+    if *eligible_from != 0 && Clock::get()?.unix_timestamp >= *eligible_from {
+        *eligible_from = 0;
+    }
+    Ok(())
+}
+
+/// Rewards a position earned since `reward_debt`, split into what it may claim and what it
+/// forfeits for distributions before its eligibility window. Returns `(claimable, forfeited)`.
+fn eligible_rewards(
+    engine_state: &EngineState,
+    reward_debt: u128,
+    eligible_from: i64,
+    staked_amount: u64,
+) -> Result<(u64, u64)> {
+    let earned = pending_rewards(engine_state, reward_debt, staked_amount)?;
+    let claimable = pending_rewards(
+        engine_state,
+        eligible_reward_debt(engine_state, reward_debt, eligible_from),
+        staked_amount,
+    )?;
+    Ok((claimable, earned - claimable))
+}
+
+/// Scaled reward debt (`rate delta * staked_amount`) that the engine must add to its
+/// aggregate when this position is settled up to the current rate.
+fn settled_reward_debt(
+    engine_state: &EngineState,
+    stake_state: &NftStakeState,
+) -> Result<u128> {
+    pending_reward_debt(engine_state, stake_state.reward_debt, stake_state.staked_amount)
+}
+
+fn pending_rewards(engine_state: &EngineState, reward_debt: u128, staked_amount: u64) -> Result<u64> {
+    let current = engine_state.reward_per_token_cumulative;
+    if current <= reward_debt {
+        return Ok(0);
+    }
+    let diff = current
+        .checked_sub(reward_debt)
+        .ok_or(StakingError::CalculationError)?;
+    let reward = diff
+        .checked_mul(staked_amount as u128)
+        .and_then(|r| r.checked_div(PRECISION))
+        .ok_or(StakingError::CalculationError)?;
+    require!(reward <= u64::MAX as u128, StakingError::CalculationError);
+    Ok(reward as u64)
+}
+
+fn pending_reward_debt(
+    engine_state: &EngineState,
+    reward_debt: u128,
+    staked_amount: u64,
+) -> Result<u128> {
+    let current = engine_state.reward_per_token_cumulative;
+    if current <= reward_debt {
+        return Ok(0);
+    }
+    let settled = (current - reward_debt)
+        .checked_mul(staked_amount as u128)
+        .ok_or(StakingError::CalculationError)?;
+    Ok(settled)
+}
+
+/// Fills the appended fields of a migrated `NftStakeState`. An earlier position staked its
+/// registry weight unboosted, so that weight is its base weight.
+fn migrate_nft_stake_state(stake_state: &mut NftStakeState) {
+    if stake_state.base_weight == 0 {
+        stake_state.base_weight = stake_state.staked_amount;
+    }
+}
+
+/// Adds one position to the running reconcile totals.
+fn count_reconcile_position(
+    reconcile_state: &mut ReconcileState,
+    staked_amount: u64,
+    reward_debt: u128,
+) -> Result<()> {
+    let position_debt = reward_debt
+        .checked_mul(staked_amount as u128)
+        .ok_or(StakingError::CalculationError)?;
+    reconcile_state.counted_total = reconcile_state
+        .counted_total
+        .checked_add(staked_amount)
+        .ok_or(StakingError::CalculationError)?;
+    reconcile_state.counted_reward_debt = reconcile_state
+        .counted_reward_debt
+        .checked_add(position_debt)
+        .ok_or(StakingError::CalculationError)?;
+    Ok(())
+}
+
+/// Shared body of `claim_rewards` and `claim_rewards_as`: checks that `user_wallet` owns
+/// the staked, untokenized position, settles it and pays `destination`.
+fn claim_nft_rewards<'info>(
+    payout: &mut RewardPayout<'_, 'info>,
+    referral: &mut ReferralCredit,
+    stake_state: &mut NftStakeState,
+    user_wallet: Pubkey,
+    destination: &Account<'info, TokenAccount>,
+    summary: Option<&mut UserStakeSummary>,
+) -> Result<Settlement> {
+    require_keys_eq!(
+        *payout.engine_state.owner,
+        REVENUE_ENGINE_PROGRAM_ID,
+        StakingError::Unauthorized
+    );
+    let engine_state_data = payout.engine_state.try_borrow_data()?;
+    let current_engine_state = EngineState::try_deserialize(&mut &engine_state_data[..])?;
+    drop(engine_state_data);
+
+    require!(stake_state.is_staked, StakingError::NftNotStaked);
+    require_keys_eq!(stake_state.user_wallet, user_wallet, StakingError::Unauthorized);
+    require_not_tokenized(stake_state)?;
+    require_reward_destination(stake_state, destination)?;
+
+    // Any IOU from an earlier short pool is settled first, then as much as the pool holds.
+    let mut settlement = Settlement::new(payout.reward_pool_pda.amount);
+    let paid = settle_nft_rewards(&mut settlement, referral, &current_engine_state, stake_state)?;
+    require!(!settlement.is_empty(), StakingError::NoRewardsToClaim);
+    pay_settlement(payout, destination, &settlement)?;
+    summary_add_claimed(summary, paid)?;
+    Ok(settlement)
+}
+
+/// Settles a token position up to the current rate, keeping what the pool cannot cover as
+/// the position's IOU. Returns the amount paid.
+fn settle_token_position(
+    settlement: &mut Settlement,
+    engine_state: &EngineState,
+    position: &mut TokenStakeState,
+) -> Result<u64> {
+    let (claimable, forfeited) = eligible_rewards(
+        engine_state,
+        position.reward_debt,
+        position.eligible_from,
+        position.staked_amount,
+    )?;
+    let settled_debt =
+        pending_reward_debt(engine_state, position.reward_debt, position.staked_amount)?;
+    let (paid, unpaid) =
+        settlement.settle(claimable, forfeited, settled_debt, position.unpaid_rewards)?;
+    position.reward_debt = engine_state.reward_per_token_cumulative;
+    position.unpaid_rewards = unpaid;
+    close_eligibility_window(&mut position.eligible_from)?;
+    Ok(paid)
+}
+
+/// Pays a token position's pending rewards and IOU to `user_haio_account` (or the wallet's
+/// vesting escrow) as far as the pool allows, records the claim with the engine and moves
+/// the position's debt to the current rate. Returns the amount paid.
+fn settle_token_rewards(
+    accounts: &mut TokenStake,
+    bumps: &TokenStakeBumps,
+    engine_state: &EngineState,
+) -> Result<u64> {
+    let mut settlement = Settlement::new(accounts.reward_pool_pda.amount);
+    let paid = settle_token_position(
+        &mut settlement,
+        engine_state,
+        &mut accounts.token_stake_state,
+    )?;
+    pay_settlement(
+        &mut reward_payout!(accounts, bumps),
+        &accounts.user_haio_account,
+        &settlement,
+    )?;
+    Ok(paid)
+}
+
+/// Credits the referrer `referral_bps` of a claim, limited to the referral budget not yet
+/// reserved for earlier credits. Returns the amount credited.
+fn credit_referral(
+    config: &mut StakingConfig,
+    referral_account: &mut ReferralAccount,
+    referral_budget: &TokenAccount,
+    claimed: u64,
+) -> Result<u64> {
+    let share = (claimed as u128)
+        .checked_mul(config.referral_bps as u128)
+        .map(|v| (v / 10000) as u64)
+        .ok_or(StakingError::CalculationError)?;
+    let available = referral_budget
+        .amount
+        .saturating_sub(config.referral_liabilities);
+    let credit = share.min(available);
+    if credit == 0 {
+        return Ok(0);
+    }
+
+    referral_account.claimable = referral_account
+        .claimable
+        .checked_add(credit)
+        .ok_or(StakingError::CalculationError)?;
+    referral_account.lifetime_earned = referral_account
+        .lifetime_earned
+        .checked_add(credit)
+        .ok_or(StakingError::CalculationError)?;
+    config.referral_liabilities = config
+        .referral_liabilities
+        .checked_add(credit)
+        .ok_or(StakingError::CalculationError)?;
+    msg!(
+        "Credited {} HAiO to referrer {} ({} short of the {} bps share)",
+        credit,
+        referral_account.referrer,
+        share - credit,
+        config.referral_bps
+    );
+    Ok(credit)
+}
+
+/// Rewards a ve lock earned since its last claim: each ve distribution at time `t` before
+/// unlock pays `rate increase * weight * (unlock - t) / MAX_VE_LOCK_SECS`. Returns the reward
+/// and the engine rates to mark as paid, which past unlock are the unlock week's snapshot.
+fn pending_ve_rewards(
+    engine_state: &EngineState,
+    ve_schedule: &VeSchedule,
+    lock: &VeLock,
+) -> Result<(u64, u128, u128)> {
+    if lock.unlock_settled {
+        return Ok((0, lock.reward_per_weight_paid, lock.reward_time_paid));
+    }
+    let Some((rate, rate_time)) = ve_schedule.rates_at_unlock(engine_state, lock.unlock_timestamp)
+    else {
+        msg!("Unlock week left the ve schedule before it counted locks; ve rewards are forfeited");
+        return Ok((
+            0,
+            engine_state.ve_reward_per_weight_cumulative,
+            engine_state.ve_reward_time_cumulative,
+        ));
+    };
+    let rate_delta = rate
+        .checked_sub(lock.reward_per_weight_paid)
+        .ok_or(StakingError::CalculationError)?;
+    let time_delta = rate_time
+        .checked_sub(lock.reward_time_paid)
+        .ok_or(StakingError::CalculationError)?;
+    let weighted_delta = rate_delta
+        .checked_mul(lock.unlock_timestamp as u128)
+        .and_then(|v| v.checked_sub(time_delta))
+        .ok_or(StakingError::CalculationError)?;
+    let reward = (weighted_delta / MAX_VE_LOCK_SECS as u128)
+        .checked_mul(lock.weight as u128)
+        .map(|v| v / PRECISION)
+        .ok_or(StakingError::CalculationError)?;
+    require!(reward <= u64::MAX as u128, StakingError::CalculationError);
+    Ok((reward as u64, rate, rate_time))
+}
+
+/// Pays a ve lock's rewards and IOU to `destination` (or its owner's vesting escrow) as far
+/// as the pool allows, keeping the rest as IOU. The first settlement past the unlock week
+/// snapshots that week's rates on the lock and releases the week in the engine's schedule.
+/// Returns the amount paid.
+fn settle_ve_rewards<'info>(
+    payout: &mut RewardPayout<'_, 'info>,
+    ve_schedule: &Account<'info, VeSchedule>,
+    lock: &mut VeLock,
+    destination: &Account<'info, TokenAccount>,
+    engine_state: &EngineState,
+) -> Result<u64> {
+    let (claimable, rate, rate_time) = pending_ve_rewards(engine_state, ve_schedule, lock)?;
+    let previous_unpaid = lock.unpaid_rewards;
+    let owed = claimable
+        .checked_add(previous_unpaid)
+        .ok_or(StakingError::CalculationError)?;
+    let paid = owed.min(payout.reward_pool_pda.amount);
+    let unpaid = owed - paid;
+
+    transfer_payout(payout, destination, paid)?;
+    if claimable > 0 || paid > 0 {
+        revenue_engine::cpi::record_ve_claim(
+            engine_cpi_ctx!(
+                payout.revenue_engine_program,
+                payout.engine_state,
+                payout.engine_caller,
+                payout.engine_caller_bump,
+            ),
+            claimable,
+            paid,
+        )?;
+    }
+    sync_reward_iou(
+        payout.revenue_engine_program,
+        payout.engine_state,
+        payout.engine_caller,
+        payout.engine_caller_bump,
+        previous_unpaid,
+        unpaid,
+    )?;
+
+    lock.reward_per_weight_paid = rate;
+    lock.reward_time_paid = rate_time;
+    lock.unpaid_rewards = unpaid;
+    if !lock.unlock_settled && Clock::get()?.unix_timestamp >= lock.unlock_timestamp {
+        lock.unlock_settled = true;
+        revenue_engine::cpi::release_ve_unlock(
+            CpiContext::new_with_signer(
+                payout.revenue_engine_program.to_account_info(),
+                revenue_engine::cpi::accounts::UpdateVeLock {
+                    engine_state: payout.engine_state.to_account_info(),
+                    ve_schedule: ve_schedule.to_account_info(),
+                    engine_caller: payout.engine_caller.to_account_info(),
+                },
+                &[&[ENGINE_CALLER_SEED, &[payout.engine_caller_bump]]],
+            ),
+            lock.unlock_timestamp,
+        )?;
+    }
+    Ok(paid)
+}
+
+/// Recomputes a settled token position's weight from its deposit and pushes the
+/// difference to the engine. Returns the new weight.
+fn apply_token_weight<'info>(
+    position: &mut TokenStakeState,
+    token_stake_unit: u64,
+    revenue_engine_program: &Program<'info, RevenueEngine>,
+    engine_state: &AccountInfo<'info>,
+    engine_caller: &AccountInfo<'info>,
+    engine_caller_bump: u8,
+) -> Result<u64> {
+    let new_weight = position
+        .deposited_amount
+        .checked_div(token_stake_unit)
+        .unwrap_or(0);
+    let old_weight = position.staked_amount;
+    if new_weight > old_weight {
+        revenue_engine::cpi::increase_total_staked(
+            engine_cpi_ctx!(
+                revenue_engine_program,
+                engine_state,
+                engine_caller,
+                engine_caller_bump,
+            ),
+            new_weight - old_weight,
+        )?;
+    } else if new_weight < old_weight {
+        revenue_engine::cpi::decrease_total_staked(
+            engine_cpi_ctx!(
+                revenue_engine_program,
+                engine_state,
+                engine_caller,
+                engine_caller_bump,
+            ),
+            old_weight - new_weight,
+        )?;
+    }
+    position.staked_amount = new_weight;
+    Ok(new_weight)
+}
+
+#[derive(Accounts)]
+pub struct StakeNft<'info> {
+    #[account(mut)]
+    pub user_wallet: Signer<'info>,
+    pub nft_mint: Account<'info, Mint>,
+
+    /// CHECK: Engine State Account. Marked mut for CPI call. Owner check done in handler.
+    #[account(mut, owner = REVENUE_ENGINE_PROGRAM_ID)]
+    pub engine_state: AccountInfo<'info>,
+
+    #[account(address = REVENUE_ENGINE_PROGRAM_ID)]
+    pub revenue_engine_program: Program<'info, RevenueEngine>,
+
+    #[account(
+        init,
+        payer = user_wallet,
+        space = 8 + NftStakeState::MAX_SIZE,
+        seeds = [b"nft_stake", user_wallet.key().as_ref(), nft_mint.key().as_ref()],
+        bump
+    )]
+    pub nft_stake_state: Account<'info, NftStakeState>,
+
+    #[account(
+        init_if_needed,
+        payer = user_wallet,
+        space = 8 + UserStakeSummary::MAX_SIZE,
+        seeds = [b"user_stake_summary", user_wallet.key().as_ref()],
+        bump
+    )]
+    pub user_stake_summary: Account<'info, UserStakeSummary>,
+
+    /// CHECK: Optional `NftWeight` registry entry; may be uninitialized (default weight).
+    #[account(seeds = [b"nft_weight", nft_mint.key().as_ref()], bump)]
+    pub nft_weight: AccountInfo<'info>,
+
+    /// Required only when staking with a lock tier.
+    #[account(seeds = [b"staking_config"], bump = staking_config.bump)]
+    pub staking_config: Option<Account<'info, StakingConfig>>,
+
+    /// CHECK: PDA that signs this program's CPIs into the revenue engine.
+    #[account(seeds = [ENGINE_CALLER_SEED], bump)]
+    pub engine_caller: AccountInfo<'info>,
+
+    /// Required when staking with a referrer.
+    pub referral_account: Option<Account<'info, ReferralAccount>>,
+
+    /// Required when tokenizing: the staker's account holding the NFT.
+    #[account(
+        mut,
+        constraint = user_nft_account.mint == nft_mint.key()
+            && user_nft_account.owner == user_wallet.key() @ StakingError::InvalidNftAccount
+    )]
+    pub user_nft_account: Option<Account<'info, TokenAccount>>,
+
+    /// Required when tokenizing: holds the NFT until the receipt holder unstakes.
+    #[account(
+        init,
+        payer = user_wallet,
+        seeds = [b"nft_vault", nft_stake_state.key().as_ref()],
+        bump,
+        token::mint = nft_mint,
+        token::authority = receipt_authority,
+    )]
+    pub nft_vault: Option<Account<'info, TokenAccount>>,
+
+    /// Required when tokenizing.
+    #[account(
+        init,
+        payer = user_wallet,
+        seeds = [b"stake_receipt", nft_stake_state.key().as_ref()],
+        bump,
+        mint::decimals = 0,
+        mint::authority = receipt_authority,
+    )]
+    pub receipt_mint: Option<Account<'info, Mint>>,
+
+    /// CHECK: Receipt mint and NFT vault authority PDA; required when tokenizing.
+    #[account(seeds = [b"receipt_authority"], bump)]
+    pub receipt_authority: Option<UncheckedAccount<'info>>,
+
+    /// Required when tokenizing.
+    #[account(
+        init,
+        payer = user_wallet,
+        associated_token::mint = receipt_mint,
+        associated_token::authority = user_wallet,
+    )]
+    pub user_receipt_account: Option<Account<'info, TokenAccount>>,
+
+    pub associated_token_program: Option<Program<'info, AssociatedToken>>,
+
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct UnstakeNft<'info> {
+    #[account(mut)]
+    pub user_wallet: Signer<'info>,
+
+    #[account(address = nft_stake_state.nft_mint)]
+    pub nft_mint: Account<'info, Mint>,
+
+    #[account(mut)]
+    pub user_haio_account: Account<'info, TokenAccount>,
+
+    /// CHECK: Engine State Account. Marked mut for CPI call. Owner verification added
+    #[account(mut, owner = REVENUE_ENGINE_PROGRAM_ID, address = nft_stake_state.engine_state_ref)]
+    pub engine_state: AccountInfo<'info>,
+
+    #[account(address = REVENUE_ENGINE_PROGRAM_ID)]
+    pub revenue_engine_program: Program<'info, RevenueEngine>,
+
+    #[account(
+        mut,
+        close = user_wallet,
+        seeds = [b"nft_stake", user_wallet.key().as_ref(), nft_mint.key().as_ref()],
+        bump = nft_stake_state.bump,
+        has_one = user_wallet,
+        has_one = nft_mint,
+    )]
+    pub nft_stake_state: Account<'info, NftStakeState>,
+
+    /// Absent for wallets whose positions predate summaries.
+    #[account(
+        mut,
+        seeds = [b"user_stake_summary", user_wallet.key().as_ref()],
+        bump = user_stake_summary.bump
+    )]
+    pub user_stake_summary: Option<Account<'info, UserStakeSummary>>,
+
+    /// CHECK: Staking config; may not exist yet for positions staked before it was set up.
+    /// Pinned by its seeds, so it cannot be left out once it exists.
+    #[account(mut, seeds = [b"staking_config"], bump)]
+    pub staking_config: UncheckedAccount<'info>,
+
+    #[account(address = nft_stake_state.engine_state_ref)]
+    pub engine_state_loader: Account<'info, EngineState>,
+
+    #[account(mut, address = engine_state_loader.reward_pool_pda)]
+    pub reward_pool_pda: Account<'info, TokenAccount>,
+
+    /// CHECK: Reward Pool Authority PDA
+    #[account(seeds = [b"reward_pool_authority_seed"], bump)]
+    pub reward_pool_authority: AccountInfo<'info>,
+
+    /// CHECK: PDA that signs this program's CPIs into the revenue engine.
+    #[account(seeds = [ENGINE_CALLER_SEED], bump)]
+    pub engine_caller: AccountInfo<'info>,
+
+    /// CHECK: Leftover claim of the user wallet; created only when a leaving position is
+    /// still owed rewards the pool cannot cover.
+    #[account(mut, seeds = [b"leftover_claim", user_wallet.key().as_ref()], bump)]
+    pub leftover_claim: UncheckedAccount<'info>,
+
+    /// Escrow of the reward destination's owner; required when vesting is enabled.
+    #[account(mut)]
+    pub vesting_escrow: Option<Account<'info, VestingEscrow>>,
+
+    /// Required when vesting is enabled.
+    #[account(mut, seeds = [b"vesting_vault"], bump)]
+    pub vesting_vault: Option<Account<'info, TokenAccount>>,
+
+    /// Required when the position has a referrer.
+    #[account(
+        mut,
+        seeds = [b"referral", nft_stake_state.referrer.as_ref()],
+        bump = referral_account.bump
+    )]
+    pub referral_account: Option<Account<'info, ReferralAccount>>,
+
+    /// Required when the position has a referrer.
+    #[account(seeds = [b"referral_budget"], bump)]
+    pub referral_budget: Option<Account<'info, TokenAccount>>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct StakeInPlace<'info> {
+    #[account(mut)]
+    pub user_wallet: Signer<'info>,
+    pub nft_mint: Account<'info, Mint>,
+
+    #[account(
+        mut,
+        constraint = user_nft_account.mint == nft_mint.key()
+            && user_nft_account.owner == user_wallet.key()
+            && user_nft_account.amount == 1 @ StakingError::InvalidNftAccount
+    )]
+    pub user_nft_account: Account<'info, TokenAccount>,
+
+    /// CHECK: Token Metadata metadata PDA of `nft_mint`; validated by seeds and by Token Metadata.
+    #[account(
+        seeds = [b"metadata", token_metadata_program.key().as_ref(), nft_mint.key().as_ref()],
+        seeds::program = token_metadata_program.key(),
+        bump
+    )]
+    pub nft_metadata: UncheckedAccount<'info>,
+
+    /// CHECK: Master edition PDA of `nft_mint` (the mint's freeze authority).
+    #[account(
+        seeds = [b"metadata", token_metadata_program.key().as_ref(), nft_mint.key().as_ref(), b"edition"],
+        seeds::program = token_metadata_program.key(),
+        bump
+    )]
+    pub nft_edition: UncheckedAccount<'info>,
+
+    /// CHECK: Program-wide delegate PDA that freezes staked NFTs in place.
+    #[account(seeds = [b"stake_delegate"], bump)]
+    pub stake_delegate: AccountInfo<'info>,
+
+    /// CHECK: Engine State Account. Marked mut for CPI call.
+    #[account(mut, owner = REVENUE_ENGINE_PROGRAM_ID)]
+    pub engine_state: AccountInfo<'info>,
+
+    #[account(address = REVENUE_ENGINE_PROGRAM_ID)]
+    pub revenue_engine_program: Program<'info, RevenueEngine>,
+
+    #[account(
+        init,
+        payer = user_wallet,
+        space = 8 + NftStakeState::MAX_SIZE,
+        seeds = [b"nft_stake", user_wallet.key().as_ref(), nft_mint.key().as_ref()],
+        bump
+    )]
+    pub nft_stake_state: Account<'info, NftStakeState>,
+
+    #[account(
+        init_if_needed,
+        payer = user_wallet,
+        space = 8 + UserStakeSummary::MAX_SIZE,
+        seeds = [b"user_stake_summary", user_wallet.key().as_ref()],
+        bump
+    )]
+    pub user_stake_summary: Account<'info, UserStakeSummary>,
+
+    /// CHECK: Optional `NftWeight` registry entry; may be uninitialized (default weight).
+    #[account(seeds = [b"nft_weight", nft_mint.key().as_ref()], bump)]
+    pub nft_weight: AccountInfo<'info>,
+
+    #[account(seeds = [b"staking_config"], bump = staking_config.bump)]
+    pub staking_config: Account<'info, StakingConfig>,
+
+    /// CHECK: PDA that signs this program's CPIs into the revenue engine.
+    #[account(seeds = [ENGINE_CALLER_SEED], bump)]
+    pub engine_caller: AccountInfo<'info>,
+
+    pub token_program: Program<'info, Token>,
+    pub token_metadata_program: Program<'info, Metadata>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UnstakeInPlace<'info> {
+    #[account(mut)]
+    pub user_wallet: Signer<'info>,
+
+    #[account(address = nft_stake_state.nft_mint)]
+    pub nft_mint: Account<'info, Mint>,
+
+    #[account(
+        mut,
+        constraint = user_nft_account.mint == nft_mint.key()
+            && user_nft_account.owner == user_wallet.key() @ StakingError::InvalidNftAccount
+    )]
+    pub user_nft_account: Account<'info, TokenAccount>,
+
+    /// CHECK: Token Metadata metadata PDA of `nft_mint`; validated by seeds and by Token Metadata.
+    #[account(
+        seeds = [b"metadata", token_metadata_program.key().as_ref(), nft_mint.key().as_ref()],
+        seeds::program = token_metadata_program.key(),
+        bump
+    )]
+    pub nft_metadata: UncheckedAccount<'info>,
+
+    /// CHECK: Master edition PDA of `nft_mint` (the mint's freeze authority).
+    #[account(
+        seeds = [b"metadata", token_metadata_program.key().as_ref(), nft_mint.key().as_ref(), b"edition"],
+        seeds::program = token_metadata_program.key(),
+        bump
+    )]
+    pub nft_edition: UncheckedAccount<'info>,
+
+    /// CHECK: Program-wide delegate PDA that freezes staked NFTs in place.
+    #[account(seeds = [b"stake_delegate"], bump)]
+    pub stake_delegate: AccountInfo<'info>,
+
+    #[account(mut)]
+    pub user_haio_account: Account<'info, TokenAccount>,
+
+    /// CHECK: Engine State Account. Marked mut for CPI call.
+    #[account(mut, owner = REVENUE_ENGINE_PROGRAM_ID, address = nft_stake_state.engine_state_ref)]
+    pub engine_state: AccountInfo<'info>,
+
+    #[account(address = REVENUE_ENGINE_PROGRAM_ID)]
+    pub revenue_engine_program: Program<'info, RevenueEngine>,
+
+    #[account(
+        mut,
+        close = user_wallet,
+        seeds = [b"nft_stake", user_wallet.key().as_ref(), nft_mint.key().as_ref()],
+        bump = nft_stake_state.bump,
+        has_one = user_wallet,
+        has_one = nft_mint,
+    )]
+    pub nft_stake_state: Account<'info, NftStakeState>,
+
+    /// Absent for wallets whose positions predate summaries.
+    #[account(
+        mut,
+        seeds = [b"user_stake_summary", user_wallet.key().as_ref()],
+        bump = user_stake_summary.bump
+    )]
+    pub user_stake_summary: Option<Account<'info, UserStakeSummary>>,
+
+    /// CHECK: Staking config; may not exist yet for positions staked before it was set up.
+    /// Pinned by its seeds, so it cannot be left out once it exists.
+    #[account(mut, seeds = [b"staking_config"], bump)]
+    pub staking_config: UncheckedAccount<'info>,
+
+    #[account(address = nft_stake_state.engine_state_ref)]
+    pub engine_state_loader: Account<'info, EngineState>,
+
+    #[account(mut, address = engine_state_loader.reward_pool_pda)]
+    pub reward_pool_pda: Account<'info, TokenAccount>,
+
+    /// CHECK: Reward Pool Authority PDA
+    #[account(seeds = [b"reward_pool_authority_seed"], bump)]
+    pub reward_pool_authority: AccountInfo<'info>,
+
+    /// CHECK: PDA that signs this program's CPIs into the revenue engine.
+    #[account(seeds = [ENGINE_CALLER_SEED], bump)]
+    pub engine_caller: AccountInfo<'info>,
+
+    /// Escrow of the reward destination's owner; required when vesting is enabled.
+    #[account(mut)]
+    pub vesting_escrow: Option<Account<'info, VestingEscrow>>,
+
+    /// Required when vesting is enabled.
+    #[account(mut, seeds = [b"vesting_vault"], bump)]
+    pub vesting_vault: Option<Account<'info, TokenAccount>>,
+
+    /// CHECK: Leftover claim of the user wallet; created only when a leaving position is
+    /// still owed rewards the pool cannot cover.
+    #[account(mut, seeds = [b"leftover_claim", user_wallet.key().as_ref()], bump)]
+    pub leftover_claim: UncheckedAccount<'info>,
+
+    /// Required when the position has a referrer.
+    #[account(
+        mut,
+        seeds = [b"referral", nft_stake_state.referrer.as_ref()],
+        bump = referral_account.bump
+    )]
+    pub referral_account: Option<Account<'info, ReferralAccount>>,
+
+    /// Required when the position has a referrer.
+    #[account(seeds = [b"referral_budget"], bump)]
+    pub referral_budget: Option<Account<'info, TokenAccount>>,
+
+    pub token_program: Program<'info, Token>,
+    pub token_metadata_program: Program<'info, Metadata>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct StakePnft<'info> {
+    #[account(mut)]
+    pub user_wallet: Signer<'info>,
+    pub nft_mint: Account<'info, Mint>,
+
+    #[account(
+        mut,
+        constraint = user_nft_account.mint == nft_mint.key()
+            && user_nft_account.owner == user_wallet.key()
+            && user_nft_account.amount == 1 @ StakingError::InvalidNftAccount
+    )]
+    pub user_nft_account: Account<'info, TokenAccount>,
+
+    /// CHECK: Token Metadata metadata PDA of `nft_mint`; deserialized and checked in the handler.
+    #[account(
+        mut,
+        seeds = [b"metadata", token_metadata_program.key().as_ref(), nft_mint.key().as_ref()],
+        seeds::program = token_metadata_program.key(),
+        bump
+    )]
+    pub nft_metadata: UncheckedAccount<'info>,
+
+    /// CHECK: Master edition PDA of `nft_mint`.
+    #[account(
+        seeds = [b"metadata", token_metadata_program.key().as_ref(), nft_mint.key().as_ref(), b"edition"],
+        seeds::program = token_metadata_program.key(),
+        bump
+    )]
+    pub nft_edition: UncheckedAccount<'info>,
+
+    /// CHECK: Token record PDA of `user_nft_account`; holds the pNFT delegate and lock state.
+    #[account(
+        mut,
+        seeds = [
+            b"metadata",
+            token_metadata_program.key().as_ref(),
+            nft_mint.key().as_ref(),
+            b"token_record",
+            user_nft_account.key().as_ref()
+        ],
+        seeds::program = token_metadata_program.key(),
+        bump
+    )]
+    pub token_record: UncheckedAccount<'info>,
+
+    /// CHECK: Program-wide delegate PDA that locks staked pNFTs.
+    #[account(seeds = [b"stake_delegate"], bump)]
+    pub stake_delegate: AccountInfo<'info>,
+
+    /// CHECK: Token Auth Rules program; only required when the pNFT has a rule set.
+    #[account(address = TOKEN_AUTH_RULES_PROGRAM_ID)]
+    pub authorization_rules_program: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: Rule set named in the pNFT's programmable config; matched in the handler.
+    #[account(owner = TOKEN_AUTH_RULES_PROGRAM_ID)]
+    pub authorization_rules: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: Instructions sysvar, required by Token Metadata for pNFT operations.
+    #[account(address = anchor_lang::solana_program::sysvar::instructions::ID)]
+    pub sysvar_instructions: UncheckedAccount<'info>,
+
+    /// CHECK: Engine State Account. Marked mut for CPI call.
+    #[account(mut, owner = REVENUE_ENGINE_PROGRAM_ID)]
+    pub engine_state: AccountInfo<'info>,
+
+    #[account(address = REVENUE_ENGINE_PROGRAM_ID)]
+    pub revenue_engine_program: Program<'info, RevenueEngine>,
+
+    #[account(
+        init,
+        payer = user_wallet,
+        space = 8 + NftStakeState::MAX_SIZE,
+        seeds = [b"nft_stake", user_wallet.key().as_ref(), nft_mint.key().as_ref()],
+        bump
+    )]
+    pub nft_stake_state: Account<'info, NftStakeState>,
+
+    #[account(
+        init_if_needed,
+        payer = user_wallet,
+        space = 8 + UserStakeSummary::MAX_SIZE,
+        seeds = [b"user_stake_summary", user_wallet.key().as_ref()],
+        bump
+    )]
+    pub user_stake_summary: Account<'info, UserStakeSummary>,
+
+    /// CHECK: Optional `NftWeight` registry entry; may be uninitialized (default weight).
+    #[account(seeds = [b"nft_weight", nft_mint.key().as_ref()], bump)]
+    pub nft_weight: AccountInfo<'info>,
+
+    #[account(seeds = [b"staking_config"], bump = staking_config.bump)]
+    pub staking_config: Account<'info, StakingConfig>,
+
+    /// CHECK: PDA that signs this program's CPIs into the revenue engine.
+    #[account(seeds = [ENGINE_CALLER_SEED], bump)]
+    pub engine_caller: AccountInfo<'info>,
+
+    pub token_program: Program<'info, Token>,
+    pub token_metadata_program: Program<'info, Metadata>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UnstakePnft<'info> {
+    #[account(mut)]
+    pub user_wallet: Signer<'info>,
+
+    #[account(address = nft_stake_state.nft_mint)]
+    pub nft_mint: Account<'info, Mint>,
+
+    #[account(
+        mut,
+        constraint = user_nft_account.mint == nft_mint.key()
+            && user_nft_account.owner == user_wallet.key() @ StakingError::InvalidNftAccount
+    )]
+    pub user_nft_account: Account<'info, TokenAccount>,
+
+    /// CHECK: Token Metadata metadata PDA of `nft_mint`; deserialized and checked in the handler.
+    #[account(
+        mut,
+        seeds = [b"metadata", token_metadata_program.key().as_ref(), nft_mint.key().as_ref()],
+        seeds::program = token_metadata_program.key(),
+        bump
+    )]
+    pub nft_metadata: UncheckedAccount<'info>,
+
+    /// CHECK: Master edition PDA of `nft_mint`.
+    #[account(
+        seeds = [b"metadata", token_metadata_program.key().as_ref(), nft_mint.key().as_ref(), b"edition"],
+        seeds::program = token_metadata_program.key(),
+        bump
+    )]
+    pub nft_edition: UncheckedAccount<'info>,
+
+    /// CHECK: Token record PDA of `user_nft_account`; holds the pNFT delegate and lock state.
+    #[account(
+        mut,
+        seeds = [
+            b"metadata",
+            token_metadata_program.key().as_ref(),
+            nft_mint.key().as_ref(),
+            b"token_record",
+            user_nft_account.key().as_ref()
+        ],
+        seeds::program = token_metadata_program.key(),
+        bump
+    )]
+    pub token_record: UncheckedAccount<'info>,
+
+    /// CHECK: Program-wide delegate PDA that locks staked pNFTs.
+    #[account(seeds = [b"stake_delegate"], bump)]
+    pub stake_delegate: AccountInfo<'info>,
+
+    /// CHECK: Token Auth Rules program; only required when the pNFT has a rule set.
+    #[account(address = TOKEN_AUTH_RULES_PROGRAM_ID)]
+    pub authorization_rules_program: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: Rule set named in the pNFT's programmable config; matched in the handler.
+    #[account(owner = TOKEN_AUTH_RULES_PROGRAM_ID)]
+    pub authorization_rules: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: Instructions sysvar, required by Token Metadata for pNFT operations.
+    #[account(address = anchor_lang::solana_program::sysvar::instructions::ID)]
+    pub sysvar_instructions: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub user_haio_account: Account<'info, TokenAccount>,
+
+    /// CHECK: Engine State Account. Marked mut for CPI call.
+    #[account(mut, owner = REVENUE_ENGINE_PROGRAM_ID, address = nft_stake_state.engine_state_ref)]
+    pub engine_state: AccountInfo<'info>,
+
+    #[account(address = REVENUE_ENGINE_PROGRAM_ID)]
+    pub revenue_engine_program: Program<'info, RevenueEngine>,
+
+    #[account(
+        mut,
+        close = user_wallet,
+        seeds = [b"nft_stake", user_wallet.key().as_ref(), nft_mint.key().as_ref()],
+        bump = nft_stake_state.bump,
+        has_one = user_wallet,
+        has_one = nft_mint,
+    )]
+    pub nft_stake_state: Account<'info, NftStakeState>,
+
+    /// Absent for wallets whose positions predate summaries.
+    #[account(
+        mut,
+        seeds = [b"user_stake_summary", user_wallet.key().as_ref()],
+        bump = user_stake_summary.bump
+    )]
+    pub user_stake_summary: Option<Account<'info, UserStakeSummary>>,
+
+    /// CHECK: Staking config; may not exist yet for positions staked before it was set up.
+    /// Pinned by its seeds, so it cannot be left out once it exists.
+    #[account(mut, seeds = [b"staking_config"], bump)]
+    pub staking_config: UncheckedAccount<'info>,
+
+    #[account(address = nft_stake_state.engine_state_ref)]
+    pub engine_state_loader: Account<'info, EngineState>,
+
+    #[account(mut, address = engine_state_loader.reward_pool_pda)]
+    pub reward_pool_pda: Account<'info, TokenAccount>,
+
+    /// CHECK: Reward Pool Authority PDA
+    #[account(seeds = [b"reward_pool_authority_seed"], bump)]
+    pub reward_pool_authority: AccountInfo<'info>,
+
+    /// CHECK: PDA that signs this program's CPIs into the revenue engine.
+    #[account(seeds = [ENGINE_CALLER_SEED], bump)]
+    pub engine_caller: AccountInfo<'info>,
+
+    /// Escrow of the reward destination's owner; required when vesting is enabled.
+    #[account(mut)]
+    pub vesting_escrow: Option<Account<'info, VestingEscrow>>,
+
+    /// Required when vesting is enabled.
+    #[account(mut, seeds = [b"vesting_vault"], bump)]
+    pub vesting_vault: Option<Account<'info, TokenAccount>>,
+
+    /// CHECK: Leftover claim of the user wallet; created only when a leaving position is
+    /// still owed rewards the pool cannot cover.
+    #[account(mut, seeds = [b"leftover_claim", user_wallet.key().as_ref()], bump)]
+    pub leftover_claim: UncheckedAccount<'info>,
+
+    /// Required when the position has a referrer.
+    #[account(
+        mut,
+        seeds = [b"referral", nft_stake_state.referrer.as_ref()],
+        bump = referral_account.bump
+    )]
+    pub referral_account: Option<Account<'info, ReferralAccount>>,
+
+    /// Required when the position has a referrer.
+    #[account(seeds = [b"referral_budget"], bump)]
+    pub referral_budget: Option<Account<'info, TokenAccount>>,
+
+    pub token_program: Program<'info, Token>,
+    pub token_metadata_program: Program<'info, Metadata>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(asset_id: Pubkey)]
+pub struct StakeCnft<'info> {
+    #[account(mut)]
+    pub user_wallet: Signer<'info>,
+
+    /// CHECK: Bubblegum tree config PDA of `merkle_tree`.
+    #[account(
+        mut,
+        seeds = [merkle_tree.key().as_ref()],
+        seeds::program = bubblegum_program.key(),
+        bump
+    )]
+    pub tree_config: UncheckedAccount<'info>,
+
+    /// CHECK: Concurrent Merkle tree holding the leaf; proof is verified by Account Compression.
+    #[account(mut, owner = SPL_ACCOUNT_COMPRESSION_ID)]
+    pub merkle_tree: UncheckedAccount<'info>,
+
+    /// CHECK: Program PDA that owns escrowed compressed NFTs.
+    #[account(seeds = [b"cnft_vault"], bump)]
+    pub cnft_vault: AccountInfo<'info>,
+
+    /// CHECK: Bubblegum program.
+    #[account(address = mpl_bubblegum::ID)]
+    pub bubblegum_program: UncheckedAccount<'info>,
+
+    /// CHECK: SPL Noop program used by Bubblegum for leaf logs.
+    #[account(address = SPL_NOOP_ID)]
+    pub log_wrapper: UncheckedAccount<'info>,
+
+    /// CHECK: SPL Account Compression program.
+    #[account(address = SPL_ACCOUNT_COMPRESSION_ID)]
+    pub compression_program: UncheckedAccount<'info>,
+
+    /// CHECK: Engine State Account. Marked mut for CPI call.
+    #[account(mut, owner = REVENUE_ENGINE_PROGRAM_ID)]
+    pub engine_state: AccountInfo<'info>,
+
+    #[account(address = REVENUE_ENGINE_PROGRAM_ID)]
+    pub revenue_engine_program: Program<'info, RevenueEngine>,
+
+    #[account(
+        init,
+        payer = user_wallet,
+        space = 8 + NftStakeState::MAX_SIZE,
+        seeds = [b"nft_stake", user_wallet.key().as_ref(), asset_id.as_ref()],
+        bump
+    )]
+    pub nft_stake_state: Account<'info, NftStakeState>,
+
+    #[account(
+        init_if_needed,
+        payer = user_wallet,
+        space = 8 + UserStakeSummary::MAX_SIZE,
+        seeds = [b"user_stake_summary", user_wallet.key().as_ref()],
+        bump
+    )]
+    pub user_stake_summary: Account<'info, UserStakeSummary>,
+
+    /// CHECK: Optional `NftWeight` registry entry keyed by asset ID; may be uninitialized.
+    #[account(seeds = [b"nft_weight", asset_id.as_ref()], bump)]
+    pub nft_weight: AccountInfo<'info>,
+
+    #[account(seeds = [b"staking_config"], bump = staking_config.bump)]
+    pub staking_config: Account<'info, StakingConfig>,
+
+    /// CHECK: PDA that signs this program's CPIs into the revenue engine.
+    #[account(seeds = [ENGINE_CALLER_SEED], bump)]
+    pub engine_caller: AccountInfo<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UnstakeCnft<'info> {
+    #[account(mut)]
+    pub user_wallet: Signer<'info>,
+
+    /// CHECK: Bubblegum tree config PDA of `merkle_tree`.
+    #[account(
+        mut,
+        seeds = [merkle_tree.key().as_ref()],
+        seeds::program = bubblegum_program.key(),
+        bump
+    )]
+    pub tree_config: UncheckedAccount<'info>,
+
+    /// CHECK: Concurrent Merkle tree holding the leaf; proof is verified by Account Compression.
+    #[account(mut, owner = SPL_ACCOUNT_COMPRESSION_ID)]
+    pub merkle_tree: UncheckedAccount<'info>,
+
+    /// CHECK: Program PDA that owns escrowed compressed NFTs.
+    #[account(seeds = [b"cnft_vault"], bump)]
+    pub cnft_vault: AccountInfo<'info>,
+
+    /// CHECK: Bubblegum program.
+    #[account(address = mpl_bubblegum::ID)]
+    pub bubblegum_program: UncheckedAccount<'info>,
+
+    /// CHECK: SPL Noop program used by Bubblegum for leaf logs.
+    #[account(address = SPL_NOOP_ID)]
+    pub log_wrapper: UncheckedAccount<'info>,
+
+    /// CHECK: SPL Account Compression program.
+    #[account(address = SPL_ACCOUNT_COMPRESSION_ID)]
+    pub compression_program: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub user_haio_account: Account<'info, TokenAccount>,
+
+    /// CHECK: Engine State Account. Marked mut for CPI call.
+    #[account(mut, owner = REVENUE_ENGINE_PROGRAM_ID, address = nft_stake_state.engine_state_ref)]
+    pub engine_state: AccountInfo<'info>,
+
+    #[account(address = REVENUE_ENGINE_PROGRAM_ID)]
+    pub revenue_engine_program: Program<'info, RevenueEngine>,
+
+    #[account(
+        mut,
+        close = user_wallet,
+        seeds = [b"nft_stake", user_wallet.key().as_ref(), nft_stake_state.nft_mint.as_ref()],
+        bump = nft_stake_state.bump,
+        has_one = user_wallet,
+    )]
+    pub nft_stake_state: Account<'info, NftStakeState>,
+
+    /// Absent for wallets whose positions predate summaries.
+    #[account(
+        mut,
+        seeds = [b"user_stake_summary", user_wallet.key().as_ref()],
+        bump = user_stake_summary.bump
+    )]
+    pub user_stake_summary: Option<Account<'info, UserStakeSummary>>,
+
+    /// CHECK: Staking config; may not exist yet for positions staked before it was set up.
+    /// Pinned by its seeds, so it cannot be left out once it exists.
+    #[account(mut, seeds = [b"staking_config"], bump)]
+    pub staking_config: UncheckedAccount<'info>,
+
+    #[account(address = nft_stake_state.engine_state_ref)]
+    pub engine_state_loader: Account<'info, EngineState>,
+
+    #[account(mut, address = engine_state_loader.reward_pool_pda)]
+    pub reward_pool_pda: Account<'info, TokenAccount>,
+
+    /// CHECK: Reward Pool Authority PDA
+    #[account(seeds = [b"reward_pool_authority_seed"], bump)]
+    pub reward_pool_authority: AccountInfo<'info>,
+
+    /// CHECK: PDA that signs this program's CPIs into the revenue engine.
+    #[account(seeds = [ENGINE_CALLER_SEED], bump)]
+    pub engine_caller: AccountInfo<'info>,
+
+    /// Escrow of the reward destination's owner; required when vesting is enabled.
+    #[account(mut)]
+    pub vesting_escrow: Option<Account<'info, VestingEscrow>>,
+
+    /// Required when vesting is enabled.
+    #[account(mut, seeds = [b"vesting_vault"], bump)]
+    pub vesting_vault: Option<Account<'info, TokenAccount>>,
+
+    /// CHECK: Leftover claim of the user wallet; created only when a leaving position is
+    /// still owed rewards the pool cannot cover.
+    #[account(mut, seeds = [b"leftover_claim", user_wallet.key().as_ref()], bump)]
+    pub leftover_claim: UncheckedAccount<'info>,
+
+    /// Required when the position has a referrer.
+    #[account(
+        mut,
+        seeds = [b"referral", nft_stake_state.referrer.as_ref()],
+        bump = referral_account.bump
+    )]
+    pub referral_account: Option<Account<'info, ReferralAccount>>,
+
+    /// Required when the position has a referrer.
+    #[account(seeds = [b"referral_budget"], bump)]
+    pub referral_budget: Option<Account<'info, TokenAccount>>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct StakeCore<'info> {
+    #[account(mut)]
+    pub user_wallet: Signer<'info>,
+
+    /// CHECK: Core asset; owner and collection are checked in the handler.
+    #[account(mut, owner = mpl_core::ID)]
+    pub asset: UncheckedAccount<'info>,
+
+    /// CHECK: Core collection of `asset`; must equal `staking_config.core_collection`.
+    #[account(mut, owner = mpl_core::ID)]
+    pub collection: UncheckedAccount<'info>,
+
+    /// CHECK: Program-wide delegate PDA that holds the freeze plugin authority.
+    #[account(seeds = [b"stake_delegate"], bump)]
+    pub stake_delegate: AccountInfo<'info>,
+
+    /// CHECK: Metaplex Core program.
+    #[account(address = mpl_core::ID)]
+    pub core_program: UncheckedAccount<'info>,
+
+    /// CHECK: Engine State Account. Marked mut for CPI call.
+    #[account(mut, owner = REVENUE_ENGINE_PROGRAM_ID)]
+    pub engine_state: AccountInfo<'info>,
+
+    #[account(address = REVENUE_ENGINE_PROGRAM_ID)]
+    pub revenue_engine_program: Program<'info, RevenueEngine>,
+
+    #[account(
+        init,
+        payer = user_wallet,
+        space = 8 + NftStakeState::MAX_SIZE,
+        seeds = [b"nft_stake", user_wallet.key().as_ref(), asset.key().as_ref()],
+        bump
+    )]
+    pub nft_stake_state: Account<'info, NftStakeState>,
+
+    #[account(
+        init_if_needed,
+        payer = user_wallet,
+        space = 8 + UserStakeSummary::MAX_SIZE,
+        seeds = [b"user_stake_summary", user_wallet.key().as_ref()],
+        bump
+    )]
+    pub user_stake_summary: Account<'info, UserStakeSummary>,
+
+    /// CHECK: Optional `NftWeight` registry entry keyed by asset; may be uninitialized.
+    #[account(seeds = [b"nft_weight", asset.key().as_ref()], bump)]
+    pub nft_weight: AccountInfo<'info>,
+
+    #[account(seeds = [b"staking_config"], bump = staking_config.bump)]
+    pub staking_config: Account<'info, StakingConfig>,
+
+    /// CHECK: PDA that signs this program's CPIs into the revenue engine.
+    #[account(seeds = [ENGINE_CALLER_SEED], bump)]
+    pub engine_caller: AccountInfo<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UnstakeCore<'info> {
+    #[account(mut)]
+    pub user_wallet: Signer<'info>,
+
+    /// CHECK: Core asset recorded in the position.
+    #[account(mut, owner = mpl_core::ID, address = nft_stake_state.nft_mint)]
+    pub asset: UncheckedAccount<'info>,
+
+    /// CHECK: Core collection the asset was staked under; the config may have changed since.
+    #[account(mut, owner = mpl_core::ID, address = nft_stake_state.collection)]
+    pub collection: UncheckedAccount<'info>,
+
+    /// CHECK: Program-wide delegate PDA that holds the freeze plugin authority.
+    #[account(seeds = [b"stake_delegate"], bump)]
+    pub stake_delegate: AccountInfo<'info>,
+
+    /// CHECK: Metaplex Core program.
+    #[account(address = mpl_core::ID)]
+    pub core_program: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub user_haio_account: Account<'info, TokenAccount>,
+
+    /// CHECK: Engine State Account. Marked mut for CPI call.
+    #[account(mut, owner = REVENUE_ENGINE_PROGRAM_ID, address = nft_stake_state.engine_state_ref)]
+    pub engine_state: AccountInfo<'info>,
+
+    #[account(address = REVENUE_ENGINE_PROGRAM_ID)]
+    pub revenue_engine_program: Program<'info, RevenueEngine>,
+
+    #[account(
+        mut,
+        close = user_wallet,
+        seeds = [b"nft_stake", user_wallet.key().as_ref(), asset.key().as_ref()],
+        bump = nft_stake_state.bump,
+        has_one = user_wallet,
+    )]
+    pub nft_stake_state: Account<'info, NftStakeState>,
+
+    /// Absent for wallets whose positions predate summaries.
+    #[account(
+        mut,
+        seeds = [b"user_stake_summary", user_wallet.key().as_ref()],
+        bump = user_stake_summary.bump
+    )]
+    pub user_stake_summary: Option<Account<'info, UserStakeSummary>>,
+
+    /// CHECK: Staking config; may not exist yet for positions staked before it was set up.
+    /// Pinned by its seeds, so it cannot be left out once it exists.
+    #[account(mut, seeds = [b"staking_config"], bump)]
+    pub staking_config: UncheckedAccount<'info>,
+
+    #[account(address = nft_stake_state.engine_state_ref)]
+    pub engine_state_loader: Account<'info, EngineState>,
+
+    #[account(mut, address = engine_state_loader.reward_pool_pda)]
+    pub reward_pool_pda: Account<'info, TokenAccount>,
+
+    /// CHECK: Reward Pool Authority PDA
+    #[account(seeds = [b"reward_pool_authority_seed"], bump)]
+    pub reward_pool_authority: AccountInfo<'info>,
+
+    /// CHECK: PDA that signs this program's CPIs into the revenue engine.
+    #[account(seeds = [ENGINE_CALLER_SEED], bump)]
+    pub engine_caller: AccountInfo<'info>,
+
+    /// Escrow of the reward destination's owner; required when vesting is enabled.
+    #[account(mut)]
+    pub vesting_escrow: Option<Account<'info, VestingEscrow>>,
+
+    /// Required when vesting is enabled.
+    #[account(mut, seeds = [b"vesting_vault"], bump)]
+    pub vesting_vault: Option<Account<'info, TokenAccount>>,
+
+    /// CHECK: Leftover claim of the user wallet; created only when a leaving position is
+    /// still owed rewards the pool cannot cover.
+    #[account(mut, seeds = [b"leftover_claim", user_wallet.key().as_ref()], bump)]
+    pub leftover_claim: UncheckedAccount<'info>,
+
+    /// Required when the position has a referrer.
+    #[account(
+        mut,
+        seeds = [b"referral", nft_stake_state.referrer.as_ref()],
+        bump = referral_account.bump
+    )]
+    pub referral_account: Option<Account<'info, ReferralAccount>>,
+
+    /// Required when the position has a referrer.
+    #[account(seeds = [b"referral_budget"], bump)]
+    pub referral_budget: Option<Account<'info, TokenAccount>>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct TokenStake<'info> {
+    #[account(mut)]
+    pub user_wallet: Signer<'info>,
+
+    #[account(
+        mut,
+        constraint = user_haio_account.mint == haio_mint.key()
+            && user_haio_account.owner == user_wallet.key() @ StakingError::Unauthorized
+    )]
+    pub user_haio_account: Account<'info, TokenAccount>,
+
+    #[account(seeds = [b"staking_config"], bump = staking_config.bump)]
+    pub staking_config: Account<'info, StakingConfig>,
+
+    /// CHECK: Engine State Account. Marked mut for CPI call.
+    #[account(mut, owner = REVENUE_ENGINE_PROGRAM_ID, address = staking_config.engine_state_ref)]
+    pub engine_state: AccountInfo<'info>,
+
+    #[account(address = staking_config.engine_state_ref)]
+    pub engine_state_loader: Account<'info, EngineState>,
+
+    #[account(mut, address = engine_state_loader.reward_pool_pda)]
+    pub reward_pool_pda: Account<'info, TokenAccount>,
+
+    /// CHECK: Reward Pool Authority PDA
+    #[account(seeds = [b"reward_pool_authority_seed"], bump)]
+    pub reward_pool_authority: AccountInfo<'info>,
+
+    #[account(address = reward_pool_pda.mint)]
+    pub haio_mint: Account<'info, Mint>,
+
+    /// CHECK: PDA that owns the fungible stake vault.
+    #[account(seeds = [b"haio_vault_authority"], bump)]
+    pub haio_vault_authority: AccountInfo<'info>,
+
+    #[account(
+        init_if_needed,
+        payer = user_wallet,
+        seeds = [b"haio_stake_vault"],
+        bump,
+        token::mint = haio_mint,
+        token::authority = haio_vault_authority,
+    )]
+    pub haio_stake_vault: Account<'info, TokenAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = user_wallet,
+        space = 8 + TokenStakeState::MAX_SIZE,
+        seeds = [b"token_stake", user_wallet.key().as_ref()],
+        bump
+    )]
+    pub token_stake_state: Account<'info, TokenStakeState>,
+
+    #[account(address = REVENUE_ENGINE_PROGRAM_ID)]
+    pub revenue_engine_program: Program<'info, RevenueEngine>,
+
+    /// CHECK: PDA that signs this program's CPIs into the revenue engine.
+    #[account(seeds = [ENGINE_CALLER_SEED], bump)]
+    pub engine_caller: AccountInfo<'info>,
+
+    /// Escrow of the reward destination's owner; required when vesting is enabled.
+    #[account(mut)]
+    pub vesting_escrow: Option<Account<'info, VestingEscrow>>,
+
+    /// Required when vesting is enabled.
+    #[account(mut, seeds = [b"vesting_vault"], bump)]
+    pub vesting_vault: Option<Account<'info, TokenAccount>>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct VeLockAction<'info> {
+    #[account(mut)]
+    pub user_wallet: Signer<'info>,
+
+    #[account(
+        mut,
+        constraint = user_haio_account.mint == haio_mint.key()
+            && user_haio_account.owner == user_wallet.key() @ StakingError::Unauthorized
+    )]
+    pub user_haio_account: Account<'info, TokenAccount>,
+
+    #[account(seeds = [b"staking_config"], bump = staking_config.bump)]
+    pub staking_config: Account<'info, StakingConfig>,
+
+    /// CHECK: Engine State Account. Marked mut for CPI call.
+    #[account(mut, owner = REVENUE_ENGINE_PROGRAM_ID, address = staking_config.engine_state_ref)]
+    pub engine_state: AccountInfo<'info>,
+
+    #[account(address = staking_config.engine_state_ref)]
+    pub engine_state_loader: Account<'info, EngineState>,
+
+    #[account(
+        mut,
+        constraint = ve_schedule.engine_state_ref == engine_state.key() @ StakingError::Unauthorized
+    )]
+    pub ve_schedule: Box<Account<'info, VeSchedule>>,
+
+    #[account(mut, address = engine_state_loader.reward_pool_pda)]
+    pub reward_pool_pda: Account<'info, TokenAccount>,
+
+    /// CHECK: Reward Pool Authority PDA
+    #[account(seeds = [b"reward_pool_authority_seed"], bump)]
+    pub reward_pool_authority: AccountInfo<'info>,
+
+    #[account(address = reward_pool_pda.mint)]
+    pub haio_mint: Account<'info, Mint>,
+
+    /// CHECK: PDA that owns the fungible stake vault.
+    #[account(seeds = [b"haio_vault_authority"], bump)]
+    pub haio_vault_authority: AccountInfo<'info>,
+
+    #[account(
+        init_if_needed,
+        payer = user_wallet,
+        seeds = [b"haio_stake_vault"],
+        bump,
+        token::mint = haio_mint,
+        token::authority = haio_vault_authority,
+    )]
+    pub haio_stake_vault: Account<'info, TokenAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = user_wallet,
+        space = 8 + VeLock::MAX_SIZE,
+        seeds = [b"ve_lock", user_wallet.key().as_ref()],
+        bump
+    )]
+    pub ve_lock: Account<'info, VeLock>,
+
+    #[account(address = REVENUE_ENGINE_PROGRAM_ID)]
+    pub revenue_engine_program: Program<'info, RevenueEngine>,
+
+    /// CHECK: PDA that signs this program's CPIs into the revenue engine.
+    #[account(seeds = [ENGINE_CALLER_SEED], bump)]
+    pub engine_caller: AccountInfo<'info>,
+
+    /// Escrow of the reward destination's owner; required when vesting is enabled.
+    #[account(mut)]
+    pub vesting_escrow: Option<Account<'info, VestingEscrow>>,
+
+    /// Required when vesting is enabled.
+    #[account(mut, seeds = [b"vesting_vault"], bump)]
+    pub vesting_vault: Option<Account<'info, TokenAccount>>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct NftVeLockAction<'info> {
+    #[account(mut)]
+    pub user_wallet: Signer<'info>,
+
+    #[account(
+        mut,
+        constraint = user_haio_account.mint == haio_mint.key()
+            && user_haio_account.owner == user_wallet.key() @ StakingError::Unauthorized
+    )]
+    pub user_haio_account: Account<'info, TokenAccount>,
+
+    #[account(seeds = [b"staking_config"], bump = staking_config.bump)]
+    pub staking_config: Account<'info, StakingConfig>,
+
+    /// CHECK: Engine State Account. Marked mut for CPI call.
+    #[account(mut, owner = REVENUE_ENGINE_PROGRAM_ID, address = nft_stake_state.engine_state_ref)]
+    pub engine_state: AccountInfo<'info>,
+
+    #[account(address = nft_stake_state.engine_state_ref)]
+    pub engine_state_loader: Account<'info, EngineState>,
+
+    #[account(
+        mut,
+        constraint = ve_schedule.engine_state_ref == engine_state.key() @ StakingError::Unauthorized
+    )]
+    pub ve_schedule: Box<Account<'info, VeSchedule>>,
+
+    #[account(mut, address = engine_state_loader.reward_pool_pda)]
+    pub reward_pool_pda: Account<'info, TokenAccount>,
+
+    /// CHECK: Reward Pool Authority PDA
+    #[account(seeds = [b"reward_pool_authority_seed"], bump)]
+    pub reward_pool_authority: AccountInfo<'info>,
+
+    #[account(address = reward_pool_pda.mint)]
+    pub haio_mint: Account<'info, Mint>,
+
+    #[account(
+        mut,
+        seeds = [b"nft_stake", user_wallet.key().as_ref(), nft_stake_state.nft_mint.as_ref()],
+        bump = nft_stake_state.bump,
+        has_one = user_wallet
+    )]
+    pub nft_stake_state: Account<'info, NftStakeState>,
+
+    /// Absent for wallets whose positions predate summaries.
+    #[account(
+        mut,
+        seeds = [b"user_stake_summary", user_wallet.key().as_ref()],
+        bump = user_stake_summary.bump
+    )]
+    pub user_stake_summary: Option<Account<'info, UserStakeSummary>>,
+
+    #[account(
+        init_if_needed,
+        payer = user_wallet,
+        space = 8 + VeLock::MAX_SIZE,
+        seeds = [b"ve_lock", nft_stake_state.key().as_ref()],
+        bump
+    )]
+    pub ve_lock: Account<'info, VeLock>,
+
+    #[account(address = REVENUE_ENGINE_PROGRAM_ID)]
+    pub revenue_engine_program: Program<'info, RevenueEngine>,
+
+    /// CHECK: PDA that signs this program's CPIs into the revenue engine.
+    #[account(seeds = [ENGINE_CALLER_SEED], bump)]
+    pub engine_caller: AccountInfo<'info>,
+
+    /// Escrow of the reward destination's owner; required when vesting is enabled.
+    #[account(mut)]
+    pub vesting_escrow: Option<Account<'info, VestingEscrow>>,
+
+    /// Required when vesting is enabled.
+    #[account(mut, seeds = [b"vesting_vault"], bump)]
+    pub vesting_vault: Option<Account<'info, TokenAccount>>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+/// `NftVeLockAction` plus the leftover claim that takes the lock's IOU when it closes.
+#[derive(Accounts)]
+pub struct ReleaseNftVeLock<'info> {
+    #[account(mut)]
+    pub user_wallet: Signer<'info>,
+
+    #[account(
+        mut,
+        constraint = user_haio_account.mint == haio_mint.key()
+            && user_haio_account.owner == user_wallet.key() @ StakingError::Unauthorized
+    )]
+    pub user_haio_account: Account<'info, TokenAccount>,
+
+    #[account(seeds = [b"staking_config"], bump = staking_config.bump)]
+    pub staking_config: Account<'info, StakingConfig>,
+
+    /// CHECK: Engine State Account. Marked mut for CPI call.
+    #[account(mut, owner = REVENUE_ENGINE_PROGRAM_ID, address = nft_stake_state.engine_state_ref)]
+    pub engine_state: AccountInfo<'info>,
+
+    #[account(address = nft_stake_state.engine_state_ref)]
+    pub engine_state_loader: Account<'info, EngineState>,
+
+    #[account(
+        mut,
+        constraint = ve_schedule.engine_state_ref == engine_state.key() @ StakingError::Unauthorized
+    )]
+    pub ve_schedule: Box<Account<'info, VeSchedule>>,
+
+    #[account(mut, address = engine_state_loader.reward_pool_pda)]
+    pub reward_pool_pda: Account<'info, TokenAccount>,
+
+    /// CHECK: Reward Pool Authority PDA
+    #[account(seeds = [b"reward_pool_authority_seed"], bump)]
+    pub reward_pool_authority: AccountInfo<'info>,
+
+    #[account(address = reward_pool_pda.mint)]
+    pub haio_mint: Account<'info, Mint>,
+
+    #[account(
+        mut,
+        seeds = [b"nft_stake", user_wallet.key().as_ref(), nft_stake_state.nft_mint.as_ref()],
+        bump = nft_stake_state.bump,
+        has_one = user_wallet
+    )]
+    pub nft_stake_state: Account<'info, NftStakeState>,
+
+    /// Absent for wallets whose positions predate summaries.
+    #[account(
+        mut,
+        seeds = [b"user_stake_summary", user_wallet.key().as_ref()],
+        bump = user_stake_summary.bump
+    )]
+    pub user_stake_summary: Option<Account<'info, UserStakeSummary>>,
+
+    #[account(
+        mut,
+        seeds = [b"ve_lock", nft_stake_state.key().as_ref()],
+        bump = ve_lock.bump
+    )]
+    pub ve_lock: Account<'info, VeLock>,
+
+    /// CHECK: Leftover claim of the user wallet; created only when a leaving position is
+    /// still owed rewards the pool cannot cover.
+    #[account(mut, seeds = [b"leftover_claim", user_wallet.key().as_ref()], bump)]
+    pub leftover_claim: UncheckedAccount<'info>,
+
+    #[account(address = REVENUE_ENGINE_PROGRAM_ID)]
+    pub revenue_engine_program: Program<'info, RevenueEngine>,
+
+    /// CHECK: PDA that signs this program's CPIs into the revenue engine.
+    #[account(seeds = [ENGINE_CALLER_SEED], bump)]
+    pub engine_caller: AccountInfo<'info>,
+
+    /// Escrow of the reward destination's owner; required when vesting is enabled.
+    #[account(mut)]
+    pub vesting_escrow: Option<Account<'info, VestingEscrow>>,
+
+    /// Required when vesting is enabled.
+    #[account(mut, seeds = [b"vesting_vault"], bump)]
+    pub vesting_vault: Option<Account<'info, TokenAccount>>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimAndCompound<'info> {
+    #[account(mut)]
+    pub user_wallet: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"nft_stake", user_wallet.key().as_ref(), nft_stake_state.nft_mint.as_ref()],
+        bump = nft_stake_state.bump,
+        has_one = user_wallet
+    )]
+    pub nft_stake_state: Account<'info, NftStakeState>,
+
+    /// Absent for wallets whose positions predate summaries.
+    #[account(
+        mut,
+        seeds = [b"user_stake_summary", user_wallet.key().as_ref()],
+        bump = user_stake_summary.bump
+    )]
+    pub user_stake_summary: Option<Account<'info, UserStakeSummary>>,
+
+    #[account(mut, seeds = [b"staking_config"], bump = staking_config.bump)]
+    pub staking_config: Account<'info, StakingConfig>,
+
+    /// CHECK: Engine State Account. Marked mut for CPI call.
+    #[account(
+        mut,
+        owner = REVENUE_ENGINE_PROGRAM_ID,
+        address = nft_stake_state.engine_state_ref,
+        constraint = engine_state.key() == staking_config.engine_state_ref @ StakingError::Unauthorized
+    )]
+    pub engine_state: AccountInfo<'info>,
+
+    #[account(address = nft_stake_state.engine_state_ref)]
+    pub engine_state_loader: Account<'info, EngineState>,
+
+    #[account(mut, address = engine_state_loader.reward_pool_pda)]
+    pub reward_pool_pda: Account<'info, TokenAccount>,
+
+    /// CHECK: Reward Pool Authority PDA
+    #[account(seeds = [b"reward_pool_authority_seed"], bump)]
+    pub reward_pool_authority: AccountInfo<'info>,
+
+    #[account(address = reward_pool_pda.mint)]
+    pub haio_mint: Account<'info, Mint>,
+
+    /// CHECK: PDA that owns the fungible stake vault.
+    #[account(seeds = [b"haio_vault_authority"], bump)]
+    pub haio_vault_authority: AccountInfo<'info>,
+
+    #[account(
+        init_if_needed,
+        payer = user_wallet,
+        seeds = [b"haio_stake_vault"],
+        bump,
+        token::mint = haio_mint,
+        token::authority = haio_vault_authority,
+    )]
+    pub haio_stake_vault: Account<'info, TokenAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = user_wallet,
+        space = 8 + TokenStakeState::MAX_SIZE,
+        seeds = [b"token_stake", user_wallet.key().as_ref()],
+        bump
+    )]
+    pub token_stake_state: Account<'info, TokenStakeState>,
+
+    #[account(address = REVENUE_ENGINE_PROGRAM_ID)]
+    pub revenue_engine_program: Program<'info, RevenueEngine>,
+
+    /// CHECK: PDA that signs this program's CPIs into the revenue engine.
+    #[account(seeds = [ENGINE_CALLER_SEED], bump)]
+    pub engine_caller: AccountInfo<'info>,
+
+    /// Required when the position has a referrer.
+    #[account(
+        mut,
+        seeds = [b"referral", nft_stake_state.referrer.as_ref()],
+        bump = referral_account.bump
+    )]
+    pub referral_account: Option<Account<'info, ReferralAccount>>,
+
+    /// Required when the position has a referrer.
+    #[account(seeds = [b"referral_budget"], bump)]
+    pub referral_budget: Option<Account<'info, TokenAccount>>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct StakeBatch<'info> {
+    #[account(mut)]
+    pub user_wallet: Signer<'info>,
+
+    /// CHECK: Engine State Account. Marked mut for CPI call.
+    #[account(mut, owner = REVENUE_ENGINE_PROGRAM_ID)]
+    pub engine_state: AccountInfo<'info>,
+
+    #[account(address = REVENUE_ENGINE_PROGRAM_ID)]
+    pub revenue_engine_program: Program<'info, RevenueEngine>,
+
+    #[account(
+        init_if_needed,
+        payer = user_wallet,
+        space = 8 + UserStakeSummary::MAX_SIZE,
+        seeds = [b"user_stake_summary", user_wallet.key().as_ref()],
+        bump
+    )]
+    pub user_stake_summary: Account<'info, UserStakeSummary>,
+
+    #[account(seeds = [b"staking_config"], bump = staking_config.bump)]
+    pub staking_config: Account<'info, StakingConfig>,
+
+    /// CHECK: PDA that signs this program's CPIs into the revenue engine.
+    #[account(seeds = [ENGINE_CALLER_SEED], bump)]
+    pub engine_caller: AccountInfo<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct BatchPositions<'info> {
+    #[account(mut)]
+    pub user_wallet: Signer<'info>,
+
+    #[account(mut)]
+    pub user_haio_account: Account<'info, TokenAccount>,
+
+    /// CHECK: Engine State Account. Marked mut for CPI call.
+    #[account(mut, owner = REVENUE_ENGINE_PROGRAM_ID)]
+    pub engine_state: AccountInfo<'info>,
+
+    #[account(address = REVENUE_ENGINE_PROGRAM_ID)]
+    pub revenue_engine_program: Program<'info, RevenueEngine>,
+
+    /// Absent for wallets whose positions predate summaries.
+    #[account(
+        mut,
+        seeds = [b"user_stake_summary", user_wallet.key().as_ref()],
+        bump = user_stake_summary.bump
+    )]
+    pub user_stake_summary: Option<Account<'info, UserStakeSummary>>,
+
+    /// CHECK: Staking config; may not exist yet for positions staked before it was set up.
+    /// Pinned by its seeds, so it cannot be left out once it exists.
+    #[account(mut, seeds = [b"staking_config"], bump)]
+    pub staking_config: UncheckedAccount<'info>,
+
+    #[account(address = engine_state.key())]
+    pub engine_state_loader: Account<'info, EngineState>,
+
+    #[account(mut, address = engine_state_loader.reward_pool_pda)]
+    pub reward_pool_pda: Account<'info, TokenAccount>,
+
+    /// CHECK: Reward Pool Authority PDA
+    #[account(seeds = [b"reward_pool_authority_seed"], bump)]
+    pub reward_pool_authority: AccountInfo<'info>,
+
+    /// CHECK: PDA that signs this program's CPIs into the revenue engine.
+    #[account(seeds = [ENGINE_CALLER_SEED], bump)]
+    pub engine_caller: AccountInfo<'info>,
+
+    /// Escrow of the reward destination's owner; required when vesting is enabled.
+    #[account(mut)]
+    pub vesting_escrow: Option<Account<'info, VestingEscrow>>,
+
+    /// Required when vesting is enabled.
+    #[account(mut, seeds = [b"vesting_vault"], bump)]
+    pub vesting_vault: Option<Account<'info, TokenAccount>>,
+
+    /// Required when a position has a referrer; referred positions in one batch must
+    /// share it.
+    #[account(mut)]
+    pub referral_account: Option<Account<'info, ReferralAccount>>,
+
+    /// Required when a position has a referrer.
+    #[account(seeds = [b"referral_budget"], bump)]
+    pub referral_budget: Option<Account<'info, TokenAccount>>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+/// `BatchPositions` plus the leftover claim that takes the IOUs of the closed positions.
+#[derive(Accounts)]
+pub struct UnstakeBatch<'info> {
+    #[account(mut)]
+    pub user_wallet: Signer<'info>,
+
+    #[account(mut)]
+    pub user_haio_account: Account<'info, TokenAccount>,
+
+    /// CHECK: Engine State Account. Marked mut for CPI call.
+    #[account(mut, owner = REVENUE_ENGINE_PROGRAM_ID)]
+    pub engine_state: AccountInfo<'info>,
+
+    #[account(address = REVENUE_ENGINE_PROGRAM_ID)]
+    pub revenue_engine_program: Program<'info, RevenueEngine>,
+
+    /// Absent for wallets whose positions predate summaries.
+    #[account(
+        mut,
+        seeds = [b"user_stake_summary", user_wallet.key().as_ref()],
+        bump = user_stake_summary.bump
+    )]
+    pub user_stake_summary: Option<Account<'info, UserStakeSummary>>,
+
+    /// CHECK: Staking config; may not exist yet for positions staked before it was set up.
+    /// Pinned by its seeds, so it cannot be left out once it exists.
+    #[account(mut, seeds = [b"staking_config"], bump)]
+    pub staking_config: UncheckedAccount<'info>,
+
+    #[account(address = engine_state.key())]
+    pub engine_state_loader: Account<'info, EngineState>,
+
+    #[account(mut, address = engine_state_loader.reward_pool_pda)]
+    pub reward_pool_pda: Account<'info, TokenAccount>,
+
+    /// CHECK: Reward Pool Authority PDA
+    #[account(seeds = [b"reward_pool_authority_seed"], bump)]
+    pub reward_pool_authority: AccountInfo<'info>,
+
+    /// CHECK: PDA that signs this program's CPIs into the revenue engine.
+    #[account(seeds = [ENGINE_CALLER_SEED], bump)]
+    pub engine_caller: AccountInfo<'info>,
+
+    /// Escrow of the reward destination's owner; required when vesting is enabled.
+    #[account(mut)]
+    pub vesting_escrow: Option<Account<'info, VestingEscrow>>,
+
+    /// Required when vesting is enabled.
+    #[account(mut, seeds = [b"vesting_vault"], bump)]
+    pub vesting_vault: Option<Account<'info, TokenAccount>>,
+
+    /// CHECK: Leftover claim of the user wallet; created only when a leaving position is
+    /// still owed rewards the pool cannot cover.
+    #[account(mut, seeds = [b"leftover_claim", user_wallet.key().as_ref()], bump)]
+    pub leftover_claim: UncheckedAccount<'info>,
+
+    /// Required when a position has a referrer; referred positions in one batch must
+    /// share it.
+    #[account(mut)]
+    pub referral_account: Option<Account<'info, ReferralAccount>>,
+
+    /// Required when a position has a referrer.
+    #[account(seeds = [b"referral_budget"], bump)]
+    pub referral_budget: Option<Account<'info, TokenAccount>>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetRewardBeneficiary<'info> {
+    pub user_wallet: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"nft_stake", user_wallet.key().as_ref(), nft_stake_state.nft_mint.as_ref()],
+        bump = nft_stake_state.bump,
+        has_one = user_wallet,
+    )]
+    pub nft_stake_state: Account<'info, NftStakeState>,
+}
+
+#[derive(Accounts)]
+pub struct CrankClaim<'info> {
+    pub cranker: Signer<'info>,
+
+    #[account(
+        mut,
+        constraint = beneficiary_haio_account.owner == nft_stake_state.reward_beneficiary @ StakingError::InvalidBeneficiary
+    )]
+    pub beneficiary_haio_account: Account<'info, TokenAccount>,
+
+    /// CHECK: Engine State Account. Marked mut for CPI call.
+    #[account(mut, owner = REVENUE_ENGINE_PROGRAM_ID, address = nft_stake_state.engine_state_ref)]
+    pub engine_state: AccountInfo<'info>,
+
+    #[account(address = REVENUE_ENGINE_PROGRAM_ID)]
+    pub revenue_engine_program: Program<'info, RevenueEngine>,
+
+    #[account(
+        mut,
+        seeds = [b"nft_stake", nft_stake_state.user_wallet.as_ref(), nft_stake_state.nft_mint.as_ref()],
+        bump = nft_stake_state.bump,
+    )]
+    pub nft_stake_state: Account<'info, NftStakeState>,
+
+    /// Absent for wallets whose positions predate summaries.
+    #[account(
+        mut,
+        seeds = [b"user_stake_summary", nft_stake_state.user_wallet.as_ref()],
+        bump = user_stake_summary.bump
+    )]
+    pub user_stake_summary: Option<Account<'info, UserStakeSummary>>,
+
+    /// CHECK: Staking config; may not exist yet for positions staked before it was set up.
+    /// Pinned by its seeds, so it cannot be left out once it exists.
+    #[account(mut, seeds = [b"staking_config"], bump)]
+    pub staking_config: UncheckedAccount<'info>,
+
+    #[account(address = nft_stake_state.engine_state_ref)]
+    pub engine_state_loader: Account<'info, EngineState>,
+
+    #[account(mut, address = engine_state_loader.reward_pool_pda)]
+    pub reward_pool_pda: Account<'info, TokenAccount>,
+
+    /// CHECK: Reward Pool Authority PDA
+    #[account(seeds = [b"reward_pool_authority_seed"], bump)]
+    pub reward_pool_authority: AccountInfo<'info>,
+
+    /// CHECK: PDA that signs this program's CPIs into the revenue engine.
+    #[account(seeds = [ENGINE_CALLER_SEED], bump)]
+    pub engine_caller: AccountInfo<'info>,
+
+    /// Escrow of the reward destination's owner; required when vesting is enabled.
+    #[account(mut)]
+    pub vesting_escrow: Option<Account<'info, VestingEscrow>>,
+
+    /// Required when vesting is enabled.
+    #[account(mut, seeds = [b"vesting_vault"], bump)]
+    pub vesting_vault: Option<Account<'info, TokenAccount>>,
+
+    /// Required when the position has a referrer.
+    #[account(
+        mut,
+        seeds = [b"referral", nft_stake_state.referrer.as_ref()],
+        bump = referral_account.bump
+    )]
+    pub referral_account: Option<Account<'info, ReferralAccount>>,
+
+    /// Required when the position has a referrer.
+    #[account(seeds = [b"referral_budget"], bump)]
+    pub referral_budget: Option<Account<'info, TokenAccount>>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct RequestUnstake<'info> {
+    #[account(mut)]
+    pub user_wallet: Signer<'info>,
+
+    #[account(mut)]
+    pub user_haio_account: Account<'info, TokenAccount>,
+
+    /// CHECK: Engine State Account. Marked mut for CPI call.
+    #[account(mut, owner = REVENUE_ENGINE_PROGRAM_ID, address = nft_stake_state.engine_state_ref)]
+    pub engine_state: AccountInfo<'info>,
+
+    #[account(address = REVENUE_ENGINE_PROGRAM_ID)]
+    pub revenue_engine_program: Program<'info, RevenueEngine>,
+
+    #[account(
+        mut,
+        seeds = [b"nft_stake", user_wallet.key().as_ref(), nft_stake_state.nft_mint.as_ref()],
+        bump = nft_stake_state.bump,
+        has_one = user_wallet,
+    )]
+    pub nft_stake_state: Account<'info, NftStakeState>,
+
+    /// Absent for wallets whose positions predate summaries.
+    #[account(
+        mut,
+        seeds = [b"user_stake_summary", user_wallet.key().as_ref()],
+        bump = user_stake_summary.bump
+    )]
+    pub user_stake_summary: Option<Account<'info, UserStakeSummary>>,
+
+    /// CHECK: Staking config; may not exist yet for positions staked before it was set up.
+    /// Pinned by its seeds, so it cannot be left out once it exists.
+    #[account(mut, seeds = [b"staking_config"], bump)]
+    pub staking_config: UncheckedAccount<'info>,
+
+    #[account(address = nft_stake_state.engine_state_ref)]
+    pub engine_state_loader: Account<'info, EngineState>,
+
+    #[account(mut, address = engine_state_loader.reward_pool_pda)]
+    pub reward_pool_pda: Account<'info, TokenAccount>,
+
+    /// CHECK: Reward Pool Authority PDA
+    #[account(seeds = [b"reward_pool_authority_seed"], bump)]
+    pub reward_pool_authority: AccountInfo<'info>,
+
+    /// CHECK: PDA that signs this program's CPIs into the revenue engine.
+    #[account(seeds = [ENGINE_CALLER_SEED], bump)]
+    pub engine_caller: AccountInfo<'info>,
+
+    /// Escrow of the reward destination's owner; required when vesting is enabled.
+    #[account(mut)]
+    pub vesting_escrow: Option<Account<'info, VestingEscrow>>,
+
+    /// Required when vesting is enabled.
+    #[account(mut, seeds = [b"vesting_vault"], bump)]
+    pub vesting_vault: Option<Account<'info, TokenAccount>>,
+
+    /// CHECK: Leftover claim of the user wallet; created only when a leaving position is
+    /// still owed rewards the pool cannot cover.
+    #[account(mut, seeds = [b"leftover_claim", user_wallet.key().as_ref()], bump)]
+    pub leftover_claim: UncheckedAccount<'info>,
+
+    /// Required when the position has a referrer.
+    #[account(
+        mut,
+        seeds = [b"referral", nft_stake_state.referrer.as_ref()],
+        bump = referral_account.bump
+    )]
+    pub referral_account: Option<Account<'info, ReferralAccount>>,
+
+    /// Required when the position has a referrer.
+    #[account(seeds = [b"referral_budget"], bump)]
+    pub referral_budget: Option<Account<'info, TokenAccount>>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CompleteUnstake<'info> {
+    #[account(
+        mut,
+        constraint = recipient.key() == nft_stake_state.user_wallet
+            || recipient.key() == nft_stake_state.unbond_recipient @ StakingError::Unauthorized
+    )]
+    pub recipient: Signer<'info>,
+
+    /// CHECK: Staker who paid the position's rent; receives it back.
+    #[account(mut, address = nft_stake_state.user_wallet)]
+    pub rent_receiver: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        close = rent_receiver,
+        seeds = [b"nft_stake", nft_stake_state.user_wallet.as_ref(), nft_stake_state.nft_mint.as_ref()],
+        bump = nft_stake_state.bump,
+    )]
+    pub nft_stake_state: Account<'info, NftStakeState>,
+
+    /// Required for positions unstaked by a receipt holder.
+    #[account(mut, seeds = [b"nft_vault", nft_stake_state.key().as_ref()], bump)]
+    pub nft_vault: Option<Account<'info, TokenAccount>>,
+
+    /// Required for positions unstaked by a receipt holder.
+    #[account(
+        mut,
+        constraint = recipient_nft_account.mint == nft_stake_state.nft_mint
+            && recipient_nft_account.owner == recipient.key() @ StakingError::InvalidNftAccount
+    )]
+    pub recipient_nft_account: Option<Account<'info, TokenAccount>>,
+
+    /// CHECK: Receipt mint and NFT vault authority PDA.
+    #[account(seeds = [b"receipt_authority"], bump)]
+    pub receipt_authority: Option<UncheckedAccount<'info>>,
+
+    pub token_program: Option<Program<'info, Token>>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimRewardsByReceipt<'info> {
+    pub holder: Signer<'info>,
+
+    #[account(
+        constraint = nft_stake_state.receipt_mint != Pubkey::default() @ StakingError::PositionNotTokenized,
+        constraint = receipt_token_account.mint == nft_stake_state.receipt_mint
+            && receipt_token_account.owner == holder.key()
+            && receipt_token_account.amount == 1 @ StakingError::NotReceiptHolder
+    )]
+    pub receipt_token_account: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub holder_haio_account: Account<'info, TokenAccount>,
+
+    /// CHECK: Engine State Account. Marked mut for CPI call.
+    #[account(mut, owner = REVENUE_ENGINE_PROGRAM_ID, address = nft_stake_state.engine_state_ref)]
+    pub engine_state: AccountInfo<'info>,
+
+    #[account(address = REVENUE_ENGINE_PROGRAM_ID)]
+    pub revenue_engine_program: Program<'info, RevenueEngine>,
+
+    #[account(
+        mut,
+        seeds = [b"nft_stake", nft_stake_state.user_wallet.as_ref(), nft_stake_state.nft_mint.as_ref()],
+        bump = nft_stake_state.bump,
+    )]
+    pub nft_stake_state: Account<'info, NftStakeState>,
+
+    /// CHECK: Staking config; may not exist yet for positions staked before it was set up.
+    /// Pinned by its seeds, so it cannot be left out once it exists.
+    #[account(mut, seeds = [b"staking_config"], bump)]
+    pub staking_config: UncheckedAccount<'info>,
+
+    #[account(address = nft_stake_state.engine_state_ref)]
+    pub engine_state_loader: Account<'info, EngineState>,
+
+    #[account(mut, address = engine_state_loader.reward_pool_pda)]
+    pub reward_pool_pda: Account<'info, TokenAccount>,
+
+    /// CHECK: Reward Pool Authority PDA
+    #[account(seeds = [b"reward_pool_authority_seed"], bump)]
+    pub reward_pool_authority: AccountInfo<'info>,
+
+    /// CHECK: PDA that signs this program's CPIs into the revenue engine.
+    #[account(seeds = [ENGINE_CALLER_SEED], bump)]
+    pub engine_caller: AccountInfo<'info>,
+
+    /// Escrow of the reward destination's owner; required when vesting is enabled.
+    #[account(mut)]
+    pub vesting_escrow: Option<Account<'info, VestingEscrow>>,
+
+    /// Required when vesting is enabled.
+    #[account(mut, seeds = [b"vesting_vault"], bump)]
+    pub vesting_vault: Option<Account<'info, TokenAccount>>,
+
+    /// Required when the position has a referrer.
+    #[account(
+        mut,
+        seeds = [b"referral", nft_stake_state.referrer.as_ref()],
+        bump = referral_account.bump
+    )]
+    pub referral_account: Option<Account<'info, ReferralAccount>>,
+
+    /// Required when the position has a referrer.
+    #[account(seeds = [b"referral_budget"], bump)]
+    pub referral_budget: Option<Account<'info, TokenAccount>>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct UnstakeByReceipt<'info> {
+    #[account(mut)]
+    pub holder: Signer<'info>,
+
+    #[account(
+        mut,
+        constraint = nft_stake_state.receipt_mint != Pubkey::default() @ StakingError::PositionNotTokenized,
+        constraint = receipt_token_account.mint == nft_stake_state.receipt_mint
+            && receipt_token_account.owner == holder.key()
+            && receipt_token_account.amount == 1 @ StakingError::NotReceiptHolder
+    )]
+    pub receipt_token_account: Account<'info, TokenAccount>,
+
+    #[account(mut, address = nft_stake_state.receipt_mint)]
+    pub receipt_mint: Account<'info, Mint>,
+
+    #[account(mut)]
+    pub holder_haio_account: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = holder_nft_account.mint == nft_stake_state.nft_mint
+            && holder_nft_account.owner == holder.key() @ StakingError::InvalidNftAccount
+    )]
+    pub holder_nft_account: Account<'info, TokenAccount>,
+
+    #[account(mut, seeds = [b"nft_vault", nft_stake_state.key().as_ref()], bump)]
+    pub nft_vault: Account<'info, TokenAccount>,
+
+    /// CHECK: Receipt mint and NFT vault authority PDA.
+    #[account(seeds = [b"receipt_authority"], bump)]
+    pub receipt_authority: UncheckedAccount<'info>,
+
+    /// CHECK: Staker who paid the position's rent; receives it back.
+    #[account(mut, address = nft_stake_state.user_wallet)]
+    pub rent_receiver: UncheckedAccount<'info>,
+
+    /// CHECK: Engine State Account. Marked mut for CPI call.
+    #[account(mut, owner = REVENUE_ENGINE_PROGRAM_ID, address = nft_stake_state.engine_state_ref)]
+    pub engine_state: AccountInfo<'info>,
+
+    #[account(address = REVENUE_ENGINE_PROGRAM_ID)]
+    pub revenue_engine_program: Program<'info, RevenueEngine>,
+
+    #[account(
+        mut,
+        seeds = [b"nft_stake", nft_stake_state.user_wallet.as_ref(), nft_stake_state.nft_mint.as_ref()],
+        bump = nft_stake_state.bump,
+    )]
+    pub nft_stake_state: Account<'info, NftStakeState>,
+
+    /// CHECK: Staking config; may not exist yet for positions staked before it was set up.
+    /// Pinned by its seeds, so it cannot be left out once it exists.
+    #[account(mut, seeds = [b"staking_config"], bump)]
+    pub staking_config: UncheckedAccount<'info>,
+
+    #[account(address = nft_stake_state.engine_state_ref)]
+    pub engine_state_loader: Account<'info, EngineState>,
+
+    #[account(mut, address = engine_state_loader.reward_pool_pda)]
+    pub reward_pool_pda: Account<'info, TokenAccount>,
+
+    /// CHECK: Reward Pool Authority PDA
+    #[account(seeds = [b"reward_pool_authority_seed"], bump)]
+    pub reward_pool_authority: AccountInfo<'info>,
+
+    /// CHECK: PDA that signs this program's CPIs into the revenue engine.
+    #[account(seeds = [ENGINE_CALLER_SEED], bump)]
+    pub engine_caller: AccountInfo<'info>,
+
+    /// Escrow of the reward destination's owner; required when vesting is enabled.
+    #[account(mut)]
+    pub vesting_escrow: Option<Account<'info, VestingEscrow>>,
+
+    /// Required when vesting is enabled.
+    #[account(mut, seeds = [b"vesting_vault"], bump)]
+    pub vesting_vault: Option<Account<'info, TokenAccount>>,
+
+    /// CHECK: Leftover claim of the holder; created only when a leaving position is
+    /// still owed rewards the pool cannot cover.
+    #[account(mut, seeds = [b"leftover_claim", holder.key().as_ref()], bump)]
+    pub leftover_claim: UncheckedAccount<'info>,
+
+    /// Required when the position has a referrer.
+    #[account(
+        mut,
+        seeds = [b"referral", nft_stake_state.referrer.as_ref()],
+        bump = referral_account.bump
+    )]
+    pub referral_account: Option<Account<'info, ReferralAccount>>,
+
+    /// Required when the position has a referrer.
+    #[account(seeds = [b"referral_budget"], bump)]
+    pub referral_budget: Option<Account<'info, TokenAccount>>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimRewards<'info> {
+    #[account(mut)]
+    pub user_wallet: Signer<'info>,
+
+    #[account(mut)]
+    pub user_haio_account: Account<'info, TokenAccount>,
+
+    /// CHECK: Engine State Account (must be owned by RevenueEngine program). Marked mut for CPI call.
+    #[account(mut, owner = REVENUE_ENGINE_PROGRAM_ID, address = nft_stake_state.engine_state_ref)]
+    pub engine_state: AccountInfo<'info>,
+
+    #[account(address = REVENUE_ENGINE_PROGRAM_ID)]
+    pub revenue_engine_program: Program<'info, RevenueEngine>,
+
+    #[account(
+        mut,
+        seeds = [b"nft_stake", user_wallet.key().as_ref(), nft_stake_state.nft_mint.as_ref()],
+        bump = nft_stake_state.bump,
+        has_one = user_wallet
+    )]
+    pub nft_stake_state: Account<'info, NftStakeState>,
+
+    /// Absent for wallets whose positions predate summaries.
+    #[account(
+        mut,
+        seeds = [b"user_stake_summary", user_wallet.key().as_ref()],
+        bump = user_stake_summary.bump
+    )]
+    pub user_stake_summary: Option<Account<'info, UserStakeSummary>>,
+
+    #[account(address = nft_stake_state.engine_state_ref)]
+    pub engine_state_loader: Account<'info, EngineState>,
+
+    #[account(mut, address = engine_state_loader.reward_pool_pda)]
+    pub reward_pool_pda: Account<'info, TokenAccount>,
+
+    /// CHECK: Reward Pool Authority PDA
+    #[account(seeds = [b"reward_pool_authority_seed"], bump)]
+    pub reward_pool_authority: AccountInfo<'info>,
+
+    /// CHECK: PDA that signs this program's CPIs into the revenue engine.
+    #[account(seeds = [ENGINE_CALLER_SEED], bump)]
+    pub engine_caller: AccountInfo<'info>,
+
+    /// CHECK: Staking config; may not exist yet for positions staked before it was set up.
+    /// Pinned by its seeds, so it cannot be left out once it exists.
+    #[account(mut, seeds = [b"staking_config"], bump)]
+    pub staking_config: UncheckedAccount<'info>,
+
+    /// Escrow of the reward destination's owner; required when vesting is enabled.
+    #[account(mut)]
+    pub vesting_escrow: Option<Account<'info, VestingEscrow>>,
+
+    /// Required when vesting is enabled.
+    #[account(mut, seeds = [b"vesting_vault"], bump)]
+    pub vesting_vault: Option<Account<'info, TokenAccount>>,
+
+    /// Required when the position has a referrer.
+    #[account(
+        mut,
+        seeds = [b"referral", nft_stake_state.referrer.as_ref()],
+        bump = referral_account.bump
+    )]
+    pub referral_account: Option<Account<'info, ReferralAccount>>,
+
+    /// Required when the position has a referrer.
+    #[account(seeds = [b"referral_budget"], bump)]
+    pub referral_budget: Option<Account<'info, TokenAccount>>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimRewardsAs<'info> {
+    #[account(mut)]
+    pub user_wallet: Signer<'info>,
+
+    /// Receives the HAiO rewards just before they are swapped.
+    #[account(
+        mut,
+        constraint = user_haio_account.owner == user_wallet.key()
+            && user_haio_account.mint == haio_mint.key() @ StakingError::Unauthorized
+    )]
+    pub user_haio_account: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = user_output_account.owner == user_wallet.key()
+            && user_output_account.mint == output_mint.key() @ StakingError::InvalidOutputMint
+    )]
+    pub user_output_account: Account<'info, TokenAccount>,
+
+    /// CHECK: Engine State Account (must be owned by RevenueEngine program). Marked mut for CPI call.
+    #[account(mut, owner = REVENUE_ENGINE_PROGRAM_ID, address = nft_stake_state.engine_state_ref)]
+    pub engine_state: AccountInfo<'info>,
+
+    #[account(address = REVENUE_ENGINE_PROGRAM_ID)]
+    pub revenue_engine_program: Program<'info, RevenueEngine>,
+
+    #[account(
+        mut,
+        seeds = [b"nft_stake", user_wallet.key().as_ref(), nft_stake_state.nft_mint.as_ref()],
+        bump = nft_stake_state.bump,
+        has_one = user_wallet
+    )]
+    pub nft_stake_state: Account<'info, NftStakeState>,
+
+    /// Absent for wallets whose positions predate summaries.
+    #[account(
+        mut,
+        seeds = [b"user_stake_summary", user_wallet.key().as_ref()],
+        bump = user_stake_summary.bump
+    )]
+    pub user_stake_summary: Option<Account<'info, UserStakeSummary>>,
+
+    #[account(address = nft_stake_state.engine_state_ref)]
+    pub engine_state_loader: Account<'info, EngineState>,
+
+    #[account(mut, address = engine_state_loader.reward_pool_pda)]
+    pub reward_pool_pda: Account<'info, TokenAccount>,
+
+    /// CHECK: Reward Pool Authority PDA
+    #[account(seeds = [b"reward_pool_authority_seed"], bump)]
+    pub reward_pool_authority: AccountInfo<'info>,
+
+    #[account(address = reward_pool_pda.mint)]
+    pub haio_mint: Account<'info, Mint>,
+
+    pub output_mint: Account<'info, Mint>,
+
+    /// CHECK: PDA that signs this program's CPIs into the revenue engine.
+    #[account(seeds = [ENGINE_CALLER_SEED], bump)]
+    pub engine_caller: AccountInfo<'info>,
+
+    /// CHECK: Staking config; may not exist yet for positions staked before it was set up.
+    /// Pinned by its seeds, so it cannot be left out once it exists.
+    #[account(mut, seeds = [b"staking_config"], bump)]
+    pub staking_config: UncheckedAccount<'info>,
+
+    /// Required when the position has a referrer.
+    #[account(
+        mut,
+        seeds = [b"referral", nft_stake_state.referrer.as_ref()],
+        bump = referral_account.bump
+    )]
+    pub referral_account: Option<Account<'info, ReferralAccount>>,
+
+    /// Required when the position has a referrer.
+    #[account(seeds = [b"referral_budget"], bump)]
+    pub referral_budget: Option<Account<'info, TokenAccount>>,
+
+    pub swap_program: Program<'info, MockSwapProgram>,
+
+    /// CHECK: PDA that owns the swap vaults; validated by the swap program.
+    pub swap_vault_authority: AccountInfo<'info>,
+
+    #[account(mut)]
+    pub swap_haio_vault: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub swap_output_vault: Account<'info, TokenAccount>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimLeftover<'info> {
+    #[account(mut)]
+    pub user_wallet: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"leftover_claim", user_wallet.key().as_ref()],
+        bump = leftover_claim.bump,
+        has_one = user_wallet
+    )]
+    pub leftover_claim: Account<'info, LeftoverClaim>,
+
+    #[account(mut, constraint = user_haio_account.owner == user_wallet.key() @ StakingError::Unauthorized)]
+    pub user_haio_account: Account<'info, TokenAccount>,
+
+    /// CHECK: Staking config; may not exist yet for positions staked before it was set up.
+    /// Pinned by its seeds, so it cannot be left out once it exists.
+    #[account(seeds = [b"staking_config"], bump)]
+    pub staking_config: UncheckedAccount<'info>,
+
+    /// CHECK: Engine State Account. Marked mut for CPI call.
+    #[account(mut, owner = REVENUE_ENGINE_PROGRAM_ID, address = leftover_claim.engine_state_ref)]
+    pub engine_state: AccountInfo<'info>,
+
+    #[account(address = leftover_claim.engine_state_ref)]
+    pub engine_state_loader: Account<'info, EngineState>,
+
+    #[account(mut, address = engine_state_loader.reward_pool_pda)]
+    pub reward_pool_pda: Account<'info, TokenAccount>,
+
+    /// CHECK: Reward Pool Authority PDA
+    #[account(seeds = [b"reward_pool_authority_seed"], bump)]
+    pub reward_pool_authority: AccountInfo<'info>,
+
+    #[account(address = REVENUE_ENGINE_PROGRAM_ID)]
+    pub revenue_engine_program: Program<'info, RevenueEngine>,
+
+    /// CHECK: PDA that signs this program's CPIs into the revenue engine.
+    #[account(seeds = [ENGINE_CALLER_SEED], bump)]
+    pub engine_caller: AccountInfo<'info>,
+
+    /// Escrow of the reward destination's owner; required when vesting is enabled.
+    #[account(mut)]
+    pub vesting_escrow: Option<Account<'info, VestingEscrow>>,
+
+    /// Required when vesting is enabled.
+    #[account(mut, seeds = [b"vesting_vault"], bump)]
+    pub vesting_vault: Option<Account<'info, TokenAccount>>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct OpenVestingEscrow<'info> {
+    #[account(mut)]
+    pub user_wallet: Signer<'info>,
+
+    #[account(
+        init,
+        payer = user_wallet,
+        space = 8 + VestingEscrow::MAX_SIZE,
+        seeds = [b"vesting_escrow", user_wallet.key().as_ref()],
+        bump
+    )]
+    pub vesting_escrow: Account<'info, VestingEscrow>,
+
+    #[account(seeds = [b"staking_config"], bump = staking_config.bump)]
+    pub staking_config: Account<'info, StakingConfig>,
+
+    #[account(address = staking_config.engine_state_ref)]
+    pub engine_state_loader: Account<'info, EngineState>,
+
+    #[account(address = engine_state_loader.reward_pool_pda)]
+    pub reward_pool_pda: Account<'info, TokenAccount>,
+
+    #[account(address = reward_pool_pda.mint)]
+    pub haio_mint: Account<'info, Mint>,
+
+    /// CHECK: PDA that owns the vesting vault.
+    #[account(seeds = [b"vesting_vault_authority"], bump)]
+    pub vesting_vault_authority: AccountInfo<'info>,
+
+    #[account(
+        init_if_needed,
+        payer = user_wallet,
+        seeds = [b"vesting_vault"],
+        bump,
+        token::mint = haio_mint,
+        token::authority = vesting_vault_authority,
+    )]
+    pub vesting_vault: Account<'info, TokenAccount>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawVesting<'info> {
+    pub user_wallet: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"vesting_escrow", user_wallet.key().as_ref()],
+        bump = vesting_escrow.bump,
+        has_one = user_wallet
+    )]
+    pub vesting_escrow: Account<'info, VestingEscrow>,
+
+    #[account(mut, seeds = [b"vesting_vault"], bump)]
+    pub vesting_vault: Account<'info, TokenAccount>,
+
+    /// CHECK: PDA that owns the vesting vault.
+    #[account(seeds = [b"vesting_vault_authority"], bump)]
+    pub vesting_vault_authority: AccountInfo<'info>,
+
+    #[account(mut, constraint = user_haio_account.owner == user_wallet.key() @ StakingError::Unauthorized)]
+    pub user_haio_account: Account<'info, TokenAccount>,
+
+    #[account(seeds = [b"staking_config"], bump = staking_config.bump)]
+    pub staking_config: Account<'info, StakingConfig>,
+
+    /// CHECK: Engine State Account. Marked mut for CPI call.
+    #[account(mut, owner = REVENUE_ENGINE_PROGRAM_ID, address = staking_config.engine_state_ref)]
+    pub engine_state: AccountInfo<'info>,
+
+    #[account(address = staking_config.engine_state_ref)]
+    pub engine_state_loader: Account<'info, EngineState>,
+
+    #[account(mut, address = engine_state_loader.reward_pool_pda)]
+    pub reward_pool_pda: Account<'info, TokenAccount>,
+
+    #[account(address = REVENUE_ENGINE_PROGRAM_ID)]
+    pub revenue_engine_program: Program<'info, RevenueEngine>,
+
+    /// CHECK: PDA that signs this program's CPIs into the revenue engine.
+    #[account(seeds = [ENGINE_CALLER_SEED], bump)]
+    pub engine_caller: AccountInfo<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct StartReconcile<'info> {
+    #[account(mut)]
+    pub cranker: Signer<'info>,
+
+    /// CHECK: Engine State Account (must be owned by RevenueEngine program)
+    #[account(owner = REVENUE_ENGINE_PROGRAM_ID)]
+    pub engine_state: AccountInfo<'info>,
+
+    #[account(
+        init_if_needed,
+        payer = cranker,
+        space = 8 + ReconcileState::MAX_SIZE,
+        seeds = [b"reconcile_state", engine_state.key().as_ref()],
+        bump
+    )]
+    pub reconcile_state: Account<'info, ReconcileState>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ReconcileStaked<'info> {
+    #[account(
+        mut,
+        seeds = [b"reconcile_state", reconcile_state.engine_state_ref.as_ref()],
+        bump = reconcile_state.bump
+    )]
+    pub reconcile_state: Account<'info, ReconcileState>,
+}
+
+#[derive(Accounts)]
+pub struct FinalizeReconcile<'info> {
+    pub authority: Signer<'info>,
+
+    /// CHECK: Engine State Account (must be owned by RevenueEngine program)
+    #[account(owner = REVENUE_ENGINE_PROGRAM_ID, address = reconcile_state.engine_state_ref)]
+    pub engine_state: AccountInfo<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"reconcile_state", reconcile_state.engine_state_ref.as_ref()],
+        bump = reconcile_state.bump
+    )]
+    pub reconcile_state: Account<'info, ReconcileState>,
+}
+
+#[derive(Accounts)]
+pub struct ApplyReconcileCorrection<'info> {
+    pub authority: Signer<'info>,
+
+    /// CHECK: Engine State Account. Marked mut for CPI call.
+    #[account(mut, owner = REVENUE_ENGINE_PROGRAM_ID, address = reconcile_state.engine_state_ref)]
+    pub engine_state: AccountInfo<'info>,
+
+    #[account(address = REVENUE_ENGINE_PROGRAM_ID)]
+    pub revenue_engine_program: Program<'info, RevenueEngine>,
+
+    #[account(
+        mut,
+        seeds = [b"reconcile_state", reconcile_state.engine_state_ref.as_ref()],
+        bump = reconcile_state.bump
+    )]
+    pub reconcile_state: Account<'info, ReconcileState>,
+
+    /// CHECK: PDA that signs this program's CPIs into the revenue engine.
+    #[account(seeds = [ENGINE_CALLER_SEED], bump)]
+    pub engine_caller: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SweepRewardPool<'info> {
+    pub authority: Signer<'info>,
+
+    pub engine_state: Account<'info, EngineState>,
+
+    #[account(mut, address = engine_state.reward_pool_pda)]
+    pub reward_pool_pda: Account<'info, TokenAccount>,
+
+    #[account(mut, constraint = destination.mint == reward_pool_pda.mint)]
+    pub destination: Account<'info, TokenAccount>,
+
+    /// CHECK: Reward Pool Authority PDA
+    #[account(seeds = [b"reward_pool_authority_seed"], bump)]
+    pub reward_pool_authority: AccountInfo<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeStakingConfig<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub engine_state: Account<'info, EngineState>,
+
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + StakingConfig::MAX_SIZE,
+        seeds = [b"staking_config"],
+        bump
+    )]
+    pub staking_config: Account<'info, StakingConfig>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RegisterReferrer<'info> {
+    #[account(mut)]
+    pub referrer: Signer<'info>,
+
+    #[account(
+        init,
+        payer = referrer,
+        space = 8 + ReferralAccount::MAX_SIZE,
+        seeds = [b"referral", referrer.key().as_ref()],
+        bump
+    )]
+    pub referral_account: Account<'info, ReferralAccount>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct FundReferralBudget<'info> {
+    #[account(mut)]
+    pub funder: Signer<'info>,
+
+    #[account(mut, constraint = funder_haio_account.mint == haio_mint.key() @ StakingError::Unauthorized)]
+    pub funder_haio_account: Account<'info, TokenAccount>,
+
+    #[account(seeds = [b"staking_config"], bump = staking_config.bump)]
+    pub staking_config: Account<'info, StakingConfig>,
+
+    #[account(address = staking_config.engine_state_ref)]
+    pub engine_state_loader: Account<'info, EngineState>,
+
+    #[account(address = engine_state_loader.reward_pool_pda)]
+    pub reward_pool_pda: Account<'info, TokenAccount>,
+
+    #[account(address = reward_pool_pda.mint)]
+    pub haio_mint: Account<'info, Mint>,
+
+    /// CHECK: PDA that owns the referral budget vault.
+    #[account(seeds = [b"referral_budget_authority"], bump)]
+    pub referral_budget_authority: AccountInfo<'info>,
+
+    #[account(
+        init_if_needed,
+        payer = funder,
+        seeds = [b"referral_budget"],
+        bump,
+        token::mint = haio_mint,
+        token::authority = referral_budget_authority,
+    )]
+    pub referral_budget: Account<'info, TokenAccount>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimReferralRewards<'info> {
+    pub referrer: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"referral", referrer.key().as_ref()],
+        bump = referral_account.bump,
+        has_one = referrer
+    )]
+    pub referral_account: Account<'info, ReferralAccount>,
+
+    #[account(mut, constraint = referrer_haio_account.mint == referral_budget.mint @ StakingError::Unauthorized)]
+    pub referrer_haio_account: Account<'info, TokenAccount>,
+
+    #[account(mut, seeds = [b"staking_config"], bump = staking_config.bump)]
+    pub staking_config: Account<'info, StakingConfig>,
+
+    /// CHECK: PDA that owns the referral budget vault.
+    #[account(seeds = [b"referral_budget_authority"], bump)]
+    pub referral_budget_authority: AccountInfo<'info>,
+
+    #[account(mut, seeds = [b"referral_budget"], bump)]
+    pub referral_budget: Account<'info, TokenAccount>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateStakingConfig<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"staking_config"],
+        bump = staking_config.bump,
+        has_one = authority @ StakingError::Unauthorized
+    )]
+    pub staking_config: Account<'info, StakingConfig>,
+}
+
+#[derive(Accounts)]
+#[instruction(nft_mint: Pubkey)]
+pub struct SetNftWeight<'info> {
+    #[account(mut)]
+    pub curator: Signer<'info>,
+
+    #[account(
+        seeds = [b"staking_config"],
+        bump = staking_config.bump,
+        has_one = curator @ StakingError::Unauthorized
+    )]
+    pub staking_config: Account<'info, StakingConfig>,
+
+    #[account(
+        init_if_needed,
+        payer = curator,
+        space = 8 + NftWeight::MAX_SIZE,
+        seeds = [b"nft_weight", nft_mint.as_ref()],
+        bump
+    )]
+    pub nft_weight: Account<'info, NftWeight>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateAccount<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    /// CHECK: An old layout cannot be deserialized; the handler checks the discriminator.
+    #[account(mut, owner = crate::ID)]
+    pub account: UncheckedAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn engine_state() -> EngineState {
+        EngineState::deserialize(&mut &vec![0u8; EngineState::MAX_SIZE][..]).unwrap()
+    }
+
+    /// Distributions at 100, 200 and 300 raising the rate to 1, 2 and 3 HAiO per weight.
+    fn engine_with_checkpoints() -> EngineState {
+        let mut state = engine_state();
+        for (timestamp, rate) in [(100, 1), (200, 2), (300, 3)] {
+            state.reward_per_token_cumulative = rate * PRECISION;
+            state.push_checkpoint(timestamp);
+        }
+        state
+    }
+
+    #[test]
+    fn settlement_keeps_what_the_pool_cannot_cover_as_iou() {
+        let mut settlement = Settlement::new(100);
+        assert_eq!(settlement.settle(80, 0, 80 * PRECISION, 0).unwrap(), (80, 0));
+        assert_eq!(settlement.settle(30, 5, 35 * PRECISION, 10).unwrap(), (20, 20));
+        assert_eq!(settlement.paid, 100);
+        assert_eq!(settlement.forfeited, 5);
+        assert_eq!(settlement.settled_debt, 115 * PRECISION);
+        assert_eq!((settlement.iou_before, settlement.iou_after), (10, 20));
+        assert!(!settlement.is_empty());
+    }
+
+    #[test]
+    fn eligible_rewards_split_off_the_forfeited_share() {
+        let state = engine_with_checkpoints();
+        assert_eq!(eligible_rewards(&state, 0, 0, 10).unwrap(), (30, 0));
+        // Eligible from 250: only the distribution at 300 counts.
+        assert_eq!(eligible_rewards(&state, 0, 250, 10).unwrap(), (10, 20));
+        // Already settled past the window: nothing more is forfeited.
+        assert_eq!(eligible_rewards(&state, 2 * PRECISION, 250, 10).unwrap(), (10, 0));
+    }
+
+    /// Bytes a baseline `NftStakeState` held after its discriminator, up to `engine_state_ref`.
+    const BASELINE_NFT_STAKE_FIELDS_LEN: usize = 32 + 32 + 8 + 16 + 8 + 1 + 1 + 32;
+
+    #[test]
+    fn migrated_baseline_position_is_counted_by_reconcile() {
+        let baseline = NftStakeState {
+            user_wallet: Pubkey::new_unique(),
+            nft_mint: Pubkey::new_unique(),
+            staked_amount: 3,
+            reward_debt: 2 * PRECISION,
+            is_staked: true,
+            bump: 255,
+            engine_state_ref: Pubkey::new_unique(),
+            ..NftStakeState::default()
+        };
+        let mut data = Vec::new();
+        baseline.try_serialize(&mut data).unwrap();
+        // What `realloc(space, true)` leaves behind: the baseline bytes, zero-extended.
+        data.truncate(8 + BASELINE_NFT_STAKE_FIELDS_LEN);
+        assert!(NftStakeState::try_deserialize(&mut &data[..]).is_err());
+        data.resize(8 + NftStakeState::MAX_SIZE, 0);
+
+        let mut position = NftStakeState::try_deserialize(&mut &data[..]).unwrap();
+        migrate_nft_stake_state(&mut position);
+        assert_eq!(position.base_weight, 3);
+        assert_eq!(position.custody_mode, CustodyMode::Untracked);
+        assert_eq!(position.reconcile_epoch, 0);
+
+        let mut reconcile_state =
+            ReconcileState::deserialize(&mut &vec![0u8; ReconcileState::MAX_SIZE][..]).unwrap();
+        count_reconcile_position(&mut reconcile_state, position.staked_amount, position.reward_debt)
+            .unwrap();
+        count_reconcile_position(&mut reconcile_state, 5, PRECISION).unwrap();
+        assert_eq!(reconcile_state.counted_total, 8);
+        assert_eq!(reconcile_state.counted_reward_debt, 11 * PRECISION);
+    }
+    #[test]
+    fn vested_rewards_exit_early_with_the_penalty_on_the_unvested_part() {
+        let mut escrow =
+            VestingEscrow::deserialize(&mut &vec![0u8; VestingEscrow::MAX_SIZE][..]).unwrap();
+        // A ve claim of 1000 credited at 0 with a 100 s vesting duration.
+        vest_claim(&mut escrow, 1000, 100, 0).unwrap();
+        assert_eq!(early_exit_amounts(&escrow, 5000).unwrap(), (500, 500));
+
+        checkpoint_vesting(&mut escrow, 25).unwrap();
+        assert_eq!(escrow.unlocked_amount, 250);
+        assert_eq!(early_exit_amounts(&escrow, 5000).unwrap(), (625, 375));
+        assert_eq!(early_exit_amounts(&escrow, 0).unwrap(), (1000, 0));
+    }
+
+    #[test]
+    fn token_top_up_only_ages_the_new_amount() {
+        let mut engine = engine_state();
+        engine.min_stake_age_secs = 100;
+        // First deposit at 1000: a full window.
+        assert_eq!(merged_eligible_from(&engine, 0, 0, 300, 1000).unwrap(), 1100);
+        // Topping up 100 at 1000 onto 300 already past its window.
+        assert_eq!(merged_eligible_from(&engine, 0, 300, 100, 1000).unwrap(), 1025);
+        // Topping up 300 at 1050 onto 300 still eligible from 1100.
+        assert_eq!(merged_eligible_from(&engine, 1100, 300, 300, 1050).unwrap(), 1125);
+
+        engine.min_stake_age_secs = 0;
+        assert_eq!(merged_eligible_from(&engine, 1100, 300, 300, 1050).unwrap(), 1100);
+        assert_eq!(merged_eligible_from(&engine, 0, 0, 300, 1050).unwrap(), 0);
+    }
+
+    /// What `distribute_revenue` records when `ve_reward_total` goes to ve locks at `now`.
+    fn distribute_to_ve(engine: &mut EngineState, ve_reward_total: u64, now: i64) {
+        let delta = ve_reward_total as u128 * PRECISION / engine.ve_weight() as u128;
+        engine.ve_reward_per_weight_cumulative += delta;
+        engine.ve_reward_time_cumulative += delta * now as u128;
+    }
+
+    #[test]
+    fn ve_lock_accrues_its_decayed_share_until_unlock() {
+        let mut engine = engine_state();
+        let mut schedule =
+            VeSchedule::deserialize(&mut &vec![0u8; VeSchedule::MAX_SIZE][..]).unwrap();
+        let weight = 1_000_000_000;
+        schedule
+            .add_slope(&mut engine, VeSchedule::slope_for(weight), 0, MAX_VE_LOCK_SECS)
+            .unwrap();
+        let lock = VeLock {
+            user_wallet: Pubkey::new_unique(),
+            engine_state_ref: Pubkey::new_unique(),
+            deposited_amount: weight,
+            weight,
+            locked_at: 0,
+            unlock_timestamp: MAX_VE_LOCK_SECS,
+            reward_per_weight_paid: 0,
+            reward_time_paid: 0,
+            unpaid_rewards: 0,
+            bump: 255,
+            position: Pubkey::default(),
+            unlock_settled: false,
+        };
+
+        // Halfway through, the only lock holds half its weight and earns the whole ve share.
+        let halfway = MAX_VE_LOCK_SECS / 2;
+        schedule.checkpoint(&mut engine, halfway).unwrap();
+        distribute_to_ve(&mut engine, 1000, halfway);
+        let (reward, _, _) = pending_ve_rewards(&engine, &schedule, &lock).unwrap();
+        assert!((999..=1000).contains(&reward), "reward {reward}");
+
+        // Past its unlock week the lock's rates are frozen; later ve rewards are not its.
+        schedule.checkpoint(&mut engine, MAX_VE_LOCK_SECS + 1).unwrap();
+        engine.ve_reward_per_weight_cumulative += 5 * PRECISION;
+        engine.ve_reward_time_cumulative += 5 * PRECISION * (MAX_VE_LOCK_SECS as u128 + 1);
+        let (frozen, _, _) = pending_ve_rewards(&engine, &schedule, &lock).unwrap();
+        assert_eq!(frozen, reward);
+    }
+
+    #[test]
+    fn missing_staking_config_means_no_cooldown_vesting_or_referral() {
+        let key = Pubkey::new_unique();
+        let system_program_id = System::id();
+        let (mut lamports, mut data) = (1, Vec::new());
+        let uninitialized = AccountInfo::new(
+            &key, false, true, &mut lamports, &mut data, &system_program_id, false, 0,
+        );
+        let config = read_staking_config(&uninitialized).unwrap();
+        assert!(config.is_none());
+        assert_eq!(unbonding_cooldown_secs(config.as_ref()), 0);
+        assert_eq!(vesting_duration_secs(config.as_ref()), 0);
+
+        let mut stored = StakingConfig::deserialize(
+            &mut &vec![0u8; StakingConfig::MAX_SIZE][..],
+        )
+        .unwrap();
+        stored.unbonding_cooldown_secs = 600;
+        let mut data = Vec::new();
+        stored.try_serialize(&mut data).unwrap();
+        let mut lamports = 1;
+        let program_id = crate::ID;
+        let initialized = AccountInfo::new(
+            &key, false, true, &mut lamports, &mut data, &program_id, false, 0,
+        );
+        let config = read_staking_config(&initialized).unwrap();
+        assert_eq!(unbonding_cooldown_secs(config.as_ref()), 600);
+
+        // A referred position settles without a config; it just credits no referrer.
+        let mut position = NftStakeState {
+            staked_amount: 1,
+            referrer: Pubkey::new_unique(),
+            ..NftStakeState::default()
+        };
+        let mut engine = engine_state();
+        engine.reward_per_token_cumulative = 10 * PRECISION;
+        let mut settlement = Settlement::new(100);
+        let mut referral = ReferralCredit {
+            config: None,
+            referral_account: None,
+            referral_budget: None,
+        };
+        let paid = settle_nft_rewards(&mut settlement, &mut referral, &engine, &mut position);
+        assert_eq!(paid.unwrap(), 10);
+    }
 }
@@ -1,7 +1,51 @@
 use anchor_lang::prelude::*;
+use revenue_engine::state::MAX_VE_LOCK_SECS;
 use solana_program::pubkey::Pubkey;
 
+pub const MAX_LOCK_TIERS: usize = 4;
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default)]
+pub struct LockTier {
+    pub duration_secs: i64,
+    pub boost_bps: u16,
+}
+
+impl LockTier {
+    pub const SIZE: usize = 8 // duration_secs
+        + 2; // boost_bps
+}
+
+/// How the staked NFT is held while the position is open.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum CustodyMode {
+    /// NFT is neither held nor frozen by the program (original `stake` flow).
+    #[default]
+    Untracked,
+    /// NFT stays in the owner's token account, delegated to and frozen by the program.
+    FreezeInPlace,
+    /// Programmable NFT locked in the owner's account via a Token Metadata staking delegate.
+    ProgrammableLock,
+    /// Compressed NFT leaf transferred to the `cnft_vault` PDA; `nft_mint` holds the asset ID.
+    CompressedEscrow,
+    /// Metaplex Core asset frozen in place by a `FreezeDelegate` plugin; `nft_mint` holds the asset.
+    CoreFreeze,
+    /// NFT transferred to the position's `nft_vault` when staked with a receipt; the receipt
+    /// holder receives it on unstake.
+    VaultEscrow,
+}
+
+/// Bubblegum leaf fields needed to prove and transfer a compressed NFT.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub struct CompressedLeaf {
+    pub root: [u8; 32],
+    pub data_hash: [u8; 32],
+    pub creator_hash: [u8; 32],
+    pub nonce: u64,
+    pub index: u32,
+}
+
 #[account]
+#[derive(Default)]
 pub struct NftStakeState {
     pub user_wallet: Pubkey,
     pub nft_mint: Pubkey,
@@ -11,16 +55,291 @@ pub struct NftStakeState {
     pub is_staked: bool,
     pub bump: u8,
     pub engine_state_ref: Pubkey,
+    pub reconcile_epoch: u64,
+    pub base_weight: u64,
+    pub lock_duration: i64,
+    pub lock_boost_bps: u16,
+    pub unbonding_ends_at: i64,
+    pub reward_beneficiary: Pubkey,
+    pub receipt_mint: Pubkey,
+    pub unbond_recipient: Pubkey,
+    pub custody_mode: CustodyMode,
+    pub unpaid_rewards: u64,
+    pub referrer: Pubkey,
+    pub collection: Pubkey,
+    pub eligible_from: i64,
+    pub ve_unlock_timestamp: i64,
 }
 
 impl NftStakeState {
     pub const MAX_SIZE: usize = 8  // Discriminator
         + 32                       // user_wallet
-        + 32                       // nft_mint
+        + 32                       // nft_mint (asset ID for compressed NFTs and Core assets)
         + 8                        // staked_amount
         + 16                       // reward_debt (u128)
         + 8                        // last_staked_timestamp
         + 1                        // is_staked (bool)
         + 1                        // bump
-        + 32; // agent_state_ref
+        + 32                       // agent_state_ref
+        + 8                        // reconcile_epoch
+        + 8                        // base_weight (registry weight before boost)
+        + 8                        // lock_duration (seconds, 0 = unlocked)
+        + 2                        // lock_boost_bps
+        + 8                        // unbonding_ends_at (0 = not unbonding)
+        + 32                       // reward_beneficiary (default = rewards go to the staker)
+        + 32                       // receipt_mint (default = position not tokenized)
+        + 32                       // unbond_recipient (receipt holder that may complete the unbond)
+        + 1                        // custody_mode
+        + 8                        // unpaid_rewards (IOU left when the reward pool was short)
+        + 32                       // referrer (default = no referrer)
+        + 32                       // collection (Core and compressed positions: collection verified at stake time)
+        + 8                        // eligible_from (distributions before it are skipped; 0 = window settled or none)
+        + 8; // ve_unlock_timestamp (weight moved to a ve lock until then; 0 = not ve-locked)
+}
+
+/// Referral earnings of one referrer, paid from the referral budget vault.
+#[account]
+pub struct ReferralAccount {
+    pub referrer: Pubkey,
+    pub claimable: u64,
+    pub lifetime_earned: u64,
+    pub bump: u8,
+}
+
+impl ReferralAccount {
+    pub const MAX_SIZE: usize = 8  // Discriminator
+        + 32                       // referrer
+        + 8                        // claimable
+        + 8                        // lifetime_earned
+        + 1; // bump
+}
+
+/// Fungible HAiO stake of one wallet. Weight (`staked_amount`) is `deposited_amount`
+/// divided by the config's `token_stake_unit`, and counts toward the same engine total as NFTs.
+#[account]
+pub struct TokenStakeState {
+    pub user_wallet: Pubkey,
+    pub engine_state_ref: Pubkey,
+    pub deposited_amount: u64,
+    pub staked_amount: u64,
+    pub reward_debt: u128,
+    pub last_staked_timestamp: i64,
+    pub reconcile_epoch: u64,
+    pub bump: u8,
+    pub unpaid_rewards: u64,
+    pub eligible_from: i64,
+}
+
+impl TokenStakeState {
+    pub const MAX_SIZE: usize = 8  // Discriminator
+        + 32                       // user_wallet
+        + 32                       // engine_state_ref
+        + 8                        // deposited_amount (HAiO in the vault)
+        + 8                        // staked_amount (weight)
+        + 16                       // reward_debt (u128)
+        + 8                        // last_staked_timestamp
+        + 8                        // reconcile_epoch
+        + 1                        // bump
+        + 8                        // unpaid_rewards (IOU left when the reward pool was short)
+        + 8; // eligible_from (distributions before it are skipped; 0 = window settled or none)
+}
+
+/// Vote-escrow lock of one wallet's HAiO, or of one staked NFT position whose weight moved
+/// out of regular staking. Its reward weight and voting power decay linearly from
+/// `weight * remaining / MAX_VE_LOCK_SECS` to zero at `unlock_timestamp`. Once settled past
+/// its unlock, the paid rates are the unlock week's and the lock earns nothing further.
+#[account]
+pub struct VeLock {
+    pub user_wallet: Pubkey,
+    pub engine_state_ref: Pubkey,
+    pub deposited_amount: u64,
+    pub weight: u64,
+    pub locked_at: i64,
+    pub unlock_timestamp: i64,
+    pub reward_per_weight_paid: u128,
+    pub reward_time_paid: u128,
+    pub unpaid_rewards: u64,
+    pub bump: u8,
+    pub position: Pubkey,
+    pub unlock_settled: bool,
+}
+
+impl VeLock {
+    pub const MAX_SIZE: usize = 8  // Discriminator
+        + 32                       // user_wallet
+        + 32                       // engine_state_ref
+        + 8                        // deposited_amount (HAiO in the vault)
+        + 8                        // weight (full-length weight before decay)
+        + 8                        // locked_at
+        + 8                        // unlock_timestamp (week boundary)
+        + 16                       // reward_per_weight_paid (u128, engine ve rate at last claim)
+        + 16                       // reward_time_paid (u128, engine ve time-weighted rate at last claim)
+        + 8                        // unpaid_rewards (IOU left when the reward pool was short)
+        + 1                        // bump
+        + 32                       // position (locked NftStakeState; default = HAiO lock)
+        + 1; // unlock_settled (paid rates hold the unlock week's snapshot)
+
+    /// Decayed weight at `timestamp`.
+    pub fn weight_at(&self, timestamp: i64) -> u64 {
+        if timestamp >= self.unlock_timestamp {
+            return 0;
+        }
+        let remaining = (self.unlock_timestamp - timestamp) as u128;
+        (self.weight as u128 * remaining / MAX_VE_LOCK_SECS as u128) as u64
+    }
+}
+
+/// Claimed rewards vesting linearly for one wallet. `locked_amount` vests from
+/// `start_timestamp` to `end_timestamp`; `released_amount` of it has already moved to
+/// `unlocked_amount`, which can be withdrawn without penalty. A new claim restarts the
+/// schedule for the whole unvested balance, ending at the amount-weighted average of the
+/// old end and a full vesting duration from now.
+#[account]
+pub struct VestingEscrow {
+    pub user_wallet: Pubkey,
+    pub locked_amount: u64,
+    pub released_amount: u64,
+    pub unlocked_amount: u64,
+    pub start_timestamp: i64,
+    pub end_timestamp: i64,
+    pub bump: u8,
+}
+
+impl VestingEscrow {
+    pub const MAX_SIZE: usize = 8  // Discriminator
+        + 32                       // user_wallet
+        + 8                        // locked_amount
+        + 8                        // released_amount
+        + 8                        // unlocked_amount
+        + 8                        // start_timestamp
+        + 8                        // end_timestamp
+        + 1; // bump
+}
+
+/// Reward IOU carried over from positions that were unstaked while the reward pool was short.
+#[account]
+pub struct LeftoverClaim {
+    pub user_wallet: Pubkey,
+    pub amount: u64,
+    pub bump: u8,
+    /// Engine that counts the claim in its `total_reward_iou` and whose pool pays it.
+    pub engine_state_ref: Pubkey,
+}
+
+impl LeftoverClaim {
+    pub const MAX_SIZE: usize = 8  // Discriminator
+        + 32                       // user_wallet
+        + 8                        // amount
+        + 1                        // bump
+        + 32; // engine_state_ref
+}
+
+#[account]
+pub struct ReconcileState {
+    pub engine_state_ref: Pubkey,
+    pub epoch: u64,
+    pub in_progress: bool,
+    pub started_at: i64,
+    pub start_update_count: u64,
+    pub counted_total: u64,
+    pub counted_reward_debt: u128,
+    pub positions_counted: u64,
+    pub last_finalized_at: i64,
+    pub last_counted_total: u64,
+    pub last_counted_reward_debt: u128,
+    pub last_positions_counted: u64,
+    pub last_discrepancy: i64,
+    pub last_update_count: u64,
+    pub bump: u8,
+}
+
+impl ReconcileState {
+    pub const MAX_SIZE: usize = 8  // Discriminator
+        + 32                       // engine_state_ref
+        + 8                        // epoch
+        + 1                        // in_progress (bool)
+        + 8                        // started_at
+        + 8                        // start_update_count
+        + 8                        // counted_total
+        + 16                       // counted_reward_debt (u128)
+        + 8                        // positions_counted
+        + 8                        // last_finalized_at
+        + 8                        // last_counted_total
+        + 16                       // last_counted_reward_debt (u128)
+        + 8                        // last_positions_counted
+        + 8                        // last_discrepancy (engine total - counted total)
+        + 8                        // last_update_count
+        + 1; // bump
+}
+
+#[account]
+pub struct StakingConfig {
+    pub authority: Pubkey,
+    pub curator: Pubkey,
+    pub engine_state_ref: Pubkey,
+    pub bump: u8,
+    pub lock_tier_count: u8,
+    pub lock_tiers: [LockTier; MAX_LOCK_TIERS],
+    pub unbonding_cooldown_secs: i64,
+    pub core_collection: Pubkey,
+    pub token_stake_unit: u64,
+    pub vesting_duration_secs: i64,
+    pub early_exit_penalty_bps: u16,
+    pub referral_bps: u16,
+    pub referral_liabilities: u64,
+    pub cnft_collection: Pubkey,
+}
+
+impl StakingConfig {
+    pub const MAX_SIZE: usize = 8  // Discriminator
+        + 32                       // authority
+        + 32                       // curator
+        + 32                       // engine_state_ref
+        + 1                        // bump
+        + 1                        // lock_tier_count
+        + LockTier::SIZE * MAX_LOCK_TIERS // lock_tiers
+        + 8                        // unbonding_cooldown_secs
+        + 32                       // core_collection (default = Core staking disabled)
+        + 8                        // token_stake_unit (HAiO base units per weight, 0 = fungible staking disabled)
+        + 8                        // vesting_duration_secs (0 = claim_rewards pays out directly)
+        + 2                        // early_exit_penalty_bps
+        + 2                        // referral_bps (of each claim, paid from the referral budget)
+        + 8                        // referral_liabilities (credited to referrers, not yet withdrawn)
+        + 32; // cnft_collection (default = compressed NFT staking disabled)
+}
+
+#[account]
+pub struct NftWeight {
+    pub nft_mint: Pubkey,
+    pub weight: u64,
+    pub bump: u8,
+}
+
+impl NftWeight {
+    pub const MAX_SIZE: usize = 8  // Discriminator
+        + 32                       // nft_mint
+        + 8                        // weight
+        + 1; // bump
+}
+
+/// Per-wallet totals of the positions a wallet holds directly; tokenized positions leave
+/// it when their receipt is minted.
+#[account]
+pub struct UserStakeSummary {
+    pub user_wallet: Pubkey,
+    pub staked_count: u32,
+    pub total_weight: u64,
+    pub lifetime_claimed: u64,
+    pub first_stake_timestamp: i64,
+    pub bump: u8,
+}
+
+impl UserStakeSummary {
+    pub const MAX_SIZE: usize = 8  // Discriminator
+        + 32                       // user_wallet
+        + 4                        // staked_count
+        + 8                        // total_weight
+        + 8                        // lifetime_claimed
+        + 8                        // first_stake_timestamp
+        + 1; // bump
 }